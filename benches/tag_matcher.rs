@@ -0,0 +1,54 @@
+//! Compares matching the ingested tags against the registered patterns one by
+//! one with matching them against the compiled `TagMatcher` cached along the
+//! registration.
+
+use {
+    criterion::{black_box, criterion_group, criterion_main, BenchmarkId, Criterion},
+    gilgamesh::tags::{match_tag, TagMatcher},
+};
+
+/// A registration with exact tags, digit wildcards, ranges and prefixes.
+fn registration_tags(size: u32) -> Vec<String> {
+    (0..size)
+        .map(|i| match i % 4 {
+            0 => format!("{}", 1000 + i),
+            1 => format!("{}**", 20 + i % 80),
+            2 => format!("{}-{}", 3000 + i * 10, 3009 + i * 10),
+            _ => format!("{}%", 5 + i % 5),
+        })
+        .collect()
+}
+
+/// The tags of the ingested messages, mostly not matching.
+const TAGS: [u32; 8] = [1000, 1001, 2042, 3015, 4000, 4001, 5123, 9999];
+
+fn bench_tag_matcher(c: &mut Criterion) {
+    let mut group = c.benchmark_group("match_registration");
+    for size in [4, 32, 256] {
+        let tags = registration_tags(size);
+
+        group.bench_with_input(BenchmarkId::new("patterns", size), &tags, |b, tags| {
+            b.iter(|| {
+                TAGS.iter()
+                    .filter(|&&tag| {
+                        tags.iter()
+                            .any(|pattern| match_tag(black_box(tag), pattern))
+                    })
+                    .count()
+            })
+        });
+
+        let matcher = TagMatcher::new(tags.iter().map(String::as_str));
+        group.bench_with_input(BenchmarkId::new("matcher", size), &matcher, |b, matcher| {
+            b.iter(|| {
+                TAGS.iter()
+                    .filter(|&&tag| matcher.matches(black_box(tag)))
+                    .count()
+            })
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, bench_tag_matcher);
+criterion_main!(benches);
@@ -0,0 +1,26 @@
+CREATE TABLE messages (
+    id BIGSERIAL PRIMARY KEY,
+    ts TIMESTAMPTZ NOT NULL,
+    method TEXT NOT NULL,
+    client_id TEXT NOT NULL,
+    topic TEXT NOT NULL,
+    message_id TEXT NOT NULL,
+    message TEXT NOT NULL
+);
+
+-- Mirrors the indexes of the `Messages` MongoDB collection
+CREATE INDEX messages_ts_asc ON messages (ts ASC);
+CREATE INDEX messages_ts_desc ON messages (ts DESC);
+CREATE INDEX messages_topic ON messages (topic);
+CREATE UNIQUE INDEX messages_client_id_topic_message_id ON messages (client_id, topic, message_id);
+
+CREATE TABLE registrations (
+    id BIGSERIAL PRIMARY KEY,
+    client_id TEXT NOT NULL,
+    tags TEXT[] NOT NULL,
+    topics TEXT[] NOT NULL DEFAULT '{}',
+    relay_url TEXT NOT NULL
+);
+
+-- Mirrors the indexes of the `Registrations` MongoDB collection
+CREATE UNIQUE INDEX registrations_client_id ON registrations (client_id);
@@ -0,0 +1,4 @@
+-- Kept forever when NULL
+ALTER TABLE messages ADD COLUMN expires_at TIMESTAMPTZ;
+
+CREATE INDEX messages_expires_at ON messages (expires_at);
@@ -0,0 +1,2 @@
+-- Serves the pages of a topic, ordered by timestamp then by ID
+CREATE INDEX messages_topic_ts_id ON messages (topic, ts, id);
@@ -0,0 +1,4 @@
+-- The Relay tag of the message, NULL for the messages archived before
+ALTER TABLE messages ADD COLUMN tag BIGINT;
+
+CREATE INDEX messages_topic_tag_ts_id ON messages (topic, tag, ts, id);
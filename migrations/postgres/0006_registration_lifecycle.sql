@@ -0,0 +1,7 @@
+-- NULL for the registrations stored before they were kept
+ALTER TABLE registrations ADD COLUMN created_at TIMESTAMPTZ;
+ALTER TABLE registrations ADD COLUMN updated_at TIMESTAMPTZ;
+-- Kept forever when NULL
+ALTER TABLE registrations ADD COLUMN expires_at TIMESTAMPTZ;
+
+CREATE INDEX registrations_expires_at ON registrations (expires_at);
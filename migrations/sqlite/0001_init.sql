@@ -0,0 +1,23 @@
+CREATE TABLE messages (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    ts INTEGER NOT NULL,
+    method TEXT NOT NULL,
+    client_id TEXT NOT NULL,
+    topic TEXT NOT NULL,
+    message_id TEXT NOT NULL,
+    message TEXT NOT NULL
+);
+
+CREATE INDEX messages_ts ON messages (ts);
+CREATE INDEX messages_topic_ts ON messages (topic, ts);
+CREATE UNIQUE INDEX messages_client_id_topic_message_id ON messages (client_id, topic, message_id);
+
+CREATE TABLE registrations (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    client_id TEXT NOT NULL,
+    tags TEXT NOT NULL,
+    topics TEXT NOT NULL DEFAULT '[]',
+    relay_url TEXT NOT NULL
+);
+
+CREATE UNIQUE INDEX registrations_client_id ON registrations (client_id);
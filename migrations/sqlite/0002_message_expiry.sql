@@ -0,0 +1,4 @@
+-- The number of milliseconds since Epoch, kept forever when NULL
+ALTER TABLE messages ADD COLUMN expires_at INTEGER;
+
+CREATE INDEX messages_expires_at ON messages (expires_at);
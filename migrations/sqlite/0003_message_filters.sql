@@ -0,0 +1,3 @@
+-- Serve the pages of a topic filtered by client or method
+CREATE INDEX messages_topic_client_id_ts ON messages (topic, client_id, ts);
+CREATE INDEX messages_topic_method_ts ON messages (topic, method, ts);
@@ -0,0 +1,4 @@
+-- The Relay tag of the message, NULL for the messages archived before
+ALTER TABLE messages ADD COLUMN tag INTEGER;
+
+CREATE INDEX messages_topic_tag_ts ON messages (topic, tag, ts);
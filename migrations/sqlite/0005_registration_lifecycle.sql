@@ -0,0 +1,8 @@
+-- The number of milliseconds since Epoch, NULL for the registrations stored
+-- before they were kept
+ALTER TABLE registrations ADD COLUMN created_at INTEGER;
+ALTER TABLE registrations ADD COLUMN updated_at INTEGER;
+-- Kept forever when NULL
+ALTER TABLE registrations ADD COLUMN expires_at INTEGER;
+
+CREATE INDEX registrations_expires_at ON registrations (expires_at);
@@ -0,0 +1,8 @@
+-- The signatures of the accepted Relay requests, kept to reject their replays
+CREATE TABLE relay_signatures (
+    signature TEXT PRIMARY KEY,
+    -- The number of milliseconds since Epoch
+    expires_at INTEGER NOT NULL
+);
+
+CREATE INDEX relay_signatures_expires_at ON relay_signatures (expires_at);
@@ -0,0 +1,92 @@
+use {
+    crate::{
+        config::Configuration,
+        error::{self, Error},
+    },
+    relay_rpc::{
+        domain::ClientId,
+        jwt::{JwtBasicClaims, VerifyableClaims},
+    },
+    std::collections::HashSet,
+};
+
+/// The client JWTs accepted by the server.
+#[derive(Debug, Clone)]
+pub struct JwtPolicy {
+    audiences: HashSet<String>,
+    /// The longest accepted `exp - iat`, in seconds.
+    max_lifetime: Option<u64>,
+    /// The seconds `iat` and `exp` may be off by.
+    clock_skew: i64,
+}
+
+impl JwtPolicy {
+    /// Accepts the `AUTH_AUDIENCES` and the server's `PUBLIC_URL`.
+    pub fn from_config(config: &Configuration) -> Self {
+        JwtPolicy {
+            audiences: config
+                .auth_audiences
+                .iter()
+                .chain(std::iter::once(&config.public_url))
+                .cloned()
+                .collect(),
+            max_lifetime: config.auth_max_token_lifetime,
+            clock_skew: config.auth_clock_skew.try_into().unwrap_or(i64::MAX),
+        }
+    }
+
+    /// Verifies the client JWT, returning the authenticated client.
+    pub fn verify(&self, token: &str) -> error::Result<ClientId> {
+        let claims = JwtBasicClaims::try_from_str(token)?;
+        claims.verify_basic(&self.audiences, self.clock_skew)?;
+
+        if !self.lifetime_is_valid(claims.iat, claims.exp) {
+            return Err(Error::JwtLifetimeTooLong(self.max_lifetime.unwrap_or(0)));
+        }
+
+        Ok(ClientId::from(claims.iss))
+    }
+
+    /// Checks the token lifetime, tokens without expiry are rejected when
+    /// the lifetime is limited.
+    fn lifetime_is_valid(&self, iat: i64, exp: Option<i64>) -> bool {
+        match (self.max_lifetime, exp) {
+            (None, _) => true,
+            (Some(max_lifetime), Some(exp)) => exp
+                .checked_sub(iat)
+                .and_then(|lifetime| u64::try_from(lifetime).ok())
+                .is_some_and(|lifetime| lifetime <= max_lifetime),
+            (Some(_), None) => false,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test_jwt_policy {
+    use super::*;
+
+    fn policy(max_lifetime: Option<u64>) -> JwtPolicy {
+        JwtPolicy {
+            audiences: HashSet::new(),
+            max_lifetime,
+            clock_skew: 0,
+        }
+    }
+
+    #[test]
+    fn test_unlimited_lifetime() {
+        assert!(policy(None).lifetime_is_valid(1000, None));
+        assert!(policy(None).lifetime_is_valid(1000, Some(1000 + 365 * 24 * 3600)));
+    }
+
+    #[test]
+    fn test_max_lifetime() {
+        let policy = policy(Some(3600));
+
+        assert!(policy.lifetime_is_valid(1000, Some(1000)));
+        assert!(policy.lifetime_is_valid(1000, Some(4600)));
+        assert!(!policy.lifetime_is_valid(1000, Some(4601)));
+        assert!(!policy.lifetime_is_valid(1000, Some(999)));
+        assert!(!policy.lifetime_is_valid(1000, None));
+    }
+}
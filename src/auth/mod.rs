@@ -1,9 +1,14 @@
+pub mod jwt;
+
 use {
+    crate::{error::Error, state::AppState},
     async_trait::async_trait,
     axum::{
         extract::FromRequestParts,
-        http::{header::AUTHORIZATION, request::Parts, StatusCode},
+        http::{header::AUTHORIZATION, request::Parts},
     },
+    relay_rpc::domain::ClientId,
+    std::sync::Arc,
 };
 
 const ERR_MISSING: &str = "`Authorization` header is missing";
@@ -11,7 +16,7 @@ const ERR_CHARS: &str = "`Authorization` header contains invalid characters";
 const ERR_WRONG_BEARER: &str = "`Authorization` header must be a bearer token";
 
 /// Rejection error used in the [AuthBearer] extractors.
-pub type Rejection = (StatusCode, &'static str);
+pub type Rejection = Error;
 
 /// Bearer token extractor which contains the innards of a bearer header as a
 /// string.
@@ -31,8 +36,8 @@ pub type Rejection = (StatusCode, &'static str);
 ///
 /// # Errors
 ///
-/// There are a few errors which this extractor can make. By default, all
-/// invalid responses are `400 BAD REQUEST` with one of these messages:
+/// There are a few errors which this extractor can make. All invalid responses
+/// are `401 UNAUTHORIZED` with one of these messages:
 ///
 /// - \`Authorization\` header must be a bearer token – Somebody tried to but
 ///   basic auth here instead of bearer
@@ -56,6 +61,15 @@ where
 }
 
 impl AuthBearer {
+    /// Returns the bearer token of the request, if it carries one.
+    pub fn token<B>(request: &axum::http::Request<B>) -> Option<&str> {
+        request
+            .headers()
+            .get(AUTHORIZATION)
+            .and_then(|header| header.to_str().ok())
+            .and_then(|header| header.strip_prefix("Bearer "))
+    }
+
     fn from_header(contents: &str) -> Self {
         Self(contents.to_string())
     }
@@ -65,9 +79,9 @@ impl AuthBearer {
         let authorization = req
             .headers
             .get(AUTHORIZATION)
-            .ok_or((StatusCode::BAD_REQUEST, ERR_MISSING))?
+            .ok_or(Error::InvalidAuthorizationHeader(ERR_MISSING))?
             .to_str()
-            .map_err(|_| (StatusCode::BAD_REQUEST, ERR_CHARS))?;
+            .map_err(|_| Error::InvalidAuthorizationHeader(ERR_CHARS))?;
 
         // Check that its a well-formed bearer and return
         let split = authorization.split_once(' ');
@@ -77,7 +91,32 @@ impl AuthBearer {
             // Found empty bearer
             _ if authorization == "Bearer" => Ok(Self::from_header("")),
             // Found nothing
-            _ => Err((StatusCode::BAD_REQUEST, ERR_WRONG_BEARER)),
+            _ => Err(Error::InvalidAuthorizationHeader(ERR_WRONG_BEARER)),
+        }
+    }
+}
+
+/// The client authenticated by the request's bearer JWT, rejected like
+/// [AuthBearer] and the invalid JWTs with a `401 UNAUTHORIZED`.
+///
+/// The JWT is verified once per request: the client verified by the rate
+/// limiter is kept in the request's extensions.
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct AuthClient(pub ClientId);
+
+#[async_trait]
+impl FromRequestParts<Arc<AppState>> for AuthClient {
+    type Rejection = Error;
+
+    async fn from_request_parts(
+        req: &mut Parts,
+        state: &Arc<AppState>,
+    ) -> Result<Self, Self::Rejection> {
+        if let Some(client) = req.extensions.get::<AuthClient>() {
+            return Ok(client.clone());
         }
+
+        let AuthBearer(token) = AuthBearer::decode_request_parts(req)?;
+        Ok(Self(state.jwt_policy.verify(&token)?))
     }
 }
@@ -0,0 +1,127 @@
+use {
+    crate::{
+        config::Configuration,
+        error::{self, Error},
+        store::messages::MessageCursor,
+    },
+    data_encoding::BASE64URL_NOPAD,
+    hmac::{Hmac, Mac},
+    sha2::Sha256,
+};
+
+type HmacSha256 = Hmac<Sha256>;
+
+/// Turns the store cursors into the opaque strings handed to the clients,
+/// signed so that altered or forged cursors are rejected.
+#[derive(Clone)]
+pub struct CursorCodec {
+    mac: HmacSha256,
+}
+
+impl CursorCodec {
+    pub fn new(key: &[u8]) -> Self {
+        CursorCodec {
+            mac: HmacSha256::new_from_slice(key).expect("HMAC accepts keys of any size"),
+        }
+    }
+
+    /// Signs the cursors with `CURSOR_SECRET`, which every instance must share.
+    pub fn from_config(config: &Configuration) -> error::Result<Self> {
+        match config.cursor_secret.as_deref() {
+            Some(secret) if !secret.is_empty() => Ok(Self::new(secret.as_bytes())),
+            _ => Err(Error::InvalidConfiguration(
+                "`CURSOR_SECRET` must be set, every instance signs the cursors with it".to_string(),
+            )),
+        }
+    }
+
+    pub fn encode(&self, cursor: &MessageCursor) -> String {
+        let payload = serde_json::to_vec(cursor).unwrap_or_default();
+        let signature = self.sign(&payload).finalize().into_bytes();
+
+        format!(
+            "{}.{}",
+            BASE64URL_NOPAD.encode(&payload),
+            BASE64URL_NOPAD.encode(&signature)
+        )
+    }
+
+    pub fn decode(&self, cursor: &str) -> error::Result<MessageCursor> {
+        let (payload, signature) = cursor.split_once('.').ok_or(Error::InvalidCursor)?;
+        let payload = BASE64URL_NOPAD
+            .decode(payload.as_bytes())
+            .map_err(|_| Error::InvalidCursor)?;
+        let signature = BASE64URL_NOPAD
+            .decode(signature.as_bytes())
+            .map_err(|_| Error::InvalidCursor)?;
+
+        self.sign(&payload)
+            .verify_slice(&signature)
+            .map_err(|_| Error::InvalidCursor)?;
+
+        serde_json::from_slice(&payload).map_err(|_| Error::InvalidCursor)
+    }
+
+    fn sign(&self, payload: &[u8]) -> HmacSha256 {
+        let mut mac = self.mac.clone();
+        mac.update(payload);
+        mac
+    }
+}
+
+#[cfg(test)]
+mod test_cursor_codec {
+    use {
+        super::CursorCodec,
+        crate::{error::Error, store::messages::MessageCursor},
+        chrono::{TimeZone, Utc},
+        std::sync::Arc,
+    };
+
+    fn cursor() -> MessageCursor {
+        MessageCursor {
+            ts: Utc.timestamp_opt(1_700_000_000, 123_456_000).unwrap(),
+            id: Arc::from("6553f1000000000000000042"),
+        }
+    }
+
+    #[test]
+    fn round_trip() {
+        let codec = CursorCodec::new(b"secret");
+        let encoded = codec.encode(&cursor());
+        assert_eq!(codec.decode(&encoded).unwrap(), cursor());
+    }
+
+    #[test]
+    fn rejects_tampered_cursor() {
+        let codec = CursorCodec::new(b"secret");
+        let encoded = codec.encode(&cursor());
+        let (_, signature) = encoded.split_once('.').unwrap();
+
+        let mut forged = cursor();
+        forged.id = Arc::from("6553f1000000000000000043");
+        let payload = data_encoding::BASE64URL_NOPAD.encode(&serde_json::to_vec(&forged).unwrap());
+
+        assert!(matches!(
+            codec.decode(&format!("{payload}.{signature}")),
+            Err(Error::InvalidCursor)
+        ));
+    }
+
+    #[test]
+    fn rejects_other_key() {
+        let encoded = CursorCodec::new(b"secret").encode(&cursor());
+        assert!(matches!(
+            CursorCodec::new(b"other").decode(&encoded),
+            Err(Error::InvalidCursor)
+        ));
+    }
+
+    #[test]
+    fn rejects_malformed_cursor() {
+        let codec = CursorCodec::new(b"secret");
+        for cursor in ["", "1", "not.base64!", "e30.e30"] {
+            assert!(matches!(codec.decode(cursor), Err(Error::InvalidCursor)));
+        }
+    }
+}
@@ -1,11 +1,19 @@
 use {
     crate::{
-        handlers::{ErrorField, ErrorLocation, ResponseError},
+        handlers::{save_message::PayloadError, ErrorField, ErrorLocation, ResponseError},
         relay::signature::{SIGNATURE_HEADER_NAME, TIMESTAMP_HEADER_NAME},
         store::StoreError,
+        tags::TagPatternError,
+    },
+    axum::{
+        http::{
+            header::{AUTHORIZATION, RETRY_AFTER},
+            HeaderValue,
+        },
+        response::{IntoResponse, Response},
     },
-    axum::response::{IntoResponse, Response},
     hyper::StatusCode,
+    std::time::Duration,
 };
 
 pub type Result<T> = std::result::Result<T, Error>;
@@ -66,18 +74,45 @@ pub enum Error {
     #[error("middleware failed to parse body")]
     ToBytesError,
 
+    #[error("the body is larger than the maximum of {0} bytes")]
+    PayloadTooLarge(usize),
+
     #[error("neither signature or timestamp header cannot not found")]
     MissingAllSignatureHeader,
 
+    #[error("the signature does not match the Relay's public key")]
+    InvalidSignature,
+
+    #[error("the signature timestamp is outside of the accepted window")]
+    StaleSignature,
+
+    #[error("the signed request has already been received")]
+    ReplayedSignature,
+
     #[error("invalid configuration: {0}")]
     InvalidConfiguration(String),
 
     #[error("invalid options provided for {0}")]
     InvalidOptionsProvided(String),
 
+    #[error("invalid tag patterns")]
+    InvalidTagPatterns(Vec<(String, TagPatternError)>),
+
+    #[error("invalid message payload")]
+    InvalidPayload(Vec<(String, PayloadError)>),
+
     #[error("invalid update request")]
     InvalidUpdateRequest,
 
+    #[error("`{0}` is not a valid relay URL")]
+    InvalidRelayUrl(String),
+
+    #[error("the relay `{0}` is not allowed")]
+    RelayNotAllowed(String),
+
+    #[error("the messages belong to clients registered with another relay")]
+    RelayMismatch,
+
     #[error(transparent)]
     FromUtf8Error(#[from] std::string::FromUtf8Error),
 
@@ -95,11 +130,71 @@ pub enum Error {
 
     #[error("the provided authentication does not authenticate the request")]
     InvalidAuthentication,
+
+    #[error("{0}")]
+    InvalidAuthorizationHeader(&'static str),
+
+    #[error("too many requests, retry in {} seconds", retry_after_secs(.0))]
+    RateLimited(Duration),
+
+    #[error("the message quota is exhausted{}", retry_in(.0))]
+    QuotaExceeded(Option<Duration>),
+
+    #[error("the client JWT must expire at most {0} seconds after it's issued")]
+    JwtLifetimeTooLong(u64),
+
+    #[error("the client is not authorized to access topic `{0}`")]
+    UnauthorizedTopic(String),
+
+    #[error("a `messageId` can only be deleted along with its `topic`")]
+    MessageIdWithoutTopic,
+
+    #[error("the batch contains {0} messages, more than the allowed maximum")]
+    BatchTooLarge(usize),
+
+    #[error("the cursor is malformed or has been tampered with")]
+    InvalidCursor,
+
+    #[error("the request contains {0} topics, more than the allowed maximum")]
+    TooManyTopics(usize),
+
+    #[error("the topic `{0}` is requested more than once")]
+    DuplicateTopic(String),
+
+    #[error("`{0}` is neither a tag nor a range of tags")]
+    InvalidTagFilter(String),
+
+    #[error("`since` must be before `until`")]
+    InvalidTimeRange,
+}
+
+/// Rounds the delay up to the whole seconds of the `Retry-After` header.
+fn retry_after_secs(retry_after: &Duration) -> u64 {
+    let secs = retry_after.as_secs();
+    if retry_after.subsec_nanos() > 0 || secs == 0 {
+        secs + 1
+    } else {
+        secs
+    }
+}
+
+fn retry_in(retry_after: &Option<Duration>) -> String {
+    retry_after
+        .as_ref()
+        .map(|retry_after| format!(", retry in {} seconds", retry_after_secs(retry_after)))
+        .unwrap_or_default()
 }
 
 impl IntoResponse for Error {
     fn into_response(self) -> Response {
-        match self {
+        let retry_after = match &self {
+            Error::RateLimited(retry_after) | Error::QuotaExceeded(Some(retry_after)) => {
+                Some(retry_after_secs(retry_after))
+            }
+            _ => None,
+        };
+
+        let mut response = match self {
             Error::Database(e) => crate::handlers::Response::new_failure(StatusCode::INTERNAL_SERVER_ERROR, vec![
                 ResponseError {
                     name: "mongodb".to_string(),
@@ -133,6 +228,30 @@ impl IntoResponse for Error {
                     }],
                     vec![],
                 ),
+                StoreError::Sql(e) => crate::handlers::Response::new_failure(
+                    StatusCode::INTERNAL_SERVER_ERROR,
+                    vec![ResponseError {
+                        name: "sql".to_string(),
+                        message: e.to_string(),
+                    }],
+                    vec![],
+                ),
+                e @ StoreError::WriteFailed(_) => crate::handlers::Response::new_failure(
+                    StatusCode::INTERNAL_SERVER_ERROR,
+                    vec![ResponseError {
+                        name: "write_failed".to_string(),
+                        message: e.to_string(),
+                    }],
+                    vec![],
+                ),
+                e @ StoreError::InvalidCursor(_) => crate::handlers::Response::new_failure(
+                    StatusCode::BAD_REQUEST,
+                    vec![ResponseError {
+                        name: "invalid_cursor".to_string(),
+                        message: e.to_string(),
+                    }],
+                    vec![],
+                ),
                 StoreError::NotFound(entity, id) => crate::handlers::Response::new_failure(
                     StatusCode::NOT_FOUND,
                     vec![],
@@ -160,6 +279,42 @@ impl IntoResponse for Error {
                     location: ErrorLocation::Header
                 }
             ]),
+            Error::InvalidSignature => crate::handlers::Response::new_failure(StatusCode::UNAUTHORIZED, vec![
+                ResponseError {
+                    name: "history_item_validation_failed".to_string(),
+                    message: "Failed to validate history item, the signature is invalid.".to_string(),
+                }
+            ], vec![
+                ErrorField {
+                    field: SIGNATURE_HEADER_NAME.to_string(),
+                    description: "Invalid signature".to_string(),
+                    location: ErrorLocation::Header
+                }
+            ]),
+            Error::StaleSignature => crate::handlers::Response::new_failure(StatusCode::UNAUTHORIZED, vec![
+                ResponseError {
+                    name: "history_item_validation_failed".to_string(),
+                    message: "Failed to validate history item, the signature has expired.".to_string(),
+                }
+            ], vec![
+                ErrorField {
+                    field: TIMESTAMP_HEADER_NAME.to_string(),
+                    description: "Expired timestamp".to_string(),
+                    location: ErrorLocation::Header
+                }
+            ]),
+            Error::ReplayedSignature => crate::handlers::Response::new_failure(StatusCode::UNAUTHORIZED, vec![
+                ResponseError {
+                    name: "history_item_validation_failed".to_string(),
+                    message: "Failed to validate history item, the request has already been received.".to_string(),
+                }
+            ], vec![
+                ErrorField {
+                    field: SIGNATURE_HEADER_NAME.to_string(),
+                    description: "Replayed signature".to_string(),
+                    location: ErrorLocation::Header
+                }
+            ]),
             Error::MissingSignatureHeader => crate::handlers::Response::new_failure(StatusCode::UNAUTHORIZED, vec![
                 ResponseError {
                     name: "history_item_validation_failed".to_string(),
@@ -192,6 +347,172 @@ impl IntoResponse for Error {
                 }],
                 vec![],
             ),
+            e @ (Error::JwtError(_)
+                | Error::AuthError(_)
+                | Error::InvalidAuthentication
+                | Error::InvalidAuthorizationHeader(_)
+                | Error::JwtLifetimeTooLong(_)) => crate::handlers::Response::new_failure(
+                StatusCode::UNAUTHORIZED,
+                vec![ResponseError {
+                    name: "authentication_failed".to_string(),
+                    message: e.to_string(),
+                }],
+                vec![ErrorField {
+                    field: AUTHORIZATION.to_string(),
+                    description: "Invalid or expired client JWT".to_string(),
+                    location: ErrorLocation::Header,
+                }],
+            ),
+            Error::UnauthorizedTopic(topic) => crate::handlers::Response::new_failure(
+                StatusCode::FORBIDDEN,
+                vec![ResponseError {
+                    name: "unauthorized_topic".to_string(),
+                    message: format!("The client has neither stored messages on, nor subscribed to, topic {topic}"),
+                }],
+                vec![ErrorField {
+                    field: "topic".to_string(),
+                    description: "Topic not accessible to the client".to_string(),
+                    location: ErrorLocation::Query,
+                }],
+            ),
+            e @ Error::MessageIdWithoutTopic => crate::handlers::Response::new_failure(
+                StatusCode::BAD_REQUEST,
+                vec![ResponseError {
+                    name: "topic".to_string(),
+                    message: e.to_string(),
+                }],
+                vec![ErrorField {
+                    field: "topic".to_string(),
+                    description: "Missing topic".to_string(),
+                    location: ErrorLocation::Query,
+                }],
+            ),
+            e @ Error::BatchTooLarge(_) => crate::handlers::Response::new_failure(
+                StatusCode::PAYLOAD_TOO_LARGE,
+                vec![ResponseError {
+                    name: "batch_too_large".to_string(),
+                    message: e.to_string(),
+                }],
+                vec![],
+            ),
+            e @ Error::InvalidCursor => crate::handlers::Response::new_failure(
+                StatusCode::BAD_REQUEST,
+                vec![ResponseError {
+                    name: "invalid_cursor".to_string(),
+                    message: e.to_string(),
+                }],
+                vec![],
+            ),
+            e @ (Error::TooManyTopics(_) | Error::DuplicateTopic(_)) => crate::handlers::Response::new_failure(
+                StatusCode::BAD_REQUEST,
+                vec![ResponseError {
+                    name: "topics".to_string(),
+                    message: e.to_string(),
+                }],
+                vec![ErrorField {
+                    field: "topics".to_string(),
+                    description: "Invalid list of topics".to_string(),
+                    location: ErrorLocation::Body,
+                }],
+            ),
+            e @ Error::InvalidTagFilter(_) => crate::handlers::Response::new_failure(
+                StatusCode::BAD_REQUEST,
+                vec![ResponseError {
+                    name: "invalid_tag_filter".to_string(),
+                    message: e.to_string(),
+                }],
+                vec![ErrorField {
+                    field: "tag".to_string(),
+                    description: "Expected `<tag>`, `<min>-<max>` or digits followed by `*` wildcards".to_string(),
+                    location: ErrorLocation::Query,
+                }],
+            ),
+            e @ Error::InvalidTimeRange => crate::handlers::Response::new_failure(
+                StatusCode::BAD_REQUEST,
+                vec![ResponseError {
+                    name: "invalid_time_range".to_string(),
+                    message: e.to_string(),
+                }],
+                vec![ErrorField {
+                    field: "since".to_string(),
+                    description: "Must be before until".to_string(),
+                    location: ErrorLocation::Query,
+                }],
+            ),
+            e @ Error::InvalidRelayUrl(_) => crate::handlers::Response::new_failure(
+                StatusCode::BAD_REQUEST,
+                vec![ResponseError {
+                    name: "invalid_relay_url".to_string(),
+                    message: e.to_string(),
+                }],
+                vec![ErrorField {
+                    field: "relayUrl".to_string(),
+                    description: "Must be an http(s) or ws(s) URL".to_string(),
+                    location: ErrorLocation::Body,
+                }],
+            ),
+            e @ Error::RelayNotAllowed(_) => crate::handlers::Response::new_failure(
+                StatusCode::BAD_REQUEST,
+                vec![ResponseError {
+                    name: "relay_not_allowed".to_string(),
+                    message: e.to_string(),
+                }],
+                vec![ErrorField {
+                    field: "relayUrl".to_string(),
+                    description: "Must be one of the relays allowed by the server".to_string(),
+                    location: ErrorLocation::Body,
+                }],
+            ),
+            e @ Error::RelayMismatch => crate::handlers::Response::new_failure(
+                StatusCode::FORBIDDEN,
+                vec![ResponseError {
+                    name: "relay_mismatch".to_string(),
+                    message: e.to_string(),
+                }],
+                vec![ErrorField {
+                    field: "clientId".to_string(),
+                    description: "Must be registered with the relay signing the request".to_string(),
+                    location: ErrorLocation::Body,
+                }],
+            ),
+            Error::InvalidTagPatterns(patterns) => crate::handlers::Response::new_failure(
+                StatusCode::BAD_REQUEST,
+                vec![ResponseError {
+                    name: "tags".to_string(),
+                    message: "one or more tag patterns are malformed".to_string(),
+                }],
+                patterns
+                    .into_iter()
+                    .map(|(field, e)| ErrorField {
+                        field,
+                        description: e.to_string(),
+                        location: ErrorLocation::Body,
+                    })
+                    .collect(),
+            ),
+            Error::InvalidPayload(fields) => crate::handlers::Response::new_failure(
+                StatusCode::BAD_REQUEST,
+                vec![ResponseError {
+                    name: "invalid_payload".to_string(),
+                    message: "one or more message fields are invalid".to_string(),
+                }],
+                fields
+                    .into_iter()
+                    .map(|(field, e)| ErrorField {
+                        field,
+                        description: e.to_string(),
+                        location: ErrorLocation::Body,
+                    })
+                    .collect(),
+            ),
+            e @ Error::PayloadTooLarge(_) => crate::handlers::Response::new_failure(
+                StatusCode::PAYLOAD_TOO_LARGE,
+                vec![ResponseError {
+                    name: "payload_too_large".to_string(),
+                    message: e.to_string(),
+                }],
+                vec![],
+            ),
             Error::InvalidUpdateRequest => crate::handlers::Response::new_failure(
                 StatusCode::BAD_REQUEST,
                 vec![ResponseError {
@@ -200,6 +521,22 @@ impl IntoResponse for Error {
                 }],
                 vec![],
             ),
+            e @ Error::RateLimited(_) => crate::handlers::Response::new_failure(
+                StatusCode::TOO_MANY_REQUESTS,
+                vec![ResponseError {
+                    name: "rate_limited".to_string(),
+                    message: e.to_string(),
+                }],
+                vec![],
+            ),
+            e @ Error::QuotaExceeded(_) => crate::handlers::Response::new_failure(
+                StatusCode::TOO_MANY_REQUESTS,
+                vec![ResponseError {
+                    name: "quota_exceeded".to_string(),
+                    message: e.to_string(),
+                }],
+                vec![],
+            ),
             e => crate::handlers::Response::new_failure(StatusCode::INTERNAL_SERVER_ERROR, vec![
                 ResponseError {
                     name: "unknown_error".to_string(),
@@ -210,6 +547,14 @@ impl IntoResponse for Error {
                     message: format!("{e:?}"),
                 }
             ], vec![])
-        }.into_response()
+        }.into_response();
+
+        if let Some(retry_after) = retry_after {
+            response
+                .headers_mut()
+                .insert(RETRY_AFTER, HeaderValue::from(retry_after));
+        }
+
+        response
     }
 }
@@ -0,0 +1,70 @@
+use {
+    crate::{
+        auth::AuthClient,
+        error::{self, Error},
+        increment_counter_with,
+        log::prelude::*,
+        state::AppState,
+    },
+    axum::{
+        extract::{Query, State},
+        Json,
+    },
+    serde::{Deserialize, Serialize},
+    std::sync::Arc,
+};
+
+/// The query parameters for the delete messages endpoint, the messages to
+/// delete are narrowed down by each provided parameter.
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct DeleteMessagesQuery {
+    pub topic: Option<Arc<str>>,
+    pub message_id: Option<Arc<str>>,
+}
+
+/// The response body for the delete messages endpoint.
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct DeleteMessagesResponse {
+    pub deleted: u64,
+}
+
+/// The handler for the delete messages endpoint, only the messages stored for
+/// the authenticated client are deleted.
+pub async fn handler(
+    State(state): State<Arc<AppState>>,
+    AuthClient(client_id): AuthClient,
+    Query(query): Query<DeleteMessagesQuery>,
+) -> error::Result<Json<DeleteMessagesResponse>> {
+    let deleted = match (&query.topic, &query.message_id) {
+        (Some(topic), Some(message_id)) => {
+            state
+                .messages_store
+                .delete_message(client_id.as_ref(), topic, message_id)
+                .await?
+        }
+        (Some(topic), None) => {
+            state
+                .messages_store
+                .delete_topic_messages(client_id.as_ref(), topic)
+                .await?
+        }
+        (None, None) => {
+            state
+                .messages_store
+                .delete_client_messages(client_id.as_ref())
+                .await?
+        }
+        (None, Some(_)) => return Err(Error::MessageIdWithoutTopic),
+    };
+
+    debug!(
+        "deleted {} messages for client {}",
+        deleted,
+        client_id.as_ref()
+    );
+    increment_counter_with!(state.metrics, deleted_items, deleted);
+
+    Ok(Json(DeleteMessagesResponse { deleted }))
+}
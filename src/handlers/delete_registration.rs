@@ -0,0 +1,39 @@
+use {
+    crate::{
+        auth::AuthClient,
+        error,
+        handlers::Response,
+        increment_counter,
+        state::AppState,
+        store::StoreError,
+    },
+    axum::extract::State,
+    std::sync::Arc,
+};
+
+/// The handler for the unregister endpoint, the client's archived messages
+/// are kept until they expire.
+pub async fn handler(
+    State(state): State<Arc<AppState>>,
+    AuthClient(client_id): AuthClient,
+) -> error::Result<Response> {
+    increment_counter!(state.metrics, unregister);
+
+    let deleted = state
+        .registration_store
+        .delete_registration(client_id.as_ref())
+        .await?;
+
+    increment_counter!(state.metrics, registration_cache_invalidation);
+    state.invalidate_registration(client_id.as_ref()).await;
+
+    if deleted == 0 {
+        return Err(StoreError::NotFound(
+            "registration".to_string(),
+            client_id.into_value().to_string(),
+        )
+        .into());
+    }
+
+    Ok(Response::default())
+}
@@ -1,17 +1,24 @@
 use {
     crate::{
-        error,
+        auth::AuthClient,
+        error::{self, Error},
         increment_counter,
         increment_counter_with,
         state::AppState,
-        store::messages::{Message, StoreMessages},
+        store::{
+            messages::{Message, MessageFilter, StoreMessages},
+            StoreError,
+        },
+        tags::TagPattern,
     },
     axum::{
         extract::{Query, State},
         Json,
     },
+    relay_rpc::domain::ClientId,
     serde::{Deserialize, Serialize},
     std::{cmp, sync::Arc},
+    wither::bson,
 };
 
 /// The absolute max number of messages to return in the response.
@@ -48,10 +55,56 @@ impl MessageCount {
 #[serde(rename_all = "camelCase")]
 pub struct GetMessagesBody {
     pub topic: Arc<str>,
+    /// The `nextId` of the previous page, message IDs aren't accepted.
     pub origin_id: Option<Arc<str>>,
     #[serde(default)]
     pub message_count: MessageCount,
     pub direction: Option<Direction>,
+    /// The oldest timestamp to return, inclusive, in milliseconds since Epoch.
+    pub since: Option<i64>,
+    /// The newest timestamp to return, exclusive, in milliseconds since Epoch.
+    pub until: Option<i64>,
+    /// Only returns the messages of this method (`publish`/`subscription`).
+    pub method: Option<Arc<str>>,
+    /// Only returns the messages stored by this client.
+    pub client_id: Option<Arc<str>>,
+    /// Only returns the messages with this tag, either exact (`1100`), a range
+    /// (`1100-1199`) or digits followed by wildcards (`11**`).
+    pub tag: Option<Arc<str>>,
+}
+
+impl GetMessagesBody {
+    /// Builds the store filter, checking that the time range isn't empty.
+    pub fn filter(&self) -> error::Result<MessageFilter> {
+        if let (Some(since), Some(until)) = (self.since, self.until) {
+            if since >= until {
+                return Err(Error::InvalidTimeRange);
+            }
+        }
+
+        let tags = self
+            .tag
+            .as_deref()
+            .map(|tag| {
+                tag.parse::<TagPattern>()
+                    .ok()
+                    .and_then(|pattern| pattern.as_range())
+                    .ok_or_else(|| Error::InvalidTagFilter(tag.to_string()))
+            })
+            .transpose()?;
+
+        Ok(MessageFilter {
+            since: self
+                .since
+                .map(|ms| bson::DateTime::from_millis(ms).to_chrono()),
+            until: self
+                .until
+                .map(|ms| bson::DateTime::from_millis(ms).to_chrono()),
+            method: self.method.clone(),
+            client_id: self.client_id.clone(),
+            tags,
+        })
+    }
 }
 
 /////////////////////////
@@ -62,6 +115,7 @@ pub struct GetMessagesBody {
 pub struct GetMessagesResponse {
     pub topic: Arc<str>,
     pub direction: Direction,
+    /// The opaque cursor of the next page, to send back as `originId`.
     pub next_id: Option<Arc<str>>,
     pub messages: Vec<Message>,
 }
@@ -71,27 +125,41 @@ pub struct GetMessagesResponse {
 /// The handler for the get messages endpoint.
 pub async fn handler(
     State(state): State<Arc<AppState>>,
+    AuthClient(client_id): AuthClient,
     query: Query<GetMessagesBody>,
 ) -> Result<Json<GetMessagesResponse>, error::Error> {
+    authorize_topic(&state, &client_id, query.topic.as_ref()).await?;
+
+    let filter = query.filter()?;
     let direction = query.direction.unwrap_or(Direction::Forward);
+    let origin = query
+        .origin_id
+        .as_deref()
+        .map(|origin_id| state.cursors.decode(origin_id))
+        .transpose()?;
 
-    let StoreMessages { messages, next_id } = match (&query.origin_id, direction) {
-        (origin_id, Direction::Forward) => {
+    let StoreMessages {
+        messages,
+        next_cursor,
+    } = match direction {
+        Direction::Forward => {
             state
                 .messages_store
                 .get_messages_after(
                     query.topic.as_ref(),
-                    origin_id.as_deref(),
+                    &filter,
+                    origin.as_ref(),
                     query.message_count.limit(),
                 )
                 .await?
         }
-        (origin_id, Direction::Backward) => {
+        Direction::Backward => {
             state
                 .messages_store
                 .get_messages_before(
                     query.topic.as_ref(),
-                    origin_id.as_deref(),
+                    &filter,
+                    origin.as_ref(),
                     query.message_count.limit(),
                 )
                 .await?
@@ -104,9 +172,80 @@ pub async fn handler(
     let response = GetMessagesResponse {
         topic: query.topic.clone(),
         direction,
-        next_id,
+        next_id: next_cursor.map(|cursor| state.cursors.encode(&cursor).into()),
         messages,
     };
 
     Ok(Json(response))
 }
+
+/// Returns the topics the client's relay subscribed it to, none when it isn't
+/// registered.
+async fn subscribed_topics(
+    state: &Arc<AppState>,
+    client_id: &ClientId,
+) -> error::Result<Vec<Arc<str>>> {
+    match state
+        .registration_store
+        .get_registration(client_id.as_ref())
+        .await
+    {
+        Ok(registration) => Ok(registration.topics),
+        Err(StoreError::NotFound(_, _)) => Ok(vec![]),
+        Err(e) => Err(e.into()),
+    }
+}
+
+/// Checks that the client is allowed to read the topic's history, i.e. that
+/// it stored messages on the topic or subscribed to it.
+pub(crate) async fn authorize_topic(
+    state: &Arc<AppState>,
+    client_id: &ClientId,
+    topic: &str,
+) -> error::Result<()> {
+    if state
+        .messages_store
+        .has_messages(client_id.as_ref(), topic)
+        .await?
+        || subscribed_topics(state, client_id)
+            .await?
+            .iter()
+            .any(|subscribed| subscribed.as_ref() == topic)
+    {
+        Ok(())
+    } else {
+        Err(Error::UnauthorizedTopic(topic.to_string()))
+    }
+}
+
+/// Checks that the client is allowed to read the history of every topic.
+pub(crate) async fn authorize_topics<'a>(
+    state: &Arc<AppState>,
+    client_id: &ClientId,
+    topics: impl Iterator<Item = &'a str>,
+) -> error::Result<()> {
+    let mut topics: Vec<&str> = topics.collect();
+    let subscribed = subscribed_topics(state, client_id).await?;
+    topics.retain(|topic| {
+        !subscribed
+            .iter()
+            .any(|subscribed| subscribed.as_ref() == *topic)
+    });
+    if topics.is_empty() {
+        return Ok(());
+    }
+
+    let authorized = state
+        .messages_store
+        .topics_with_messages(client_id.as_ref(), &topics)
+        .await?;
+
+    match topics.into_iter().find(|topic| {
+        !authorized
+            .iter()
+            .any(|authorized| authorized.as_ref() == *topic)
+    }) {
+        Some(topic) => Err(Error::UnauthorizedTopic(topic.to_string())),
+        None => Ok(()),
+    }
+}
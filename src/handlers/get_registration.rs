@@ -1,33 +1,26 @@
 use {
     super::register::RegisterPayload,
     crate::{
-        auth::AuthBearer,
+        auth::AuthClient,
         error,
         increment_counter,
         state::{AppState, CachedRegistration},
     },
     axum::{extract::State, Json},
-    relay_rpc::{
-        domain::ClientId,
-        jwt::{JwtBasicClaims, VerifyableClaims},
-    },
     std::sync::Arc,
 };
 
 pub async fn handler(
     State(state): State<Arc<AppState>>,
-    AuthBearer(token): AuthBearer,
+    AuthClient(client_id): AuthClient,
 ) -> Result<Json<RegisterPayload>, error::Error> {
-    let claims = JwtBasicClaims::try_from_str(&token)?;
-    claims.verify_basic(&state.auth_aud, None)?;
-    let client_id = ClientId::from(claims.iss);
-
     increment_counter!(state.metrics, registration_cache_invalidation);
     state
         .registration_cache
         .invalidate(client_id.as_ref())
         .await;
 
+    let version = state.registration_cache.version();
     let registration = state
         .registration_store
         .get_registration(client_id.as_ref())
@@ -35,10 +28,17 @@ pub async fn handler(
 
     state
         .registration_cache
-        .insert(client_id.into_value(), CachedRegistration {
-            tags: registration.tags.clone(),
-            relay_url: registration.relay_url.clone(),
-        })
+        .insert(
+            client_id.into_value(),
+            CachedRegistration::new(
+                registration.tags.clone(),
+                registration.relay_url.clone(),
+                registration
+                    .expires_at
+                    .map(|expires_at| expires_at.to_chrono()),
+            ),
+            version,
+        )
         .await;
 
     Ok(Json(RegisterPayload {
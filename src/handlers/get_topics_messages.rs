@@ -0,0 +1,121 @@
+use {
+    super::get_messages::{authorize_topics, MessageCount},
+    crate::{
+        auth::AuthClient,
+        error::{self, Error},
+        increment_counter,
+        increment_counter_with,
+        state::AppState,
+        store::messages::{Message, TopicOrigin},
+    },
+    axum::{extract::State, Json},
+    serde::{Deserialize, Serialize},
+    std::{collections::HashSet, sync::Arc},
+};
+
+/// The absolute max number of topics to load in a single request.
+pub const MAX_TOPIC_COUNT: usize = 500;
+
+/////////////////////////
+
+/// A topic to load, from its own position.
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct TopicQuery {
+    pub topic: Arc<str>,
+    /// The `nextId` of the topic's previous page, message IDs aren't accepted.
+    pub origin_id: Option<Arc<str>>,
+}
+
+/// The request body for the get topics messages endpoint.
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct GetTopicsMessagesBody {
+    pub topics: Vec<TopicQuery>,
+    /// The max number of messages to return across all the topics.
+    #[serde(default)]
+    pub message_count: MessageCount,
+}
+
+/////////////////////////
+
+/// The page of a single topic.
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct TopicMessages {
+    pub topic: Arc<str>,
+    /// The opaque cursor of the topic's next page, to send back as `originId`.
+    pub next_id: Option<Arc<str>>,
+    pub messages: Vec<Message>,
+}
+
+/// The response body for the get topics messages endpoint, with one page per
+/// topic in the order of the request.
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct GetTopicsMessagesResponse {
+    pub topics: Vec<TopicMessages>,
+}
+
+/////////////////////////
+
+/// The handler for the get topics messages endpoint, loading the history of
+/// several topics forward in a single request.
+pub async fn handler(
+    State(state): State<Arc<AppState>>,
+    AuthClient(client_id): AuthClient,
+    Json(body): Json<GetTopicsMessagesBody>,
+) -> Result<Json<GetTopicsMessagesResponse>, error::Error> {
+    if body.topics.len() > MAX_TOPIC_COUNT {
+        return Err(Error::TooManyTopics(body.topics.len()));
+    }
+
+    let mut topics = HashSet::new();
+    for query in &body.topics {
+        if !topics.insert(query.topic.as_ref()) {
+            return Err(Error::DuplicateTopic(query.topic.to_string()));
+        }
+    }
+
+    authorize_topics(&state, &client_id, topics.into_iter()).await?;
+
+    let origins = body
+        .topics
+        .iter()
+        .map(|query| {
+            let origin = query
+                .origin_id
+                .as_deref()
+                .map(|origin_id| state.cursors.decode(origin_id))
+                .transpose()?;
+            Ok(TopicOrigin {
+                topic: query.topic.clone(),
+                origin,
+            })
+        })
+        .collect::<error::Result<Vec<_>>>()?;
+
+    let pages = state
+        .messages_store
+        .get_topics_messages_after(&origins, body.message_count.limit())
+        .await?;
+
+    increment_counter!(state.metrics, get_queries);
+
+    let topics = origins
+        .into_iter()
+        .zip(pages)
+        .map(|(origin, page)| {
+            increment_counter_with!(state.metrics, served_items, page.messages.len() as u64);
+            TopicMessages {
+                topic: origin.topic,
+                next_id: page
+                    .next_cursor
+                    .map(|cursor| state.cursors.encode(&cursor).into()),
+                messages: page.messages,
+            }
+        })
+        .collect();
+
+    Ok(Json(GetTopicsMessagesResponse { topics }))
+}
@@ -1,9 +1,16 @@
 use {
-    crate::state::AppState,
-    axum::{extract::State, http::StatusCode, response::IntoResponse},
-    std::sync::Arc,
+    crate::{log::prelude::*, state::AppState, store::StoreError},
+    axum::{extract::State, http::StatusCode, response::IntoResponse, Json},
+    futures::future::join_all,
+    serde::{Deserialize, Serialize},
+    std::{future::Future, sync::Arc, time::Duration},
+    tokio::time::timeout,
 };
 
+/// How long the stores are given to answer the readiness pings.
+const PING_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// The liveness endpoint, answers as long as the server runs.
 pub async fn handler(State(state): State<Arc<AppState>>) -> impl IntoResponse {
     (
         StatusCode::OK,
@@ -13,3 +20,124 @@ pub async fn handler(State(state): State<Arc<AppState>>) -> impl IntoResponse {
         ),
     )
 }
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum ComponentStatus {
+    Ok,
+    Unavailable,
+    /// The component isn't used with the current configuration.
+    Disabled,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub struct ComponentHealth {
+    pub status: ComponentStatus,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub struct RelayHealth {
+    pub url: String,
+    /// Whether the relay's public key is loaded, `Disabled` when signatures
+    /// aren't validated.
+    pub public_key: ComponentStatus,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub struct ReadinessResponse {
+    /// `Ok` when every store is reachable and the server isn't shutting down.
+    pub status: ComponentStatus,
+    pub name: String,
+    pub version: String,
+    pub messages_store: ComponentHealth,
+    pub registration_store: ComponentHealth,
+    /// The relays are reported but don't affect the readiness, their missing
+    /// keys are fetched again on the next webhook.
+    pub relays: Vec<RelayHealth>,
+}
+
+/// The readiness endpoint, checks that the stores are reachable before the
+/// instance receives traffic, and stops the traffic once it drains.
+pub async fn ready_handler(State(state): State<Arc<AppState>>) -> impl IntoResponse {
+    let (messages_store, registration_store) = tokio::join!(
+        ping("messages store", state.messages_store.ping()),
+        ping("registration store", state.registration_store.ping()),
+    );
+
+    let relays = join_all(state.relays.iter().map(|relay_client| async {
+        let public_key = if !state.config.validate_signatures {
+            ComponentStatus::Disabled
+        } else if relay_client.has_public_key().await {
+            ComponentStatus::Ok
+        } else {
+            ComponentStatus::Unavailable
+        };
+
+        RelayHealth {
+            url: relay_client.base_url().to_string(),
+            public_key,
+        }
+    }))
+    .await;
+
+    let is_shutting_down = state.is_shutting_down();
+    let is_ready = !is_shutting_down
+        && messages_store.status == ComponentStatus::Ok
+        && registration_store.status == ComponentStatus::Ok;
+    let response = ReadinessResponse {
+        status: if is_ready {
+            ComponentStatus::Ok
+        } else {
+            ComponentStatus::Unavailable
+        },
+        name: state.build_info.crate_info.name.clone(),
+        version: state.build_info.crate_info.version.to_string(),
+        messages_store,
+        registration_store,
+        relays,
+    };
+
+    let status_code = if is_ready {
+        StatusCode::OK
+    } else {
+        if is_shutting_down {
+            info!("Not ready, shutting down");
+        } else {
+            warn!("Not ready: {:?}", response);
+        }
+        StatusCode::SERVICE_UNAVAILABLE
+    };
+    (status_code, Json(response))
+}
+
+async fn ping(
+    component: &str,
+    ping: impl Future<Output = Result<(), StoreError>>,
+) -> ComponentHealth {
+    // The errors are only logged, the endpoint is public
+    let is_available = match timeout(PING_TIMEOUT, ping).await {
+        Ok(Ok(())) => true,
+        Ok(Err(e)) => {
+            warn!("The {component} is unavailable: {e}");
+            false
+        }
+        Err(_) => {
+            warn!(
+                "The {component} didn't answer within {}s",
+                PING_TIMEOUT.as_secs()
+            );
+            false
+        }
+    };
+
+    ComponentHealth {
+        status: if is_available {
+            ComponentStatus::Ok
+        } else {
+            ComponentStatus::Unavailable
+        },
+    }
+}
@@ -4,18 +4,25 @@ use {
     serde_json::{json, Value},
 };
 
+pub mod delete_messages;
+pub mod delete_registration;
 pub mod get_messages;
 pub mod get_registration;
+pub mod get_topics_messages;
 pub mod health;
 pub mod metrics;
 pub mod register;
 pub mod save_message;
+pub mod save_message_batch;
+pub mod stream_messages;
+pub mod subscriptions;
 
 #[derive(serde::Serialize)]
 #[serde(rename_all = "lowercase")]
 pub enum ErrorLocation {
     Body,
     Header,
+    Query,
 }
 
 #[derive(serde::Serialize)]
@@ -1,17 +1,15 @@
 use {
     crate::{
-        auth::AuthBearer,
+        auth::AuthClient,
         error::{self, Error},
         handlers::Response,
         increment_counter,
-        log::prelude::*,
-        state::{AppState, CachedRegistration},
+        state::AppState,
+        tags::TagPattern,
     },
     axum::{extract::State, Json},
-    relay_rpc::{
-        domain::ClientId,
-        jwt::{JwtBasicClaims, VerifyableClaims},
-    },
+    chrono::Utc,
+    relay_rpc::domain::ClientId,
     serde::{Deserialize, Serialize},
     std::{collections::HashSet, sync::Arc},
 };
@@ -27,20 +25,20 @@ pub struct RegisterPayload {
 
 pub async fn handler(
     State(state): State<Arc<AppState>>,
-    AuthBearer(token): AuthBearer,
+    AuthClient(client_id): AuthClient,
     Json(body): Json<RegisterPayload>,
 ) -> error::Result<Response> {
-    let claims = JwtBasicClaims::try_from_str(&token)?;
-    claims.verify_basic(&state.auth_aud, None)?;
-    let client_id = ClientId::from(claims.iss);
-
     increment_counter!(state.metrics, register);
 
+    validate_tag_patterns(&body)?;
+    // Stored normalized so the signing relay is found by its URL
+    let relay_url = state.relays.resolve(&body.relay_url)?;
+
     if let Some(tags) = body.tags {
         increment_counter!(state.metrics, registration_overwrite);
 
         let tags = tags.into_iter().collect::<HashSet<_>>();
-        overwrite_registration(&state, client_id.clone(), tags, body.relay_url).await?;
+        overwrite_registration(&state, client_id.clone(), tags, relay_url).await?;
     } else {
         increment_counter!(state.metrics, registration_update);
 
@@ -56,7 +54,7 @@ pub async fn handler(
             client_id.clone(),
             append_tags,
             remove_tags,
-            body.relay_url,
+            relay_url,
         )
         .await?;
     }
@@ -64,28 +62,49 @@ pub async fn handler(
     Ok(Response::default())
 }
 
+/// Rejects the malformed tag patterns to register. The removed tags aren't
+/// checked so that previously stored ones can still be removed.
+fn validate_tag_patterns(body: &RegisterPayload) -> error::Result<()> {
+    let mut invalid = vec![];
+    for (field, tags) in [("tags", &body.tags), ("appendTags", &body.append_tags)] {
+        for (index, tag) in tags.iter().flatten().enumerate() {
+            if let Err(e) = tag.parse::<TagPattern>() {
+                invalid.push((format!("{field}[{index}]"), e));
+            }
+        }
+    }
+
+    if invalid.is_empty() {
+        Ok(())
+    } else {
+        Err(Error::InvalidTagPatterns(invalid))
+    }
+}
+
 async fn overwrite_registration(
     state: &Arc<AppState>,
     client_id: ClientId,
     tags: HashSet<Arc<str>>,
     relay_url: Arc<str>,
 ) -> error::Result<Response> {
+    // Registering again keeps the registration alive
+    let expires_at = state.retention.registration_expires_at(Utc::now());
+
     state
         .registration_store
         .upsert_registration(
             client_id.value(),
-            tags.iter().map(AsRef::as_ref).collect(),
+            Some(tags.iter().map(AsRef::as_ref).collect()),
             relay_url.as_ref(),
+            expires_at,
         )
         .await?;
 
-    state
-        .registration_cache
-        .insert(client_id.into_value(), CachedRegistration {
-            tags: tags.into_iter().collect::<Vec<_>>(),
-            relay_url,
-        })
-        .await;
+    // Not cached here: a concurrent change stored after this one could be
+    // overwritten in the cache. Every instance loads the new registration with
+    // its next message.
+    increment_counter!(state.metrics, registration_cache_invalidation);
+    state.invalidate_registration(client_id.as_ref()).await;
 
     Ok(Response::default())
 }
@@ -104,21 +123,25 @@ async fn update_registration(
         return Err(Error::InvalidUpdateRequest);
     }
 
-    let registration = state
+    // The tags are updated in place by the store so that concurrent updates
+    // don't overwrite each other's tags, and a registration deleted meanwhile
+    // isn't recreated
+    let expires_at = state.retention.registration_expires_at(Utc::now());
+    state
         .registration_store
-        .get_registration(client_id.as_ref())
+        .update_registration(
+            client_id.as_ref(),
+            append_tags.iter().map(AsRef::as_ref).collect(),
+            remove_tags.iter().map(AsRef::as_ref).collect(),
+            relay_url.as_ref(),
+            expires_at,
+        )
         .await?;
 
-    let tags = registration
-        .tags
-        .into_iter()
-        .collect::<HashSet<_>>()
-        .difference(&remove_tags)
-        .cloned()
-        .collect::<HashSet<_>>()
-        .union(&append_tags)
-        .cloned()
-        .collect();
-
-    overwrite_registration(state, client_id, tags, relay_url).await
+    // The resulting tags are only known to the store, they are loaded again
+    // with the next message
+    increment_counter!(state.metrics, registration_cache_invalidation);
+    state.invalidate_registration(client_id.as_ref()).await;
+
+    Ok(Response::default())
 }
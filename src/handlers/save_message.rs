@@ -1,19 +1,50 @@
 use {
     crate::{
-        error,
+        error::{self, Error},
         handlers::Response,
         increment_counter,
         log::prelude::*,
         relay::signature::RequireValidSignature,
         state::{AppState, CachedRegistration},
-        store::{registrations::Registration, StoreError},
-        tags::match_tag,
+        store::{messages::NewMessage, StoreError},
     },
     axum::{extract::State as StateExtractor, Json},
+    chrono::Utc,
     serde::{Deserialize, Serialize},
-    std::sync::Arc,
+    std::{
+        collections::{HashMap, HashSet},
+        sync::Arc,
+        time::Duration,
+    },
 };
 
+/// The methods of the messages the Relay archives.
+pub const RELAY_METHODS: [&str; 2] = ["publish", "subscription"];
+
+/// The number of bytes of the topics and message IDs, hex encoded.
+const ID_LENGTH: usize = 32;
+
+/// The room left in a message's body for its other fields and the JSON syntax.
+const PAYLOAD_OVERHEAD: usize = 1024;
+
+/// Returns the maximum size of a message's body, the larger ones are rejected
+/// before being buffered.
+pub fn body_limit(message_max_size: usize) -> usize {
+    message_max_size + PAYLOAD_OVERHEAD
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, thiserror::Error)]
+pub enum PayloadError {
+    #[error("`{0}` is not one of the relay methods: publish, subscription")]
+    UnknownMethod(String),
+
+    #[error("must be {ID_LENGTH} bytes encoded as hex")]
+    InvalidId,
+
+    #[error("the message is {0} bytes long, more than the maximum of {1}")]
+    MessageTooLarge(usize, usize),
+}
+
 #[derive(Serialize, Deserialize, Debug, Clone, Eq, PartialEq)]
 #[serde(rename_all = "camelCase")]
 pub struct HistoryPayload {
@@ -25,72 +56,279 @@ pub struct HistoryPayload {
     pub message: Arc<str>,
 }
 
+impl HistoryPayload {
+    /// Returns the invalid fields of the payload, by their name in the body.
+    pub fn validate(&self, message_max_size: usize) -> Vec<(&'static str, PayloadError)> {
+        let mut invalid = vec![];
+
+        if !RELAY_METHODS.contains(&self.method.as_ref()) {
+            invalid.push((
+                "method",
+                PayloadError::UnknownMethod(self.method.to_string()),
+            ));
+        }
+        for (field, id) in [("topic", &self.topic), ("messageId", &self.message_id)] {
+            if !is_hex_id(id) {
+                invalid.push((field, PayloadError::InvalidId));
+            }
+        }
+        if self.message.len() > message_max_size {
+            invalid.push((
+                "message",
+                PayloadError::MessageTooLarge(self.message.len(), message_max_size),
+            ));
+        }
+
+        invalid
+    }
+}
+
+pub(crate) fn is_hex_id(id: &str) -> bool {
+    id.len() == 2 * ID_LENGTH && id.bytes().all(|byte| byte.is_ascii_hexdigit())
+}
+
 pub async fn handler(
     StateExtractor(state): StateExtractor<Arc<AppState>>,
-    RequireValidSignature(Json(payload)): RequireValidSignature<Json<HistoryPayload>>,
+    RequireValidSignature(Json(payload), _): RequireValidSignature<Json<HistoryPayload>>,
 ) -> error::Result<Response> {
     debug!("Received `save_message` query: {:?}", payload);
 
     increment_counter!(state.metrics, received_items);
 
-    let registration = if let Some(registration) = state
-        .registration_cache
-        .get(payload.client_id.as_ref())
-        .map(|r| Registration {
-            id: None,
-            client_id: payload.client_id.clone(),
-            tags: r.tags,
-            relay_url: r.relay_url,
-        }) {
-        debug!("loaded registration from cache");
-        increment_counter!(state.metrics, cached_registrations);
-        registration
-    } else {
-        debug!("loading registration from database");
-        let registration = match state
-            .registration_store
-            .get_registration(payload.client_id.as_ref())
-            .await
+    let invalid = payload.validate(state.config.message_max_size);
+    if !invalid.is_empty() {
+        return Err(Error::InvalidPayload(
+            invalid
+                .into_iter()
+                .map(|(field, e)| (field.to_string(), e))
+                .collect(),
+        ));
+    }
+
+    let registration = match load_registration(&state, &payload.client_id).await? {
+        Some(registration) => registration,
+        None => return Ok(Response::default()),
+    };
+
+    if matches_registration(&registration, payload.tag) {
+        if !MessageQuotas::default()
+            .reserve(
+                &state,
+                &payload.client_id,
+                &payload.topic,
+                &payload.message_id,
+            )
+            .await?
         {
-            Ok(registration) => registration,
-            Err(StoreError::NotFound(_, _)) => return Ok(Response::default()),
-            Err(e) => return Err(e.into()),
+            increment_counter!(state.metrics, quota_exceeded_items);
+            return Err(Error::QuotaExceeded(quota_retry_after(&state)));
+        }
+
+        debug!("tag matching, storing message");
+        let message = NewMessage {
+            expires_at: state.retention.expires_at(payload.tag, Utc::now()),
+            method: payload.method,
+            client_id: payload.client_id,
+            topic: payload.topic,
+            message_id: payload.message_id,
+            message: payload.message,
+            tag: Some(payload.tag),
         };
 
+        let cursor = state.messages_store.upsert_message(&message).await?;
+
+        debug!("message stored, sending ack");
+
+        increment_counter!(state.metrics, stored_items);
+        state.publish_message(message.into_message(cursor));
+    }
+
+    Ok(Response::default())
+}
+
+/// Loads the client's registration from the cache, or from the database when
+/// not cached, `None` if the client isn't registered.
+pub(crate) async fn load_registration(
+    state: &AppState,
+    client_id: &Arc<str>,
+) -> error::Result<Option<CachedRegistration>> {
+    if let Some(registration) = state.registration_cache.get(client_id.as_ref()) {
+        if !registration.is_expired(Utc::now()) {
+            debug!("loaded registration from cache");
+            increment_counter!(state.metrics, cached_registrations);
+            return Ok(Some(registration));
+        }
+
+        // The store doesn't return expired registrations either
         state
             .registration_cache
-            .insert(payload.client_id.clone(), CachedRegistration {
-                tags: registration.tags.clone(),
-                relay_url: registration.relay_url.clone(),
-            })
+            .invalidate(client_id.as_ref())
             .await;
+        return Ok(None);
+    }
 
-        increment_counter!(state.metrics, fetched_registrations);
-        registration
+    debug!("loading registration from database");
+    let version = state.registration_cache.version();
+    let registration = match state
+        .registration_store
+        .get_registration(client_id.as_ref())
+        .await
+    {
+        Ok(registration) => registration,
+        Err(StoreError::NotFound(_, _)) => return Ok(None),
+        Err(e) => return Err(e.into()),
     };
 
-    let tags = registration.tags;
-    for tag in &tags {
-        if match_tag(payload.tag, tag) {
-            debug!("tag matching, storing message");
-            state
-                .messages_store
-                .upsert_message(
-                    payload.method.as_ref(),
-                    payload.client_id.as_ref(),
-                    payload.topic.as_ref(),
-                    payload.message_id.as_ref(),
-                    payload.message.as_ref(),
-                )
-                .await?;
-
-            debug!("message stored, sending ack");
-
-            increment_counter!(state.metrics, stored_items);
-
-            return Ok(Response::default());
+    let registration = CachedRegistration::new(
+        registration.tags,
+        registration.relay_url,
+        registration
+            .expires_at
+            .map(|expires_at| expires_at.to_chrono()),
+    );
+    state
+        .registration_cache
+        .insert(client_id.clone(), registration.clone(), version)
+        .await;
+
+    increment_counter!(state.metrics, fetched_registrations);
+    Ok(Some(registration))
+}
+
+/// Counts the messages stored for the clients against the
+/// `CLIENT_MESSAGE_QUOTA` and `TOPIC_MESSAGE_QUOTA`, loading each count once
+/// and keeping track of the messages reserved since.
+///
+/// The quotas are soft limits: the counts aren't locked, so the concurrent
+/// requests of a client may each store up to its remaining room.
+#[derive(Default)]
+pub(crate) struct MessageQuotas {
+    clients: HashMap<Arc<str>, u64>,
+    topics: HashMap<(Arc<str>, Arc<str>), u64>,
+    reserved: HashSet<(Arc<str>, Arc<str>, Arc<str>)>,
+}
+
+impl MessageQuotas {
+    /// Reserves room for the client's message on the topic, returning `false`
+    /// when a quota is exhausted. Replacing a stored message takes no room.
+    pub(crate) async fn reserve(
+        &mut self,
+        state: &AppState,
+        client_id: &Arc<str>,
+        topic: &Arc<str>,
+        message_id: &Arc<str>,
+    ) -> error::Result<bool> {
+        if state.config.client_message_quota.is_none() && state.config.topic_message_quota.is_none()
+        {
+            return Ok(true);
+        }
+
+        let store = &state.messages_store;
+        let message_key = (client_id.clone(), topic.clone(), message_id.clone());
+        if self.reserved.contains(&message_key)
+            || store.has_message(client_id, topic, message_id).await?
+        {
+            return Ok(true);
+        }
+
+        let topic_key = (client_id.clone(), topic.clone());
+
+        let client_count = match state.config.client_message_quota {
+            Some(quota) => {
+                if !self.clients.contains_key(client_id) {
+                    let count = store.count_messages(client_id, None).await?;
+                    self.clients.insert(client_id.clone(), count);
+                }
+                self.clients.get_mut(client_id).map(|count| (count, quota))
+            }
+            None => None,
+        };
+        let topic_count = match state.config.topic_message_quota {
+            Some(quota) => {
+                if !self.topics.contains_key(&topic_key) {
+                    let count = store.count_messages(client_id, Some(topic)).await?;
+                    self.topics.insert(topic_key.clone(), count);
+                }
+                self.topics.get_mut(&topic_key).map(|count| (count, quota))
+            }
+            None => None,
+        };
+
+        let counts = [client_count, topic_count];
+        if counts
+            .iter()
+            .flatten()
+            .any(|(count, quota)| **count >= *quota)
+        {
+            return Ok(false);
+        }
+
+        for (count, _) in counts.into_iter().flatten() {
+            *count += 1;
         }
+        self.reserved.insert(message_key);
+        Ok(true)
     }
+}
 
-    Ok(Response::default())
+/// Returns when the quotas may have room again, once the expired messages are
+/// deleted. Without a global max age some messages never expire, only the
+/// client deleting its messages makes room.
+pub(crate) fn quota_retry_after(state: &AppState) -> Option<Duration> {
+    state
+        .retention
+        .max_age
+        .map(|_| Duration::from_secs(state.config.message_expiry_interval))
+}
+
+/// Checks whether the message tag matches the registered tag patterns.
+pub(crate) fn matches_registration(registration: &CachedRegistration, tag: u32) -> bool {
+    registration.matcher.matches(tag)
+}
+
+#[cfg(test)]
+mod test_payload_validation {
+    use super::*;
+
+    fn payload() -> HistoryPayload {
+        HistoryPayload {
+            method: Arc::from("publish"),
+            client_id: Arc::from("client"),
+            topic: Arc::from("ab".repeat(ID_LENGTH)),
+            message_id: Arc::from("0123456789ABCDEF".repeat(4)),
+            tag: 4000,
+            message: Arc::from("message"),
+        }
+    }
+
+    #[test]
+    fn test_valid() {
+        assert_eq!(payload().validate(7), vec![]);
+
+        let payload = HistoryPayload {
+            method: Arc::from("subscription"),
+            ..payload()
+        };
+        assert_eq!(payload.validate(7), vec![]);
+    }
+
+    #[test]
+    fn test_invalid() {
+        let payload = HistoryPayload {
+            method: Arc::from("irn_publish"),
+            topic: Arc::from("ab".repeat(ID_LENGTH + 1)),
+            message_id: Arc::from(format!("{}zz", "ab".repeat(ID_LENGTH - 1))),
+            ..payload()
+        };
+
+        assert_eq!(payload.validate(6), vec![
+            (
+                "method",
+                PayloadError::UnknownMethod("irn_publish".to_string())
+            ),
+            ("topic", PayloadError::InvalidId),
+            ("messageId", PayloadError::InvalidId),
+            ("message", PayloadError::MessageTooLarge(7, 6)),
+        ]);
+    }
 }
@@ -0,0 +1,190 @@
+use {
+    super::save_message::{
+        self,
+        load_registration,
+        matches_registration,
+        HistoryPayload,
+        MessageQuotas,
+    },
+    crate::{
+        error::{self, Error},
+        increment_counter_with,
+        log::prelude::*,
+        relay::signature::RequireValidSignature,
+        state::AppState,
+        store::messages::NewMessage,
+    },
+    axum::{extract::State as StateExtractor, Json},
+    chrono::Utc,
+    serde::{Deserialize, Serialize},
+    std::{collections::HashMap, sync::Arc},
+};
+
+/// The absolute max number of messages accepted in a single batch.
+pub const MAX_BATCH_SIZE: usize = 500;
+
+/// Returns the maximum size of a batch's body, holding `MAX_BATCH_SIZE`
+/// messages of the maximum size.
+pub fn body_limit(message_max_size: usize) -> usize {
+    MAX_BATCH_SIZE * save_message::body_limit(message_max_size)
+}
+
+/// What happened to a message of the batch.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Eq, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum BatchItemStatus {
+    /// The message matched the client's tags and was stored.
+    Stored,
+    /// The message didn't match any of the client's tags.
+    FilteredOut,
+    /// The client isn't registered.
+    NotRegistered,
+    /// The client registered with another relay than the one signing the
+    /// batch.
+    RelayMismatch,
+    /// The client's message quota, or its quota on the topic, is exhausted.
+    QuotaExceeded,
+    /// The store failed to write the message, it may be sent again.
+    Failed,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Eq, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct BatchItemResult {
+    pub client_id: Arc<str>,
+    pub message_id: Arc<str>,
+    pub status: BatchItemStatus,
+}
+
+/// The response body for the batch endpoint, with one result per message in
+/// the order of the request.
+#[derive(Serialize, Deserialize, Debug, Clone, Eq, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct BatchResponse {
+    pub items: Vec<BatchItemResult>,
+}
+
+pub async fn handler(
+    StateExtractor(state): StateExtractor<Arc<AppState>>,
+    RequireValidSignature(Json(payloads), signing_relay): RequireValidSignature<
+        Json<Vec<HistoryPayload>>,
+    >,
+) -> error::Result<Json<BatchResponse>> {
+    debug!(
+        "Received `save_message_batch` query of {} items",
+        payloads.len()
+    );
+
+    if payloads.len() > MAX_BATCH_SIZE {
+        return Err(Error::BatchTooLarge(payloads.len()));
+    }
+
+    increment_counter_with!(state.metrics, received_items, payloads.len() as u64);
+
+    let invalid: Vec<_> = payloads
+        .iter()
+        .enumerate()
+        .flat_map(|(index, payload)| {
+            payload
+                .validate(state.config.message_max_size)
+                .into_iter()
+                .map(move |(field, e)| (format!("[{index}].{field}"), e))
+        })
+        .collect();
+    if !invalid.is_empty() {
+        return Err(Error::InvalidPayload(invalid));
+    }
+
+    // Each client's registration is only loaded once for the whole batch
+    let mut registrations = HashMap::new();
+    for payload in &payloads {
+        if !registrations.contains_key(&payload.client_id) {
+            let registration = load_registration(&state, &payload.client_id).await?;
+            registrations.insert(payload.client_id.clone(), registration);
+        }
+    }
+
+    let now = Utc::now();
+    let mut quotas = MessageQuotas::default();
+    let mut messages = Vec::new();
+    // The index of the stored messages' items
+    let mut stored_items = Vec::new();
+    let mut items = Vec::with_capacity(payloads.len());
+    for payload in payloads {
+        let status = match &registrations[&payload.client_id] {
+            None => BatchItemStatus::NotRegistered,
+            Some(registration)
+                if signing_relay
+                    .as_ref()
+                    .is_some_and(|relay| !state.is_registered_with(registration, relay)) =>
+            {
+                BatchItemStatus::RelayMismatch
+            }
+            Some(registration) if !matches_registration(registration, payload.tag) => {
+                BatchItemStatus::FilteredOut
+            }
+            Some(_)
+                if !quotas
+                    .reserve(
+                        &state,
+                        &payload.client_id,
+                        &payload.topic,
+                        &payload.message_id,
+                    )
+                    .await? =>
+            {
+                BatchItemStatus::QuotaExceeded
+            }
+            Some(_) => {
+                messages.push(NewMessage {
+                    method: payload.method,
+                    client_id: payload.client_id.clone(),
+                    topic: payload.topic,
+                    message_id: payload.message_id.clone(),
+                    message: payload.message,
+                    tag: Some(payload.tag),
+                    expires_at: state.retention.expires_at(payload.tag, now),
+                });
+                stored_items.push(items.len());
+                BatchItemStatus::Stored
+            }
+        };
+
+        items.push(BatchItemResult {
+            client_id: payload.client_id,
+            message_id: payload.message_id,
+            status,
+        });
+    }
+
+    let quota_exceeded = items
+        .iter()
+        .filter(|item| item.status == BatchItemStatus::QuotaExceeded)
+        .count() as u64;
+    increment_counter_with!(state.metrics, quota_exceeded_items, quota_exceeded);
+
+    let results = if messages.is_empty() {
+        vec![]
+    } else {
+        state.messages_store.upsert_messages(&messages).await?
+    };
+
+    let mut stored = 0;
+    for ((message, result), index) in messages.into_iter().zip(results).zip(stored_items) {
+        match result {
+            Ok(cursor) => {
+                stored += 1;
+                state.publish_message(message.into_message(cursor));
+            }
+            Err(e) => {
+                warn!("Failed to store a message of the batch: {:?}", e);
+                items[index].status = BatchItemStatus::Failed;
+            }
+        }
+    }
+
+    debug!("{} messages of the batch stored, sending ack", stored);
+    increment_counter_with!(state.metrics, stored_items, stored);
+
+    Ok(Json(BatchResponse { items }))
+}
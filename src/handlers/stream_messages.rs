@@ -0,0 +1,200 @@
+use {
+    super::get_messages::{authorize_topic, MAX_MESSAGE_COUNT},
+    crate::{
+        auth::AuthClient,
+        cursor::CursorCodec,
+        error,
+        increment_counter,
+        log::prelude::*,
+        state::AppState,
+        store::messages::{Message, MessageCursor, MessageFilter, StoreMessages},
+    },
+    axum::{
+        extract::{Query, State},
+        http::HeaderMap,
+        response::sse::{Event, KeepAlive, Sse},
+    },
+    futures::{stream, Stream, StreamExt},
+    serde::{Deserialize, Serialize},
+    std::{collections::HashSet, sync::Arc},
+    tokio::sync::broadcast::{self, error::RecvError},
+};
+
+/// The header set by SSE clients when reconnecting, see
+/// <https://html.spec.whatwg.org/multipage/server-sent-events.html#last-event-id>.
+pub const LAST_EVENT_ID_HEADER_NAME: &str = "last-event-id";
+
+/// The query parameters for the stream messages endpoint.
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct StreamMessagesQuery {
+    pub topic: Arc<str>,
+    /// The ID of the last event received, the messages archived after it are
+    /// sent before the new ones. Defaults to the `Last-Event-ID` header.
+    pub last_event_id: Option<Arc<str>>,
+}
+
+/// The handler for the stream messages endpoint, streams the messages
+/// archived on the topic as Server-Sent Events.
+pub async fn handler(
+    State(state): State<Arc<AppState>>,
+    AuthClient(client_id): AuthClient,
+    headers: HeaderMap,
+    Query(query): Query<StreamMessagesQuery>,
+) -> error::Result<Sse<impl Stream<Item = Result<Event, serde_json::Error>>>> {
+    authorize_topic(&state, &client_id, query.topic.as_ref()).await?;
+
+    let last_event_id = query.last_event_id.or_else(|| {
+        headers
+            .get(LAST_EVENT_ID_HEADER_NAME)
+            .and_then(|header| header.to_str().ok())
+            .map(Arc::from)
+    });
+    let last_cursor = last_event_id
+        .as_deref()
+        .map(|last_event_id| state.cursors.decode(last_event_id))
+        .transpose()?;
+
+    // Subscribed before loading the missed messages so that none archived in
+    // the meantime is lost
+    let receiver = state.message_events.subscribe();
+
+    let (pending, origin) = match &last_cursor {
+        Some(last_cursor) => {
+            let StoreMessages {
+                messages,
+                next_cursor,
+            } = state
+                .messages_store
+                .get_messages_after(
+                    query.topic.as_ref(),
+                    &MessageFilter::default(),
+                    Some(last_cursor),
+                    MAX_MESSAGE_COUNT,
+                )
+                .await?;
+            (messages, next_cursor)
+        }
+        None => (vec![], None),
+    };
+
+    increment_counter!(state.metrics, stream_subscriptions);
+
+    let cursors = state.cursors.clone();
+    // Ended on shutdown rather than holding it up, the clients reconnect
+    // from their last event
+    let shutdown_started = state.shutdown_started();
+    let subscription = Subscription {
+        state,
+        topic: query.topic,
+        receiver,
+        pending,
+        origin,
+        sent: HashSet::new(),
+    };
+
+    let stream = stream::unfold(subscription, Subscription::next)
+        .flat_map(move |messages| {
+            let events: Vec<_> = messages
+                .into_iter()
+                .map(|message| to_event(&cursors, message))
+                .collect();
+            stream::iter(events)
+        })
+        .take_until(shutdown_started);
+
+    Ok(Sse::new(stream).keep_alive(KeepAlive::default()))
+}
+
+/// A client's subscription to a topic, first catching up on the missed
+/// messages page by page then following the newly archived ones.
+struct Subscription {
+    state: Arc<AppState>,
+    topic: Arc<str>,
+    receiver: broadcast::Receiver<Message>,
+    /// The missed messages yet to be sent.
+    pending: Vec<Message>,
+    /// The position of the last loaded missed message, when more follow.
+    origin: Option<MessageCursor>,
+    /// The missed messages already sent, identified by client and message ID,
+    /// that may be archived again while catching up.
+    sent: HashSet<(Arc<str>, Arc<str>)>,
+}
+
+impl Subscription {
+    async fn next(mut self) -> Option<(Vec<Message>, Self)> {
+        if !self.pending.is_empty() {
+            let messages = std::mem::take(&mut self.pending);
+            self.sent.extend(
+                messages
+                    .iter()
+                    .map(|message| (message.client_id.clone(), message.message_id.clone())),
+            );
+            return Some((messages, self));
+        }
+
+        if let Some(origin) = self.origin.take() {
+            match self
+                .state
+                .messages_store
+                .get_messages_after(
+                    self.topic.as_ref(),
+                    &MessageFilter::default(),
+                    Some(&origin),
+                    MAX_MESSAGE_COUNT,
+                )
+                .await
+            {
+                Ok(StoreMessages {
+                    messages,
+                    next_cursor,
+                }) => {
+                    self.pending = messages;
+                    self.origin = next_cursor;
+                    return Some((vec![], self));
+                }
+                Err(e) => {
+                    warn!(
+                        "Failed to load the missed messages, closing stream: {:?}",
+                        e
+                    );
+                    return None;
+                }
+            }
+        }
+
+        loop {
+            match self.receiver.recv().await {
+                Ok(message) if message.topic == self.topic => {
+                    let key = (message.client_id.clone(), message.message_id.clone());
+                    if !self.sent.remove(&key) {
+                        return Some((vec![message], self));
+                    }
+                }
+                Ok(_) => {}
+                // The client resumes from its last received message when
+                // reconnecting, so it doesn't miss the dropped ones
+                Err(RecvError::Lagged(count)) => {
+                    warn!(
+                        "Stream subscriber lagged by {} messages, closing stream",
+                        count
+                    );
+                    return None;
+                }
+                Err(RecvError::Closed) => return None,
+            }
+        }
+    }
+}
+
+/// Returns the message event, identified by the message's cursor so that
+/// reconnecting clients resume right after it.
+fn to_event(cursors: &CursorCodec, message: Message) -> Result<Event, serde_json::Error> {
+    let event = Event::default().event("message");
+    let event = match &message.cursor {
+        Some(cursor) => event.id(cursors.encode(cursor)),
+        None => event,
+    };
+
+    event.json_data(&message)
+}
@@ -0,0 +1,64 @@
+use {
+    super::save_message::{is_hex_id, PayloadError},
+    crate::{
+        error::{self, Error},
+        handlers::Response,
+        increment_counter,
+        log::prelude::*,
+        relay::signature::RequireValidSignature,
+        state::AppState,
+    },
+    axum::{extract::State, Json},
+    serde::{Deserialize, Serialize},
+    std::sync::Arc,
+};
+
+/// The topics a client subscribed to or unsubscribed from, sent by its relay.
+/// The clients can't vouch for their own subscriptions.
+#[derive(Serialize, Deserialize, Debug, Clone, Eq, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct SubscriptionsPayload {
+    pub client_id: Arc<str>,
+    #[serde(default)]
+    pub subscribe: Vec<Arc<str>>,
+    #[serde(default)]
+    pub unsubscribe: Vec<Arc<str>>,
+}
+
+/// The handler for the relay's subscriptions webhook, the subscribed topics
+/// let the client read their history.
+pub async fn handler(
+    State(state): State<Arc<AppState>>,
+    RequireValidSignature(Json(payload), _): RequireValidSignature<Json<SubscriptionsPayload>>,
+) -> error::Result<Response> {
+    debug!("Received `subscriptions` query: {:?}", payload);
+
+    let invalid: Vec<(String, PayloadError)> = [
+        ("subscribe", &payload.subscribe),
+        ("unsubscribe", &payload.unsubscribe),
+    ]
+    .into_iter()
+    .filter(|(_, topics)| !topics.iter().all(|topic| is_hex_id(topic)))
+    .map(|(field, _)| (field.to_string(), PayloadError::InvalidId))
+    .collect();
+    if !invalid.is_empty() {
+        return Err(Error::InvalidPayload(invalid));
+    }
+
+    state
+        .registration_store
+        .update_registration_topics(
+            payload.client_id.as_ref(),
+            payload.subscribe.iter().map(AsRef::as_ref).collect(),
+            payload.unsubscribe.iter().map(AsRef::as_ref).collect(),
+        )
+        .await?;
+
+    // The other instances load the new topics with their next request
+    increment_counter!(state.metrics, registration_cache_invalidation);
+    state
+        .invalidate_registration(payload.client_id.as_ref())
+        .await;
+
+    Ok(Response::default())
+}
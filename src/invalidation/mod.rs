@@ -0,0 +1,155 @@
+use {
+    crate::{increment_counter, log::prelude::*, state::AppState, store::StoreError},
+    async_trait::async_trait,
+    futures::{
+        stream::{self, BoxStream},
+        StreamExt,
+    },
+    std::{sync::Arc, time::Duration},
+    tokio::sync::broadcast::{self, error::RecvError},
+};
+
+/// The number of registration changes kept for a slow listener before it
+/// lags behind.
+const LOCAL_INVALIDATIONS_CAPACITY: usize = 1024;
+
+/// How long the listener waits before subscribing again after a failure.
+const RESUBSCRIBE_DELAY: Duration = Duration::from_secs(5);
+
+/// A registration change received from the bus.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Invalidation {
+    /// The client's registration changed.
+    Registration(Arc<str>),
+    /// Some changes were missed, every registration may be stale.
+    All,
+}
+
+pub type InvalidationStream = BoxStream<'static, Result<Invalidation, StoreError>>;
+
+/// Broadcasts the registration changes to every instance, so that they evict
+/// the stale entries of their `registration_cache`.
+#[async_trait]
+pub trait InvalidationBus: 'static + Send + Sync {
+    /// Notifies every instance, this one included, that the client's
+    /// registration changed.
+    async fn publish(&self, client_id: &str) -> Result<(), StoreError>;
+    /// Returns the registration changes published from now on, the stream
+    /// ends on the first error.
+    async fn subscribe(&self) -> Result<InvalidationStream, StoreError>;
+}
+
+/// A bus reaching the current process only, for single instance deployments
+/// and tests.
+#[derive(Clone)]
+pub struct LocalInvalidationBus {
+    sender: broadcast::Sender<Arc<str>>,
+}
+
+impl LocalInvalidationBus {
+    pub fn new() -> Self {
+        LocalInvalidationBus {
+            sender: broadcast::channel(LOCAL_INVALIDATIONS_CAPACITY).0,
+        }
+    }
+}
+
+impl Default for LocalInvalidationBus {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[async_trait]
+impl InvalidationBus for LocalInvalidationBus {
+    async fn publish(&self, client_id: &str) -> Result<(), StoreError> {
+        // Fails only when nobody is subscribed
+        let _ = self.sender.send(Arc::from(client_id));
+        Ok(())
+    }
+
+    async fn subscribe(&self) -> Result<InvalidationStream, StoreError> {
+        let invalidations = stream::unfold(self.sender.subscribe(), |mut receiver| async move {
+            let invalidation = match receiver.recv().await {
+                Ok(client_id) => Invalidation::Registration(client_id),
+                Err(RecvError::Lagged(_)) => Invalidation::All,
+                Err(RecvError::Closed) => return None,
+            };
+            Some((Ok(invalidation), receiver))
+        });
+
+        Ok(invalidations.boxed())
+    }
+}
+
+/// Evicts the registrations changed on any instance from the cache, runs
+/// until aborted.
+pub async fn listener(state: Arc<AppState>) {
+    loop {
+        match state.invalidation_bus.subscribe().await {
+            Ok(mut invalidations) => {
+                // The changes published while unsubscribed are lost
+                state.registration_cache.invalidate_all();
+
+                while let Some(invalidation) = invalidations.next().await {
+                    match invalidation {
+                        Ok(Invalidation::Registration(client_id)) => {
+                            increment_counter!(state.metrics, registration_cache_invalidation);
+                            state.registration_cache.invalidate(&client_id).await;
+                        }
+                        Ok(Invalidation::All) => state.registration_cache.invalidate_all(),
+                        Err(e) => {
+                            warn!("Failed to receive the registration invalidations: {:?}", e);
+                            break;
+                        }
+                    }
+                }
+            }
+            Err(e) => warn!(
+                "Failed to subscribe to the registration invalidations: {:?}",
+                e
+            ),
+        }
+
+        tokio::time::sleep(RESUBSCRIBE_DELAY).await;
+    }
+}
+
+#[cfg(test)]
+mod test_local_invalidation_bus {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_publish() {
+        let bus = LocalInvalidationBus::new();
+        // Nobody is subscribed yet
+        bus.publish("lost").await.unwrap();
+
+        let mut first = bus.subscribe().await.unwrap();
+        let mut second = bus.subscribe().await.unwrap();
+        bus.publish("client").await.unwrap();
+
+        let expected = Invalidation::Registration(Arc::from("client"));
+        assert_eq!(first.next().await.unwrap().unwrap(), expected);
+        assert_eq!(second.next().await.unwrap().unwrap(), expected);
+    }
+
+    #[tokio::test]
+    async fn test_lagged() {
+        let bus = LocalInvalidationBus::new();
+        let mut invalidations = bus.subscribe().await.unwrap();
+
+        for i in 0..=LOCAL_INVALIDATIONS_CAPACITY {
+            bus.publish(&i.to_string()).await.unwrap();
+        }
+
+        assert_eq!(
+            invalidations.next().await.unwrap().unwrap(),
+            Invalidation::All
+        );
+        assert_eq!(
+            invalidations.next().await.unwrap().unwrap(),
+            Invalidation::Registration(Arc::from("1"))
+        );
+    }
+}
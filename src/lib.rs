@@ -1,19 +1,34 @@
 use {
     crate::{
+        invalidation::LocalInvalidationBus,
         log::prelude::*,
-        state::{MessagesStorageArc, RegistrationStorageArc},
+        relay::signature::BodyLimit,
+        state::{
+            InvalidationBusArc,
+            MessagesStorageArc,
+            RegistrationStorageArc,
+            SignatureStorageArc,
+        },
     },
     axum::{
+        extract::DefaultBodyLimit,
         http,
-        routing::{get, post},
+        middleware,
+        routing::{delete, get, post},
+        Extension,
         Router,
     },
-    config::Configuration,
+    config::{Configuration, InvalidationBackend, StorageBackend},
+    futures::{future, FutureExt},
     opentelemetry::{sdk::Resource, KeyValue},
     state::AppState,
-    std::{net::SocketAddr, sync::Arc},
-    store::mongo::MongoStore,
-    tokio::{select, sync::broadcast},
+    std::{net::SocketAddr, sync::Arc, time::Duration},
+    store::{mongo::MongoStore, postgres::PostgresStore, sqlite::SqliteStore},
+    tokio::{
+        select,
+        sync::broadcast,
+        time::{sleep, timeout},
+    },
     tower::ServiceBuilder,
     tower_http::{
         cors::{Any, CorsLayer},
@@ -23,12 +38,16 @@ use {
 
 pub mod auth;
 pub mod config;
+pub mod cursor;
 pub mod error;
 pub mod handlers;
+pub mod invalidation;
 pub mod log;
 pub mod macros;
 pub mod metrics;
+pub mod rate_limit;
 pub mod relay;
+pub mod retention;
 pub mod state;
 pub mod store;
 pub mod tags;
@@ -37,6 +56,10 @@ pub mod tags;
 pub struct Options {
     pub messages_store: Option<MessagesStorageArc>,
     pub registration_store: Option<RegistrationStorageArc>,
+    pub signature_store: Option<SignatureStorageArc>,
+    /// The bus broadcasting the registration changes, defaults to a local one
+    /// when all the stores are provided.
+    pub invalidation_bus: Option<InvalidationBusArc>,
 }
 
 pub async fn bootstrap(
@@ -47,35 +70,47 @@ pub async fn bootstrap(
     // Check config is valid and then throw the error if its not
     config.is_valid()?;
 
-    let (messages_store, registration_store) =
-        match (options.messages_store, options.registration_store) {
-            (Some(messages_store), Some(registration_store)) => {
-                (messages_store, registration_store)
-            }
-            (Some(messages_store), None) => {
-                let store = Arc::new(MongoStore::new(&config).await?);
-                (messages_store, store as RegistrationStorageArc)
-            }
-            (None, Some(registration_store)) => {
-                let store = Arc::new(MongoStore::new(&config).await?);
-                (store as MessagesStorageArc, registration_store)
-            }
-            _ => {
-                let store = Arc::new(MongoStore::new(&config).await?);
-                (
-                    store.clone() as MessagesStorageArc,
-                    store as RegistrationStorageArc,
-                )
-            }
-        };
+    let (messages_store, registration_store, signature_store, invalidation_bus) = match (
+        options.messages_store,
+        options.registration_store,
+        options.signature_store,
+    ) {
+        (Some(messages_store), Some(registration_store), Some(signature_store)) => (
+            messages_store,
+            registration_store,
+            signature_store,
+            options
+                .invalidation_bus
+                .unwrap_or_else(|| Arc::new(LocalInvalidationBus::new())),
+        ),
+        (messages_store, registration_store, signature_store) => {
+            let stores = connect_stores(&config).await?;
+            (
+                messages_store.unwrap_or(stores.0),
+                registration_store.unwrap_or(stores.1),
+                signature_store.unwrap_or(stores.2),
+                options.invalidation_bus.unwrap_or(stores.3),
+            )
+        }
+    };
 
-    let mut state = AppState::new(config.clone(), messages_store, registration_store)?;
+    let mut state = AppState::new(
+        config.clone(),
+        messages_store,
+        registration_store,
+        signature_store,
+        invalidation_bus,
+    )?;
 
     if config.validate_signatures {
-        // Fetch public key so it's cached for the first 6hrs
-        let public_key = state.relay_client.public_key().await;
-        if public_key.is_err() {
-            warn!("Failed initial fetch of Relay's Public Key, this may prevent items validation.")
+        // Fetch the public keys so they're cached for the first 6hrs
+        for relay_client in state.relays.iter() {
+            if relay_client.public_key().await.is_err() {
+                warn!(
+                    "Failed initial fetch of {}'s Public Key, this may prevent items validation.",
+                    relay_client.base_url()
+                )
+            }
         }
     }
 
@@ -90,10 +125,16 @@ pub async fn bootstrap(
     }
 
     let port = state.config.port;
-    let private_port = state.config.telemetry_prometheus_port.unwrap_or(3001);
+    let private_port = state.config.private_port();
 
     let state_arc = Arc::new(state);
 
+    let reaper = tokio::spawn(retention::reaper(state_arc.clone()));
+    let invalidation_listener = tokio::spawn(invalidation::listener(state_arc.clone()));
+    let public_key_refresher = config
+        .validate_signatures
+        .then(|| tokio::spawn(relay::public_key_refresher(state_arc.clone())));
+
     let global_middleware = ServiceBuilder::new().layer(
         TraceLayer::new_for_http()
             .make_span_with(DefaultMakeSpan::new().include_headers(true))
@@ -107,30 +148,171 @@ pub async fn bootstrap(
 
     let cors = CorsLayer::new()
         .allow_origin(Any)
-        .allow_headers([http::header::CONTENT_TYPE, http::header::AUTHORIZATION]);
+        .allow_methods([http::Method::GET, http::Method::POST, http::Method::DELETE])
+        .allow_headers([
+            http::header::CONTENT_TYPE,
+            http::header::AUTHORIZATION,
+            http::HeaderName::from_static(handlers::stream_messages::LAST_EVENT_ID_HEADER_NAME),
+        ]);
 
-    let app = Router::new()
-        .route("/health", get(handlers::health::handler))
+    // The Relay sends every client's messages from the same addresses, their
+    // storage is limited by the message quotas instead
+    let client_routes = Router::new()
         .route("/messages", get(handlers::get_messages::handler))
-        .route("/messages", post(handlers::save_message::handler))
+        .route("/messages", delete(handlers::delete_messages::handler))
+        .route("/messages/stream", get(handlers::stream_messages::handler))
+        .route(
+            "/messages/topics",
+            post(handlers::get_topics_messages::handler),
+        )
         .route("/register", get(handlers::get_registration::handler))
         .route("/register", post(handlers::register::handler))
+        .route("/register", delete(handlers::delete_registration::handler))
+        .route_layer(middleware::from_fn_with_state(
+            state_arc.clone(),
+            rate_limit::middleware,
+        ));
+
+    // The bodies are rejected before being buffered once over the limit
+    let message_body_limit = handlers::save_message::body_limit(config.message_max_size);
+    let batch_body_limit = handlers::save_message_batch::body_limit(config.message_max_size);
+
+    // The Relay retries the failed requests with the same signature
+    let relay_routes = Router::new()
+        .route(
+            "/messages",
+            post(handlers::save_message::handler)
+                .route_layer(DefaultBodyLimit::max(message_body_limit))
+                .route_layer(Extension(BodyLimit(message_body_limit))),
+        )
+        .route(
+            "/messages/batch",
+            post(handlers::save_message_batch::handler)
+                .route_layer(DefaultBodyLimit::max(batch_body_limit))
+                .route_layer(Extension(BodyLimit(batch_body_limit))),
+        )
+        .route("/subscriptions", post(handlers::subscriptions::handler))
+        .route_layer(middleware::from_fn_with_state(
+            state_arc.clone(),
+            relay::signature::release_failed_signatures,
+        ));
+
+    let app = Router::new()
+        .route("/health", get(handlers::health::handler))
+        .route("/health/ready", get(handlers::health::ready_handler))
+        .merge(relay_routes)
+        .merge(client_routes)
         .layer(global_middleware)
         .layer(cors)
         .with_state(state_arc.clone());
 
     let private_app = Router::new()
         .route("/metrics", get(handlers::metrics::handler))
-        .with_state(state_arc);
+        .with_state(state_arc.clone());
 
     let addr = SocketAddr::from(([0, 0, 0, 0], port));
     let private_addr = SocketAddr::from(([0, 0, 0, 0], private_port));
 
+    // Both servers stop accepting connections once the signal is received
+    let shutdown = {
+        let state = state_arc.clone();
+        async move {
+            let _ = shutdown.recv().await;
+            info!("Shutdown signal received, draining the in-flight requests");
+            state.start_shutdown();
+        }
+        .shared()
+    };
+    let shutdown_timeout = Duration::from_secs(config.shutdown_timeout);
+
+    let public_server = axum::Server::bind(&addr)
+        .serve(app.into_make_service_with_connect_info::<SocketAddr>())
+        .with_graceful_shutdown(shutdown.clone());
+    let private_server = axum::Server::bind(&private_addr)
+        .serve(private_app.into_make_service())
+        .with_graceful_shutdown(shutdown.clone());
+
     select! {
-        _ = axum::Server::bind(&addr).serve(app.into_make_service()) => info!("Server terminating"),
-        _ = axum::Server::bind(&private_addr).serve(private_app.into_make_service()) => info!("Internal Server terminating"),
-        _ = shutdown.recv() => info!("Shutdown signal received, killing servers"),
+        result = future::try_join(public_server, private_server) => match result {
+            Ok(_) => info!("Servers terminated"),
+            Err(e) => error!("Server terminating: {e}"),
+        },
+        _ = shutdown.then(|_| sleep(shutdown_timeout)) => {
+            warn!("Shutdown timeout elapsed, dropping the remaining requests")
+        }
+    }
+
+    reaper.abort();
+    invalidation_listener.abort();
+    if let Some(public_key_refresher) = public_key_refresher {
+        public_key_refresher.abort();
+    }
+    // The aborted tasks release their store connections once dropped
+    let _ = reaper.await;
+    let _ = invalidation_listener.await;
+
+    // The requests still running past the timeout may hold connections
+    let close_stores = future::join(
+        state_arc.messages_store.close(),
+        state_arc.registration_store.close(),
+    );
+    match timeout(shutdown_timeout, close_stores).await {
+        Ok(_) => info!("Stores closed"),
+        Err(_) => warn!("Timed out closing the stores"),
     }
 
     Ok(())
 }
+
+/// Connects to the storage backend selected in the configuration, along with
+/// its invalidation bus.
+async fn connect_stores(
+    config: &Configuration,
+) -> error::Result<(
+    MessagesStorageArc,
+    RegistrationStorageArc,
+    SignatureStorageArc,
+    InvalidationBusArc,
+)> {
+    let local_bus = || Arc::new(LocalInvalidationBus::new()) as InvalidationBusArc;
+    let storage_bus = config.registration_invalidation == InvalidationBackend::Storage;
+
+    Ok(match config.storage_backend {
+        StorageBackend::Mongo => {
+            let store = Arc::new(MongoStore::new(config).await?);
+            (
+                store.clone() as MessagesStorageArc,
+                store.clone() as RegistrationStorageArc,
+                store.clone() as SignatureStorageArc,
+                if storage_bus {
+                    store as InvalidationBusArc
+                } else {
+                    local_bus()
+                },
+            )
+        }
+        // A single instance owns the database file
+        StorageBackend::Sqlite => {
+            let store = Arc::new(SqliteStore::new(config).await?);
+            (
+                store.clone() as MessagesStorageArc,
+                store.clone() as RegistrationStorageArc,
+                store as SignatureStorageArc,
+                local_bus(),
+            )
+        }
+        StorageBackend::Postgres => {
+            let store = Arc::new(PostgresStore::new(config).await?);
+            (
+                store.clone() as MessagesStorageArc,
+                store.clone() as RegistrationStorageArc,
+                store.clone() as SignatureStorageArc,
+                if storage_bus {
+                    store as InvalidationBusArc
+                } else {
+                    local_bus()
+                },
+            )
+        }
+    })
+}
@@ -1,14 +1,19 @@
 use {
     dotenv::dotenv,
     gilgamesh::{config, error, log},
-    tokio::sync::broadcast,
+    tokio::{signal, sync::broadcast},
 };
 
 #[tokio::main]
 async fn main() -> error::Result<()> {
     let logger = log::Logger::init().expect("Failed to start logging");
 
-    let (_signal, shutdown) = broadcast::channel(1);
+    let (signal, shutdown) = broadcast::channel(1);
+    tokio::spawn(async move {
+        shutdown_signal().await;
+        let _ = signal.send(());
+    });
+
     dotenv().ok();
     let config = config::get_config().expect(
         "Failed to load configuration, please ensure that all environment variables are defined.",
@@ -17,7 +22,30 @@ async fn main() -> error::Result<()> {
     let options = gilgamesh::Options::default();
     let result = gilgamesh::bootstrap(shutdown, config, options).await;
 
+    // Flushes the pending spans to the OTLP exporter
     logger.stop();
 
     result
 }
+
+/// Resolves on SIGINT, or SIGTERM on Unix.
+async fn shutdown_signal() {
+    let interrupt = async {
+        signal::ctrl_c().await.expect("Failed to listen for SIGINT");
+    };
+
+    #[cfg(unix)]
+    let terminate = async {
+        signal::unix::signal(signal::unix::SignalKind::terminate())
+            .expect("Failed to listen for SIGTERM")
+            .recv()
+            .await;
+    };
+    #[cfg(not(unix))]
+    let terminate = std::future::pending::<()>();
+
+    tokio::select! {
+        _ = interrupt => {},
+        _ = terminate => {},
+    }
+}
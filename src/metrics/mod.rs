@@ -19,16 +19,24 @@ pub struct Metrics {
 
     pub received_items: Counter<u64>,
     pub stored_items: Counter<u64>,
+    pub expired_items: Counter<u64>,
+    pub deleted_items: Counter<u64>,
 
     pub get_queries: Counter<u64>,
     pub served_items: Counter<u64>,
+    pub stream_subscriptions: Counter<u64>,
 
     pub register: Counter<u64>,
     pub registration_overwrite: Counter<u64>,
     pub registration_update: Counter<u64>,
+    pub unregister: Counter<u64>,
+    pub expired_registrations: Counter<u64>,
     pub cached_registrations: Counter<u64>,
     pub fetched_registrations: Counter<u64>,
     pub registration_cache_invalidation: Counter<u64>,
+
+    pub rate_limited_requests: Counter<u64>,
+    pub quota_exceeded_items: Counter<u64>,
 }
 
 impl Metrics {
@@ -61,6 +69,16 @@ impl Metrics {
             .with_description("The number of items actually stored")
             .init();
 
+        let expired_items = meter
+            .u64_counter("expired_items")
+            .with_description("The number of expired items deleted")
+            .init();
+
+        let deleted_items = meter
+            .u64_counter("deleted_items")
+            .with_description("The number of items deleted on clients request")
+            .init();
+
         let get_queries = meter
             .u64_counter("get_queries")
             .with_description("The number of items retrieval queries")
@@ -71,6 +89,11 @@ impl Metrics {
             .with_description("The number of messages served to clients")
             .init();
 
+        let stream_subscriptions = meter
+            .u64_counter("stream_subscriptions")
+            .with_description("The number of subscriptions to the messages stream")
+            .init();
+
         let register = meter
             .u64_counter("register")
             .with_description("The total number of calls to the register method")
@@ -86,6 +109,16 @@ impl Metrics {
             .with_description("The number of calls to the register method in update mode")
             .init();
 
+        let unregister = meter
+            .u64_counter("unregister")
+            .with_description("The number of calls to the unregister method")
+            .init();
+
+        let expired_registrations = meter
+            .u64_counter("expired_registrations")
+            .with_description("The number of expired registrations deleted")
+            .init();
+
         let cached_registrations = meter
             .u64_counter("cached_registrations")
             .with_description("The number of registrations retrieved from the in-memory cache")
@@ -101,18 +134,35 @@ impl Metrics {
             .with_description("The number of registrations cache invalidations")
             .init();
 
+        let rate_limited_requests = meter
+            .u64_counter("rate_limited_requests")
+            .with_description("The number of requests rejected by the rate limiter")
+            .init();
+
+        let quota_exceeded_items = meter
+            .u64_counter("quota_exceeded_items")
+            .with_description("The number of items rejected by the message quotas")
+            .init();
+
         Ok(Metrics {
             prometheus_exporter,
             received_items,
             stored_items,
+            expired_items,
+            deleted_items,
             get_queries,
             served_items,
+            stream_subscriptions,
             register,
             registration_overwrite,
             registration_update,
+            unregister,
+            expired_registrations,
             cached_registrations,
             fetched_registrations,
             registration_cache_invalidation,
+            rate_limited_requests,
+            quota_exceeded_items,
         })
     }
 
@@ -0,0 +1,244 @@
+use {
+    crate::{
+        auth::{AuthBearer, AuthClient},
+        config::Configuration,
+        error::{self, Error},
+        increment_counter,
+        state::AppState,
+    },
+    axum::{
+        extract::{ConnectInfo, State},
+        http::Request,
+        middleware::Next,
+        response::Response,
+    },
+    moka::future::Cache,
+    std::{
+        net::{IpAddr, Ipv4Addr, SocketAddr},
+        sync::{Arc, Mutex},
+        time::{Duration, Instant},
+    },
+};
+
+const X_FORWARDED_FOR: &str = "x-forwarded-for";
+
+/// The number of clients and IP addresses tracked at once, the least recently
+/// seen ones are forgotten first.
+const MAX_TRACKED_KEYS: u64 = 1024 * 1024;
+
+/// Who a request is counted against.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum RateLimitKey {
+    /// The client authenticated by the request's JWT.
+    Client(Arc<str>),
+    /// The source of the requests without a valid client JWT.
+    Ip(IpAddr),
+}
+
+#[derive(Debug)]
+struct TokenBucket {
+    tokens: f64,
+    updated_at: Instant,
+}
+
+/// Limits the requests of each key to `max_requests` per `interval`, with a
+/// token bucket refilled continuously so that the requests may come in bursts.
+#[derive(Clone)]
+pub struct RateLimiter {
+    max_requests: f64,
+    interval: Duration,
+    buckets: Cache<RateLimitKey, Arc<Mutex<TokenBucket>>>,
+}
+
+impl RateLimiter {
+    pub fn new(max_requests: u32, interval: Duration) -> Self {
+        RateLimiter {
+            max_requests: max_requests.into(),
+            interval,
+            // An idle bucket is full again after an interval anyway
+            buckets: Cache::builder()
+                .max_capacity(MAX_TRACKED_KEYS)
+                .time_to_idle(interval)
+                .build(),
+        }
+    }
+
+    /// Returns the `RATE_LIMIT_MAX_REQUESTS` limiter, `None` if unlimited.
+    pub fn from_config(config: &Configuration) -> Option<Self> {
+        config.rate_limit_max_requests.map(|max_requests| {
+            Self::new(
+                max_requests,
+                Duration::from_secs(config.rate_limit_interval),
+            )
+        })
+    }
+
+    /// Counts a request at `now`, returning how long to wait before the next
+    /// one is allowed when over the limit.
+    pub async fn check(&self, key: RateLimitKey, now: Instant) -> Result<(), Duration> {
+        let bucket = self
+            .buckets
+            .get_with(key, async {
+                Arc::new(Mutex::new(TokenBucket {
+                    tokens: self.max_requests,
+                    updated_at: now,
+                }))
+            })
+            .await;
+        let mut bucket = bucket.lock().unwrap_or_else(|e| e.into_inner());
+
+        let refill_rate = self.max_requests / self.interval.as_secs_f64();
+        let elapsed = now.saturating_duration_since(bucket.updated_at);
+        bucket.tokens =
+            (bucket.tokens + elapsed.as_secs_f64() * refill_rate).min(self.max_requests);
+        bucket.updated_at = now;
+
+        if bucket.tokens >= 1.0 {
+            bucket.tokens -= 1.0;
+            Ok(())
+        } else {
+            Err(Duration::from_secs_f64((1.0 - bucket.tokens) / refill_rate))
+        }
+    }
+}
+
+/// Rejects the requests over the rate limit of their client, or of their IP
+/// address when they don't carry a valid client JWT.
+pub async fn middleware<B>(
+    State(state): State<Arc<AppState>>,
+    mut request: Request<B>,
+    next: Next<B>,
+) -> error::Result<Response> {
+    if let Some(rate_limiter) = &state.rate_limiter {
+        // Only verified clients are trusted, others could exhaust anyone's limit
+        let client_id =
+            AuthBearer::token(&request).and_then(|token| state.jwt_policy.verify(token).ok());
+
+        let key = match &client_id {
+            Some(client_id) => RateLimitKey::Client(client_id.clone().into_value()),
+            None => RateLimitKey::Ip(client_ip(&request, state.config.trusted_proxy_hops)),
+        };
+        if let Err(retry_after) = rate_limiter.check(key, Instant::now()).await {
+            increment_counter!(state.metrics, rate_limited_requests);
+            return Err(Error::RateLimited(retry_after));
+        }
+
+        // The handlers don't verify the JWT again
+        if let Some(client_id) = client_id {
+            request.extensions_mut().insert(AuthClient(client_id));
+        }
+    }
+
+    Ok(next.run(request).await)
+}
+
+/// Returns the address of the request's client. Each of the `trusted_hops`
+/// proxies in front of the server appends the address it received the request
+/// from to `X-Forwarded-For`, the ones before are set by the client.
+fn client_ip<B>(request: &Request<B>, trusted_hops: usize) -> IpAddr {
+    let connection_ip = request
+        .extensions()
+        .get::<ConnectInfo<SocketAddr>>()
+        .map(|ConnectInfo(addr)| addr.ip())
+        .unwrap_or(IpAddr::V4(Ipv4Addr::UNSPECIFIED));
+    if trusted_hops == 0 {
+        return connection_ip;
+    }
+
+    let forwarded: Vec<&str> = request
+        .headers()
+        .get_all(X_FORWARDED_FOR)
+        .iter()
+        .filter_map(|header| header.to_str().ok())
+        .flat_map(|header| header.split(','))
+        .map(str::trim)
+        .collect();
+
+    forwarded
+        .len()
+        .checked_sub(trusted_hops)
+        .and_then(|index| forwarded[index].parse().ok())
+        .unwrap_or(connection_ip)
+}
+
+#[cfg(test)]
+mod test_rate_limiter {
+    use super::*;
+
+    fn key(client_id: &str) -> RateLimitKey {
+        RateLimitKey::Client(Arc::from(client_id))
+    }
+
+    #[tokio::test]
+    async fn test_burst() {
+        let rate_limiter = RateLimiter::new(3, Duration::from_secs(60));
+        let now = Instant::now();
+
+        for _ in 0..3 {
+            assert!(rate_limiter.check(key("a"), now).await.is_ok());
+        }
+        assert_eq!(
+            rate_limiter.check(key("a"), now).await,
+            Err(Duration::from_secs(20))
+        );
+
+        // The other clients have their own limit
+        assert!(rate_limiter.check(key("b"), now).await.is_ok());
+    }
+
+    fn forwarded_request(forwarded_for: &[&str]) -> Request<()> {
+        let mut request = Request::new(());
+        for header in forwarded_for {
+            request
+                .headers_mut()
+                .append(X_FORWARDED_FOR, header.parse().unwrap());
+        }
+        request
+            .extensions_mut()
+            .insert(ConnectInfo(SocketAddr::from(([10, 0, 0, 1], 443))));
+        request
+    }
+
+    #[test]
+    fn test_client_ip() {
+        let proxy = IpAddr::from([10, 0, 0, 1]);
+        let client = IpAddr::from([203, 0, 113, 7]);
+
+        // Not behind a proxy, the header is set by the client
+        let request = forwarded_request(&["203.0.113.7"]);
+        assert_eq!(client_ip(&request, 0), proxy);
+
+        // The client can't spoof the address appended by the trusted proxy
+        let request = forwarded_request(&["198.51.100.1, 203.0.113.7"]);
+        assert_eq!(client_ip(&request, 1), client);
+        let request = forwarded_request(&["198.51.100.1", "203.0.113.7"]);
+        assert_eq!(client_ip(&request, 1), client);
+        let request = forwarded_request(&["203.0.113.7, 192.0.2.1"]);
+        assert_eq!(client_ip(&request, 2), client);
+
+        // Without the proxy's address, the connection's is used
+        assert_eq!(client_ip(&forwarded_request(&[]), 1), proxy);
+        assert_eq!(client_ip(&forwarded_request(&["203.0.113.7"]), 2), proxy);
+        assert_eq!(client_ip(&forwarded_request(&["unknown"]), 1), proxy);
+    }
+
+    #[tokio::test]
+    async fn test_refill() {
+        let rate_limiter = RateLimiter::new(2, Duration::from_secs(60));
+        let now = Instant::now();
+
+        assert!(rate_limiter.check(key("a"), now).await.is_ok());
+        assert!(rate_limiter.check(key("a"), now).await.is_ok());
+        assert!(rate_limiter.check(key("a"), now).await.is_err());
+
+        let later = now + Duration::from_secs(30);
+        assert!(rate_limiter.check(key("a"), later).await.is_ok());
+        assert!(rate_limiter.check(key("a"), later).await.is_err());
+
+        // The bucket doesn't fill past its capacity
+        let much_later = now + Duration::from_secs(3600);
+        assert!(rate_limiter.check(key("a"), much_later).await.is_ok());
+        assert!(rate_limiter.check(key("a"), much_later).await.is_ok());
+        assert!(rate_limiter.check(key("a"), much_later).await.is_err());
+    }
+}
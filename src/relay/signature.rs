@@ -1,25 +1,69 @@
 use {
     crate::{
-        error::Error::{
-            FromRequestError,
-            MissingAllSignatureHeader,
-            MissingSignatureHeader,
-            MissingTimestampHeader,
-            ToBytesError,
+        error::{
+            Error,
+            Error::{
+                FromRequestError,
+                InvalidSignature,
+                MissingAllSignatureHeader,
+                MissingSignatureHeader,
+                MissingTimestampHeader,
+                PayloadTooLarge,
+                ReplayedSignature,
+                StaleSignature,
+                ToBytesError,
+            },
         },
         log::prelude::*,
-        state::State,
+        relay::{RelayClient, RelayClients},
+        state::{AppState, State},
     },
     async_trait::async_trait,
-    axum::{body, extract::FromRequest, http::Request},
+    axum::{
+        body,
+        extract::{self, FromRequest},
+        http::{Request, StatusCode},
+        middleware::Next,
+        response::{IntoResponse, Response},
+        BoxError,
+    },
+    chrono::{DateTime, Utc},
     ed25519_dalek::{PublicKey, Signature, Verifier},
+    http_body::{LengthLimitError, Limited},
+    std::sync::{Arc, Mutex},
     tracing::span,
 };
 
 pub const SIGNATURE_HEADER_NAME: &str = "X-Ed25519-Signature";
 pub const TIMESTAMP_HEADER_NAME: &str = "X-Ed25519-Timestamp";
 
-pub struct RequireValidSignature<T>(pub T);
+/// The limit axum applies to the bodies by default.
+const DEFAULT_BODY_LIMIT: usize = 2 * 1024 * 1024;
+
+/// The maximum size of the route's bodies, set as an extension along with
+/// `DefaultBodyLimit` since the signed bodies are buffered before the inner
+/// extractor runs.
+#[derive(Clone, Copy, Debug)]
+pub struct BodyLimit(pub usize);
+
+/// The signature recorded for the request, set by `RequireValidSignature` for
+/// `release_failed_signatures`.
+#[derive(Clone, Default)]
+pub struct RecordedSignature(Arc<Mutex<Option<String>>>);
+
+impl RecordedSignature {
+    fn set(&self, signature: String) {
+        *self.0.lock().unwrap_or_else(|e| e.into_inner()) = Some(signature);
+    }
+
+    fn take(&self) -> Option<String> {
+        self.0.lock().unwrap_or_else(|e| e.into_inner()).take()
+    }
+}
+
+/// Extracts `T` from a body signed by one of the relays, along with the relay
+/// unless the signatures aren't validated.
+pub struct RequireValidSignature<T>(pub T, pub Option<RelayClient>);
 
 #[async_trait]
 impl<S, B, T> FromRequest<S, B> for RequireValidSignature<T>
@@ -28,30 +72,30 @@ where
     // `async_trait`
     B: Send + 'static + body::HttpBody + From<body::Bytes>,
     B::Data: Send,
+    B::Error: Into<BoxError>,
     S: Send + Sync + State,
     T: FromRequest<S, B>,
 {
     type Rejection = crate::error::Error;
 
     async fn from_request(req: Request<B>, state: &S) -> Result<Self, Self::Rejection> {
+        let body_limit = req
+            .extensions()
+            .get::<BodyLimit>()
+            .map_or(DEFAULT_BODY_LIMIT, |body_limit| body_limit.0);
+
         if !state.validate_signatures() {
             // Skip signature validation
             return T::from_request(req, state)
                 .await
-                .map(Self)
-                .map_err(|_| FromRequestError);
+                .map(|inner| Self(inner, None))
+                .map_err(|rejection| rejection_error(rejection, body_limit));
         }
 
         let s = span!(tracing::Level::DEBUG, "validate_signature");
         let _ = s.enter();
 
-        let public_key = state.relay_client().public_key().await?;
-
         let (parts, body_raw) = req.into_parts();
-        let bytes = hyper::body::to_bytes(body_raw)
-            .await
-            .map_err(|_| ToBytesError)?;
-        let body = String::from_utf8_lossy(&bytes);
 
         let signature_header = parts
             .headers
@@ -63,29 +107,157 @@ where
             .get(TIMESTAMP_HEADER_NAME)
             .and_then(|header| header.to_str().ok());
 
-        match (signature_header, timestamp_header) {
-            (Some(signature), Some(timestamp)) => {
-                match signature_is_valid(signature, timestamp, &body, &public_key).await {
-                    Ok(_) => {
-                        let req = Request::<B>::from_parts(parts, bytes.into());
-                        Ok(T::from_request(req, state)
-                            .await
-                            .map(Self)
-                            .map_err(|_| FromRequestError)?)
-                    }
-                    Err(err) => {
-                        warn!("relay signature is not valid: {err:?}");
-                        Err(err)
-                    }
+        let (signature, timestamp) = match (signature_header, timestamp_header) {
+            (Some(signature), Some(timestamp)) => (signature, timestamp),
+            (Some(_), None) => return Err(MissingTimestampHeader),
+            (None, Some(_)) => return Err(MissingSignatureHeader),
+            (None, None) => return Err(MissingAllSignatureHeader),
+        };
+
+        // Checked first so the stale requests can't trigger key refreshes
+        if !timestamp_is_fresh(timestamp, state.signature_max_age(), Utc::now()) {
+            warn!("relay signature timestamp is stale: {timestamp}");
+            return Err(StaleSignature);
+        }
+
+        let bytes = hyper::body::to_bytes(Limited::new(body_raw, body_limit))
+            .await
+            .map_err(|e| {
+                if e.is::<LengthLimitError>() {
+                    PayloadTooLarge(body_limit)
+                } else {
+                    ToBytesError
                 }
+            })?;
+        let body = String::from_utf8_lossy(&bytes);
+
+        // Only the configured relays' keys are tried, nothing is looked up
+        // for the unauthenticated body
+        let relay_client = match signing_relay(&state.relays(), signature, timestamp, &body).await {
+            Ok(relay_client) => relay_client,
+            Err(err) => {
+                warn!("relay signature is not valid: {err:?}");
+                return Err(err);
             }
-            (Some(_), None) => Err(MissingTimestampHeader),
-            (None, Some(_)) => Err(MissingSignatureHeader),
-            (None, None) => Err(MissingAllSignatureHeader),
+        };
+
+        // Recorded in the store so the other instances reject the replays too,
+        // until the timestamp is stale anyway
+        let signature = signature.to_lowercase();
+        let max_age = chrono::Duration::seconds(2 * state.signature_max_age() as i64);
+        if !state
+            .signature_store()
+            .record_signature(&signature, Utc::now() + max_age)
+            .await?
+        {
+            warn!("relay signature has already been received");
+            return Err(ReplayedSignature);
+        }
+        if let Some(recorded) = parts.extensions.get::<RecordedSignature>() {
+            recorded.set(signature);
         }
+
+        state.authorize_signing_relay(&relay_client, &bytes).await?;
+
+        let req = Request::<B>::from_parts(parts, bytes.into());
+        T::from_request(req, state)
+            .await
+            .map(|inner| Self(inner, Some(relay_client)))
+            .map_err(|rejection| rejection_error(rejection, body_limit))
     }
 }
 
+/// Forgets the signature of the requests that failed, e.g. over a quota or on
+/// a store error, so that the Relay can retry them. Their replays are only
+/// rejected once they succeed.
+pub async fn release_failed_signatures<B>(
+    extract::State(state): extract::State<Arc<AppState>>,
+    mut request: Request<B>,
+    next: Next<B>,
+) -> Response {
+    let recorded = RecordedSignature::default();
+    request.extensions_mut().insert(recorded.clone());
+
+    let response = next.run(request).await;
+    if !response.status().is_success() {
+        if let Some(signature) = recorded.take() {
+            if let Err(e) = state.signature_store.delete_signature(&signature).await {
+                warn!("Failed to release the relay signature of a failed request: {e:?}");
+            }
+        }
+    }
+
+    response
+}
+
+/// Maps the inner extractor's rejection, keeping the bodies over the limit
+/// apart from the malformed ones.
+fn rejection_error(rejection: impl IntoResponse, body_limit: usize) -> Error {
+    if rejection.into_response().status() == StatusCode::PAYLOAD_TOO_LARGE {
+        PayloadTooLarge(body_limit)
+    } else {
+        FromRequestError
+    }
+}
+
+/// Returns the relay whose public key verifies the signature, trying the
+/// cached keys before refreshing them in case a relay rotated its key.
+async fn signing_relay(
+    relays: &RelayClients,
+    signature: &str,
+    timestamp: &str,
+    body: &str,
+) -> Result<RelayClient, Error> {
+    let mut cached_keys = Vec::new();
+    let mut key_error = None;
+    for relay_client in relays.iter() {
+        let public_key = match relay_client.public_key().await {
+            Ok(public_key) => public_key,
+            Err(e) => {
+                warn!(
+                    "Failed to load the Public Key of {}: {:?}",
+                    relay_client.base_url(),
+                    e
+                );
+                key_error = Some(e);
+                continue;
+            }
+        };
+
+        if signature_is_valid(signature, timestamp, body, &public_key).await? {
+            return Ok(relay_client.clone());
+        }
+        cached_keys.push((relay_client, public_key));
+    }
+
+    debug!("relay signature is not valid with the cached keys, refreshing them");
+    for (relay_client, stale) in cached_keys {
+        let public_key = match relay_client.refresh_public_key(stale).await {
+            Ok(public_key) => public_key,
+            Err(e) => {
+                key_error = Some(e);
+                continue;
+            }
+        };
+
+        if signature_is_valid(signature, timestamp, body, &public_key).await? {
+            return Ok(relay_client.clone());
+        }
+    }
+
+    // The signature may come from the relay whose key couldn't be loaded
+    Err(key_error.unwrap_or(InvalidSignature))
+}
+
+/// Checks that the timestamp, in seconds since Epoch, is at most `max_age`
+/// seconds away from `now`.
+pub fn timestamp_is_fresh(timestamp: &str, max_age: u64, now: DateTime<Utc>) -> bool {
+    timestamp
+        .parse::<i64>()
+        .map(|timestamp| now.timestamp().abs_diff(timestamp) <= max_age)
+        .unwrap_or(false)
+}
+
 pub async fn signature_is_valid(
     signature: &str,
     timestamp: &str,
@@ -99,3 +271,36 @@ pub async fn signature_is_valid(
 
     Ok(public_key.verify(sig_body.as_bytes(), &sig).is_ok())
 }
+
+#[cfg(test)]
+mod test_replay_protection {
+    use super::*;
+
+    #[test]
+    fn test_fresh_timestamp() {
+        let now = Utc::now();
+        let timestamp = now.timestamp();
+
+        assert!(timestamp_is_fresh(&timestamp.to_string(), 300, now));
+        assert!(timestamp_is_fresh(&(timestamp - 300).to_string(), 300, now));
+        assert!(timestamp_is_fresh(&(timestamp + 300).to_string(), 300, now));
+    }
+
+    #[test]
+    fn test_stale_timestamp() {
+        let now = Utc::now();
+        let timestamp = now.timestamp();
+
+        assert!(!timestamp_is_fresh(
+            &(timestamp - 301).to_string(),
+            300,
+            now
+        ));
+        assert!(!timestamp_is_fresh(
+            &(timestamp + 301).to_string(),
+            300,
+            now
+        ));
+        assert!(!timestamp_is_fresh("yesterday", 300, now));
+    }
+}
@@ -0,0 +1,202 @@
+use {
+    crate::{
+        config::Configuration,
+        error::{Error, Result},
+        increment_counter_with,
+        log::prelude::*,
+        state::AppState,
+        tags::TagPattern,
+    },
+    chrono::{DateTime, Utc},
+    std::{sync::Arc, time::Duration},
+};
+
+const TAG_MAX_AGE_SEPARATOR: char = ':';
+
+/// How long messages and registrations are kept before expiring. Messages
+/// expire by their tag rather than by registration: several clients may
+/// archive the same message, and the tags already tell the kind of message.
+#[derive(Debug, Clone, Default, Eq, PartialEq)]
+pub struct RetentionPolicy {
+    /// The max age applied to messages not matching any tag rule.
+    pub max_age: Option<Duration>,
+    /// The max age of messages whose tag matches the pattern, the first
+    /// matching rule wins over the global max age.
+    pub tag_max_ages: Vec<(TagPattern, Duration)>,
+    /// The max age of registrations, since the client last registered.
+    pub registration_max_age: Option<Duration>,
+}
+
+impl RetentionPolicy {
+    pub fn from_config(config: &Configuration) -> Result<Self> {
+        let tag_max_ages = config
+            .message_tag_max_ages
+            .iter()
+            .map(|rule| parse_tag_max_age(rule))
+            .collect::<Result<_>>()?;
+
+        Ok(RetentionPolicy {
+            max_age: config.message_max_age.map(Duration::from_secs),
+            tag_max_ages,
+            registration_max_age: config.registration_max_age.map(Duration::from_secs),
+        })
+    }
+
+    /// Returns the max age of a message with the given tag, if any.
+    pub fn max_age(&self, tag: u32) -> Option<Duration> {
+        self.tag_max_ages
+            .iter()
+            .find(|(pattern, _)| pattern.matches(tag))
+            .map(|(_, max_age)| *max_age)
+            .or(self.max_age)
+    }
+
+    /// Returns when a message with the given tag stored at `now` expires.
+    pub fn expires_at(&self, tag: u32, now: DateTime<Utc>) -> Option<DateTime<Utc>> {
+        let max_age = chrono::Duration::from_std(self.max_age(tag)?).ok()?;
+        now.checked_add_signed(max_age)
+    }
+
+    /// Returns when a registration stored at `now` expires.
+    pub fn registration_expires_at(&self, now: DateTime<Utc>) -> Option<DateTime<Utc>> {
+        let max_age = chrono::Duration::from_std(self.registration_max_age?).ok()?;
+        now.checked_add_signed(max_age)
+    }
+}
+
+/// Parses a `<pattern>:<seconds>` rule, e.g. `40*:3600`.
+fn parse_tag_max_age(rule: &str) -> Result<(TagPattern, Duration)> {
+    let invalid = || {
+        Error::InvalidConfiguration(format!(
+            "`{rule}` is not a valid tag max age, expected `<pattern>:<seconds>`"
+        ))
+    };
+
+    let (pattern, seconds) = rule.split_once(TAG_MAX_AGE_SEPARATOR).ok_or_else(invalid)?;
+    let pattern = pattern.parse::<TagPattern>().map_err(|e| {
+        Error::InvalidConfiguration(format!("`{rule}` has an invalid tag pattern: {e}"))
+    })?;
+    let seconds = seconds.parse::<u64>().map_err(|_| invalid())?;
+
+    Ok((pattern, Duration::from_secs(seconds)))
+}
+
+/// Periodically deletes the expired messages and registrations, runs until
+/// aborted.
+pub async fn reaper(state: Arc<AppState>) {
+    let mut interval =
+        tokio::time::interval(Duration::from_secs(state.config.message_expiry_interval));
+
+    loop {
+        interval.tick().await;
+
+        match state
+            .messages_store
+            .delete_expired_messages(Utc::now())
+            .await
+        {
+            Ok(0) => {}
+            Ok(count) => {
+                debug!("deleted {} expired messages", count);
+                increment_counter_with!(state.metrics, expired_items, count);
+            }
+            Err(e) => warn!("Failed to delete expired messages: {:?}", e),
+        }
+
+        match state
+            .signature_store
+            .delete_expired_signatures(Utc::now())
+            .await
+        {
+            Ok(0) => {}
+            Ok(count) => debug!("deleted {} expired relay signatures", count),
+            Err(e) => warn!("Failed to delete expired relay signatures: {:?}", e),
+        }
+
+        // The expired registrations are already hidden, cached ones included
+        match state
+            .registration_store
+            .delete_expired_registrations(Utc::now())
+            .await
+        {
+            Ok(0) => {}
+            Ok(count) => {
+                debug!("deleted {} expired registrations", count);
+                increment_counter_with!(state.metrics, expired_registrations, count);
+            }
+            Err(e) => warn!("Failed to delete expired registrations: {:?}", e),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test_retention_policy {
+    use super::*;
+
+    fn policy(max_age: Option<u64>, rules: &[&str]) -> RetentionPolicy {
+        RetentionPolicy {
+            max_age: max_age.map(Duration::from_secs),
+            tag_max_ages: rules
+                .iter()
+                .map(|r| parse_tag_max_age(r).unwrap())
+                .collect(),
+            registration_max_age: None,
+        }
+    }
+
+    #[test]
+    fn test_no_retention() {
+        assert_eq!(policy(None, &[]).max_age(1234), None);
+        assert_eq!(policy(None, &[]).expires_at(1234, Utc::now()), None);
+    }
+
+    #[test]
+    fn test_global_max_age() {
+        let now = Utc::now();
+        let policy = policy(Some(60), &["4000:10"]);
+
+        assert_eq!(policy.max_age(1234), Some(Duration::from_secs(60)));
+        assert_eq!(
+            policy.expires_at(1234, now),
+            Some(now + chrono::Duration::seconds(60))
+        );
+    }
+
+    #[test]
+    fn test_tag_max_age() {
+        let policy = policy(Some(60), &["400*:10", "40**:20"]);
+
+        assert_eq!(policy.max_age(4001), Some(Duration::from_secs(10)));
+        assert_eq!(policy.max_age(4010), Some(Duration::from_secs(20)));
+        assert_eq!(policy.max_age(5000), Some(Duration::from_secs(60)));
+    }
+
+    #[test]
+    fn test_tag_range_max_age() {
+        let policy = policy(Some(60), &["4000-4999:10"]);
+
+        assert_eq!(policy.max_age(4500), Some(Duration::from_secs(10)));
+        assert_eq!(policy.max_age(5000), Some(Duration::from_secs(60)));
+    }
+
+    #[test]
+    fn test_registration_max_age() {
+        let now = Utc::now();
+        let mut policy = policy(Some(60), &[]);
+        assert_eq!(policy.registration_expires_at(now), None);
+
+        policy.registration_max_age = Some(Duration::from_secs(3600));
+        assert_eq!(
+            policy.registration_expires_at(now),
+            Some(now + chrono::Duration::seconds(3600))
+        );
+    }
+
+    #[test]
+    fn test_invalid_rules() {
+        assert!(parse_tag_max_age("4000").is_err());
+        assert!(parse_tag_max_age(":60").is_err());
+        assert!(parse_tag_max_age("4000:soon").is_err());
+        assert!(parse_tag_max_age("40a0:60").is_err());
+    }
+}
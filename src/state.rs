@@ -1,31 +1,198 @@
 use {
     crate::{
+        auth::jwt::JwtPolicy,
+        cursor::CursorCodec,
         error,
+        handlers::save_message::load_registration,
+        invalidation::InvalidationBus,
+        log::prelude::*,
         metrics::Metrics,
-        relay::RelayClient,
-        store::{messages::MessagesStore, registrations::RegistrationStore},
+        rate_limit::RateLimiter,
+        relay::{RelayClient, RelayClients},
+        retention::RetentionPolicy,
+        store::{
+            messages::{Message, MessagesStore},
+            registrations::RegistrationStore,
+            signatures::SignatureStore,
+        },
+        tags::TagMatcher,
         Configuration,
     },
+    async_trait::async_trait,
     build_info::BuildInfo,
+    chrono::{DateTime, Utc},
     moka::future::Cache,
-    std::{collections::HashSet, sync::Arc, time::Duration},
+    serde::Deserialize,
+    std::{
+        future::Future,
+        sync::{
+            atomic::{AtomicU64, Ordering},
+            Arc,
+        },
+        time::Duration,
+    },
+    tokio::sync::{broadcast, watch},
 };
 
+/// The number of archived messages kept for slow stream subscribers before
+/// they lag behind.
+const MESSAGE_EVENTS_CAPACITY: usize = 1024;
+
+/// How long the invalidations are remembered, longer than any registration
+/// load. A load that outlasts it may cache a stale registration until the
+/// entry expires.
+const INVALIDATIONS_TTL: Duration = Duration::from_secs(5 * 60);
+
 pub type MessagesStorageArc = Arc<dyn MessagesStore + Send + Sync + 'static>;
 pub type RegistrationStorageArc = Arc<dyn RegistrationStore + Send + Sync + 'static>;
+pub type SignatureStorageArc = Arc<dyn SignatureStore + Send + Sync + 'static>;
+pub type InvalidationBusArc = Arc<dyn InvalidationBus + Send + Sync + 'static>;
 
 #[derive(Clone)]
 pub struct CachedRegistration {
     pub tags: Vec<Arc<str>>,
     pub relay_url: Arc<str>,
+    /// The tags compiled once for all the ingested messages.
+    pub matcher: Arc<TagMatcher>,
+    pub expires_at: Option<DateTime<Utc>>,
+}
+
+impl CachedRegistration {
+    pub fn new(
+        tags: Vec<Arc<str>>,
+        relay_url: Arc<str>,
+        expires_at: Option<DateTime<Utc>>,
+    ) -> Self {
+        let matcher = Arc::new(TagMatcher::new(tags.iter().map(AsRef::as_ref)));
+
+        CachedRegistration {
+            tags,
+            relay_url,
+            matcher,
+            expires_at,
+        }
+    }
+
+    /// Checks whether the registration has expired at `now`.
+    pub fn is_expired(&self, now: DateTime<Utc>) -> bool {
+        self.expires_at.is_some_and(|expires_at| expires_at <= now)
+    }
+}
+
+/// The registrations cached by this instance, evicted when they change on
+/// any instance.
+#[derive(Clone)]
+pub struct RegistrationCache {
+    registrations: Cache<Arc<str>, CachedRegistration>,
+    /// The version of the last invalidation of the recently changed
+    /// registrations.
+    invalidations: Cache<Arc<str>, u64>,
+    /// The version of the last invalidation of every registration.
+    all_invalidated: Arc<AtomicU64>,
+    /// Incremented by every invalidation.
+    version: Arc<AtomicU64>,
+}
+
+impl RegistrationCache {
+    pub fn new() -> Self {
+        let registrations = Cache::builder()
+            .weigher(|_key, value: &CachedRegistration| -> u32 {
+                // The matcher holds about as much as the tags
+                value.relay_url.len().try_into().unwrap_or(u32::MAX)
+                    + 2 * value
+                        .tags
+                        .iter()
+                        .fold(0, |acc, tag| acc + (tag.len() as u32))
+            })
+            .max_capacity(32 * 1024 * 1024)
+            .time_to_live(Duration::from_secs(30 * 60))
+            .time_to_idle(Duration::from_secs(5 * 60))
+            .build();
+
+        RegistrationCache {
+            registrations,
+            invalidations: Cache::builder()
+                .max_capacity(1024 * 1024)
+                .time_to_live(INVALIDATIONS_TTL)
+                .build(),
+            all_invalidated: Arc::new(AtomicU64::new(0)),
+            version: Arc::new(AtomicU64::new(0)),
+        }
+    }
+
+    /// Returns the current version, to be read before loading a registration
+    /// from the store.
+    pub fn version(&self) -> u64 {
+        self.version.load(Ordering::SeqCst)
+    }
+
+    pub fn get(&self, client_id: &str) -> Option<CachedRegistration> {
+        self.registrations.get(client_id)
+    }
+
+    /// Caches the registration loaded at `version`, unless it was invalidated
+    /// since as the store may have returned it before the change.
+    pub async fn insert(
+        &self,
+        client_id: Arc<str>,
+        registration: CachedRegistration,
+        version: u64,
+    ) {
+        self.registrations
+            .insert(client_id.clone(), registration)
+            .await;
+
+        // Checked once inserted, the later invalidations evict it themselves
+        if self.is_invalidated_since(&client_id, version) {
+            self.registrations.invalidate(&client_id).await;
+        }
+    }
+
+    pub async fn invalidate(&self, client_id: &str) {
+        let version = self.version.fetch_add(1, Ordering::SeqCst) + 1;
+        self.invalidations
+            .insert(Arc::from(client_id), version)
+            .await;
+        self.registrations.invalidate(client_id).await;
+    }
+
+    pub fn invalidate_all(&self) {
+        let version = self.version.fetch_add(1, Ordering::SeqCst) + 1;
+        self.all_invalidated.fetch_max(version, Ordering::SeqCst);
+        self.registrations.invalidate_all();
+    }
+
+    fn is_invalidated_since(&self, client_id: &str, version: u64) -> bool {
+        self.all_invalidated.load(Ordering::SeqCst) > version
+            || self
+                .invalidations
+                .get(client_id)
+                .is_some_and(|invalidated| invalidated > version)
+    }
 }
 
+impl Default for RegistrationCache {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[async_trait]
 pub trait State {
     fn config(&self) -> Configuration;
     fn build_info(&self) -> BuildInfo;
     fn messages_store(&self) -> MessagesStorageArc;
-    fn relay_client(&self) -> RelayClient;
+    fn signature_store(&self) -> SignatureStorageArc;
+    fn relays(&self) -> RelayClients;
+    /// Checks that the request's clients registered with the relay whose
+    /// signature was verified.
+    async fn authorize_signing_relay(
+        &self,
+        relay_client: &RelayClient,
+        body: &[u8],
+    ) -> error::Result<()>;
     fn validate_signatures(&self) -> bool;
+    fn signature_max_age(&self) -> u64;
 }
 
 #[derive(Clone)]
@@ -35,9 +202,23 @@ pub struct AppState {
     pub metrics: Option<Metrics>,
     pub messages_store: MessagesStorageArc,
     pub registration_store: RegistrationStorageArc,
-    pub registration_cache: Cache<Arc<str>, CachedRegistration>,
-    pub relay_client: RelayClient,
-    pub auth_aud: HashSet<String>,
+    pub registration_cache: RegistrationCache,
+    /// The signatures of the accepted Relay requests, to reject their replays.
+    pub signature_store: SignatureStorageArc,
+    /// Evicts the changed registrations from every instance's cache.
+    pub invalidation_bus: InvalidationBusArc,
+    /// The relays clients may register with.
+    pub relays: RelayClients,
+    /// The newly archived messages, streamed to the subscribed clients.
+    pub message_events: broadcast::Sender<Message>,
+    pub retention: RetentionPolicy,
+    pub cursors: CursorCodec,
+    /// The client JWTs accepted by the client facing endpoints.
+    pub jwt_policy: JwtPolicy,
+    /// Throttles the client facing endpoints, unlimited if unset.
+    pub rate_limiter: Option<RateLimiter>,
+    /// Set once the server starts shutting down.
+    shutdown: Arc<watch::Sender<bool>>,
 }
 
 build_info::build_info!(fn build_info);
@@ -47,23 +228,16 @@ impl AppState {
         config: Configuration,
         messages_store: MessagesStorageArc,
         registration_store: RegistrationStorageArc,
+        signature_store: SignatureStorageArc,
+        invalidation_bus: InvalidationBusArc,
     ) -> error::Result<AppState> {
         let build_info: &BuildInfo = build_info();
 
-        let relay_url = config.relay_url.to_string();
-
-        let registration_cache = Cache::builder()
-            .weigher(|_key, value: &CachedRegistration| -> u32 {
-                value.relay_url.len().try_into().unwrap_or(u32::MAX)
-                    + value
-                        .tags
-                        .iter()
-                        .fold(0, |acc, tag| acc + (tag.len() as u32))
-            })
-            .max_capacity(32 * 1024 * 1024)
-            .time_to_live(Duration::from_secs(30 * 60))
-            .time_to_idle(Duration::from_secs(5 * 60))
-            .build();
+        let relays = RelayClients::from_config(&config)?;
+        let retention = RetentionPolicy::from_config(&config)?;
+        let cursors = CursorCodec::from_config(&config)?;
+        let jwt_policy = JwtPolicy::from_config(&config);
+        let rate_limiter = RateLimiter::from_config(&config);
 
         Ok(AppState {
             config,
@@ -71,21 +245,86 @@ impl AppState {
             metrics: None,
             messages_store,
             registration_store,
-            registration_cache,
-            relay_client: RelayClient::new(relay_url),
-            auth_aud: [
-                "wss://relay.walletconnect.com".to_owned(),
-                "https://history.walletconnect.com".to_owned(),
-            ]
-            .into(),
+            registration_cache: RegistrationCache::new(),
+            signature_store,
+            invalidation_bus,
+            relays,
+            message_events: broadcast::channel(MESSAGE_EVENTS_CAPACITY).0,
+            retention,
+            cursors,
+            jwt_policy,
+            rate_limiter,
+            shutdown: Arc::new(watch::channel(false).0),
         })
     }
 
     pub fn set_metrics(&mut self, metrics: Metrics) {
         self.metrics = Some(metrics);
     }
+
+    /// Notifies the long running requests, e.g. the message streams, that the
+    /// server is shutting down.
+    pub fn start_shutdown(&self) {
+        self.shutdown.send_replace(true);
+    }
+
+    /// Whether the server started shutting down.
+    pub fn is_shutting_down(&self) -> bool {
+        *self.shutdown.borrow()
+    }
+
+    /// Resolves once the server starts shutting down.
+    pub fn shutdown_started(&self) -> impl Future<Output = ()> + Send + 'static {
+        let mut shutdown = self.shutdown.subscribe();
+        async move {
+            while !*shutdown.borrow() {
+                if shutdown.changed().await.is_err() {
+                    return;
+                }
+            }
+        }
+    }
+
+    /// Evicts the client's changed registration from the cache of every
+    /// instance.
+    pub async fn invalidate_registration(&self, client_id: &str) {
+        self.registration_cache.invalidate(client_id).await;
+
+        // The other instances still evict it when their cache entry expires
+        if let Err(e) = self.invalidation_bus.publish(client_id).await {
+            warn!("Failed to publish the registration invalidation: {:?}", e);
+        }
+    }
+
+    /// Notifies the stream subscribers of a newly archived message.
+    /// Checks that the client registered with the relay. The registrations
+    /// stored before the relays were checked fall back to the default relay.
+    pub fn is_registered_with(
+        &self,
+        registration: &CachedRegistration,
+        relay_client: &RelayClient,
+    ) -> bool {
+        let client = self
+            .relays
+            .get(&registration.relay_url)
+            .unwrap_or_else(|| self.relays.default_client());
+        client.base_url() == relay_client.base_url()
+    }
+
+    pub fn publish_message(&self, message: Message) {
+        // Fails only when nobody is subscribed
+        let _ = self.message_events.send(message);
+    }
+}
+
+/// The client of a single message, or subscriptions update, sent by the Relay.
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct SignedMessage {
+    client_id: Arc<str>,
 }
 
+#[async_trait]
 impl State for Arc<AppState> {
     fn config(&self) -> Configuration {
         self.config.clone()
@@ -99,11 +338,101 @@ impl State for Arc<AppState> {
         self.messages_store.clone()
     }
 
-    fn relay_client(&self) -> RelayClient {
-        self.relay_client.clone()
+    fn signature_store(&self) -> SignatureStorageArc {
+        self.signature_store.clone()
+    }
+
+    fn relays(&self) -> RelayClients {
+        self.relays.clone()
+    }
+
+    async fn authorize_signing_relay(
+        &self,
+        relay_client: &RelayClient,
+        body: &[u8],
+    ) -> error::Result<()> {
+        // The batches are checked per message by their handler, so that a
+        // single client doesn't fail the others. The malformed bodies are
+        // rejected by the handlers.
+        let client_id = match serde_json::from_slice::<SignedMessage>(body) {
+            Ok(message) => message.client_id,
+            Err(_) => return Ok(()),
+        };
+
+        // The body is signed by a known relay, the registration it loads is
+        // cached for the handlers
+        match load_registration(self, &client_id).await? {
+            Some(registration) if !self.is_registered_with(&registration, relay_client) => {
+                Err(error::Error::RelayMismatch)
+            }
+            _ => Ok(()),
+        }
     }
 
     fn validate_signatures(&self) -> bool {
         self.config.validate_signatures
     }
+
+    fn signature_max_age(&self) -> u64 {
+        self.config.signature_max_age
+    }
+}
+
+#[cfg(test)]
+mod test_registration_cache {
+    use super::*;
+
+    fn registration() -> CachedRegistration {
+        CachedRegistration::new(vec![Arc::from("1000")], Arc::from("relay"), None)
+    }
+
+    #[tokio::test]
+    async fn test_insert() {
+        let cache = RegistrationCache::new();
+
+        let version = cache.version();
+        cache
+            .insert(Arc::from("client"), registration(), version)
+            .await;
+        assert!(cache.get("client").is_some());
+
+        cache.invalidate("client").await;
+        assert!(cache.get("client").is_none());
+    }
+
+    #[tokio::test]
+    async fn test_insert_invalidated() {
+        let cache = RegistrationCache::new();
+
+        // The registration changed while it was loaded
+        let version = cache.version();
+        cache.invalidate("client").await;
+        cache
+            .insert(Arc::from("client"), registration(), version)
+            .await;
+        cache
+            .insert(Arc::from("other"), registration(), version)
+            .await;
+        assert!(cache.get("client").is_none());
+        assert!(cache.get("other").is_some());
+
+        // Loaded again since
+        let version = cache.version();
+        cache
+            .insert(Arc::from("client"), registration(), version)
+            .await;
+        assert!(cache.get("client").is_some());
+    }
+
+    #[tokio::test]
+    async fn test_insert_all_invalidated() {
+        let cache = RegistrationCache::new();
+
+        let version = cache.version();
+        cache.invalidate_all();
+        cache
+            .insert(Arc::from("client"), registration(), version)
+            .await;
+        assert!(cache.get("client").is_none());
+    }
 }
@@ -1,8 +1,9 @@
 use {
     super::StoreError,
     async_trait::async_trait,
+    chrono::{DateTime, Utc},
     serde::{Deserialize, Serialize},
-    std::{fmt::Debug, sync::Arc},
+    std::{collections::HashMap, fmt::Debug, ops::RangeInclusive, sync::Arc},
     wither::{
         bson::{self, doc, oid::ObjectId},
         Model,
@@ -15,6 +16,11 @@ use {
     index(keys = r#"doc!{"ts": 1}"#),
     index(keys = r#"doc!{"ts": -1}"#),
     index(keys = r#"doc!{"topic": 1}"#),
+    index(keys = r#"doc!{"topic": 1, "ts": 1, "_id": 1}"#),
+    index(keys = r#"doc!{"topic": 1, "client_id": 1, "ts": 1, "_id": 1}"#),
+    index(keys = r#"doc!{"topic": 1, "method": 1, "ts": 1, "_id": 1}"#),
+    index(keys = r#"doc!{"topic": 1, "tag": 1, "ts": 1, "_id": 1}"#),
+    index(keys = r#"doc!{"expires_at": 1}"#),
     index(
         keys = r#"doc!{"client_id": 1, "topic": 1, "message_id": 1}"#,
         options = r#"doc!{"unique": true}"#
@@ -37,34 +43,310 @@ pub struct Message {
     pub message_id: Arc<str>,
     /// The actual message.
     pub message: Arc<str>,
+    /// The Relay tag of the message, unset for the messages archived before
+    /// tags were kept.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tag: Option<u32>,
+    /// When the message expires, kept forever if unset.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub expires_at: Option<bson::DateTime>,
+    /// The position of the message in its topic's history, set by the stores.
+    #[serde(skip)]
+    pub cursor: Option<MessageCursor>,
+}
+
+/// The position of a message in its topic's history. Messages are strictly
+/// ordered by timestamp, then by their store ID for the identical timestamps.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub struct MessageCursor {
+    #[serde(with = "chrono::serde::ts_microseconds")]
+    pub ts: DateTime<Utc>,
+    /// The ID of the message in the store, e.g. its `ObjectId` in MongoDB.
+    pub id: Arc<str>,
+}
+
+/// Restricts the messages returned by the stores, the unset fields match every
+/// message.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct MessageFilter {
+    /// The oldest timestamp, inclusive.
+    pub since: Option<DateTime<Utc>>,
+    /// The newest timestamp, exclusive.
+    pub until: Option<DateTime<Utc>>,
+    /// The messages method (`publish`/`subscription`).
+    pub method: Option<Arc<str>>,
+    /// The client that stored the messages.
+    pub client_id: Option<Arc<str>>,
+    /// The tags of the messages, inclusive.
+    pub tags: Option<RangeInclusive<u32>>,
+}
+
+impl MessageFilter {
+    /// Checks whether the message passes the filter.
+    pub fn matches(&self, message: &Message) -> bool {
+        let ts = message.timestamp.to_chrono();
+
+        self.since.is_none_or(|since| ts >= since)
+            && self.until.is_none_or(|until| ts < until)
+            && self
+                .method
+                .as_ref()
+                .is_none_or(|method| *method == message.method)
+            && self
+                .client_id
+                .as_ref()
+                .is_none_or(|client_id| *client_id == message.client_id)
+            && self
+                .tags
+                .as_ref()
+                .is_none_or(|tags| message.tag.is_some_and(|tag| tags.contains(&tag)))
+    }
 }
 
 #[derive(Debug, Serialize, Deserialize, PartialEq, Eq)]
 pub struct StoreMessages {
     pub messages: Vec<Message>,
-    pub next_id: Option<Arc<str>>,
+    /// The position of the last returned message, set when more messages
+    /// follow it.
+    pub next_cursor: Option<MessageCursor>,
+}
+
+/// A topic to load through [`MessagesStore::get_topics_messages_after`], from
+/// its own position.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TopicOrigin {
+    pub topic: Arc<str>,
+    pub origin: Option<MessageCursor>,
+}
+
+/// Splits the messages of a multi-topic query, ordered across all the topics,
+/// into one page per origin. When the query had more than `message_count`
+/// messages, every topic resumes from its last message or, if none was
+/// returned, from the later of its origin and the last message of the whole
+/// query, it has no message in between.
+pub fn split_topics_messages(
+    origins: &[TopicOrigin],
+    mut messages: Vec<Message>,
+    message_count: usize,
+) -> Vec<StoreMessages> {
+    let truncated = messages.len() > message_count;
+    messages.truncate(message_count);
+    let last_cursor = messages.last().and_then(|message| message.cursor.clone());
+
+    let indexes: HashMap<&str, usize> = origins
+        .iter()
+        .enumerate()
+        .map(|(index, origin)| (origin.topic.as_ref(), index))
+        .collect();
+    let mut pages: Vec<StoreMessages> = origins
+        .iter()
+        .map(|_| StoreMessages {
+            messages: vec![],
+            next_cursor: None,
+        })
+        .collect();
+    for message in messages {
+        if let Some(&index) = indexes.get(message.topic.as_ref()) {
+            pages[index].messages.push(message);
+        }
+    }
+
+    if truncated {
+        for (page, origin) in pages.iter_mut().zip(origins) {
+            page.next_cursor = match (page.messages.last(), &origin.origin, &last_cursor) {
+                (Some(message), _, _) => message.cursor.clone(),
+                // The store IDs aren't comparable here, the ties keep the
+                // origin
+                (None, Some(origin), Some(last_cursor)) if last_cursor.ts > origin.ts => {
+                    Some(last_cursor.clone())
+                }
+                (None, Some(origin), _) => Some(origin.clone()),
+                (None, None, _) => last_cursor.clone(),
+            };
+        }
+    }
+
+    pages
+}
+
+/// A message to store through [`MessagesStore::upsert_message`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct NewMessage {
+    pub method: Arc<str>,
+    pub client_id: Arc<str>,
+    pub topic: Arc<str>,
+    pub message_id: Arc<str>,
+    pub message: Arc<str>,
+    pub tag: Option<u32>,
+    pub expires_at: Option<DateTime<Utc>>,
+}
+
+impl NewMessage {
+    /// Returns the message as stored at the `cursor` position.
+    pub fn into_message(self, cursor: MessageCursor) -> Message {
+        Message {
+            id: None,
+            timestamp: cursor.ts.into(),
+            method: self.method,
+            client_id: self.client_id,
+            topic: self.topic,
+            message_id: self.message_id,
+            message: self.message,
+            tag: self.tag,
+            expires_at: self.expires_at.map(Into::into),
+            cursor: Some(cursor),
+        }
+    }
 }
 
 #[async_trait]
 pub trait MessagesStore: 'static + Send + Sync {
-    async fn upsert_message(
+    /// Stores the message, replacing the client's previous one with the same
+    /// ID, and returns its new position in the topic's history.
+    async fn upsert_message(&self, message: &NewMessage) -> Result<MessageCursor, StoreError>;
+    /// Stores several messages at once, upserting them like `upsert_message`,
+    /// and returns the result of each message in order. The outer error fails
+    /// the whole batch.
+    async fn upsert_messages(
         &self,
-        method: &str,
-        client_id: &str,
-        topic: &str,
-        message_id: &str,
-        message: &str,
-    ) -> Result<(), StoreError>;
+        messages: &[NewMessage],
+    ) -> Result<Vec<Result<MessageCursor, StoreError>>, StoreError>;
+    /// Returns the messages passing the filter strictly after `origin`, from
+    /// the oldest.
     async fn get_messages_after(
         &self,
         topic: &str,
-        origin: Option<&str>,
+        filter: &MessageFilter,
+        origin: Option<&MessageCursor>,
         message_count: usize,
     ) -> Result<StoreMessages, StoreError>;
+    /// Returns the messages passing the filter strictly before `origin`, from
+    /// the newest.
     async fn get_messages_before(
         &self,
         topic: &str,
-        origin: Option<&str>,
+        filter: &MessageFilter,
+        origin: Option<&MessageCursor>,
         message_count: usize,
     ) -> Result<StoreMessages, StoreError>;
+    /// Returns the messages of several topics, each strictly after its own
+    /// origin, from the oldest across all the topics and at most
+    /// `message_count` in total. The pages follow the order of `origins`.
+    async fn get_topics_messages_after(
+        &self,
+        origins: &[TopicOrigin],
+        message_count: usize,
+    ) -> Result<Vec<StoreMessages>, StoreError>;
+    async fn has_messages(&self, client_id: &str, topic: &str) -> Result<bool, StoreError>;
+    /// Checks whether the client stored the message on the topic.
+    async fn has_message(
+        &self,
+        client_id: &str,
+        topic: &str,
+        message_id: &str,
+    ) -> Result<bool, StoreError>;
+    /// Returns the `topics` the client stored messages on, in a single query.
+    async fn topics_with_messages(
+        &self,
+        client_id: &str,
+        topics: &[&str],
+    ) -> Result<Vec<Arc<str>>, StoreError>;
+    /// Counts the messages stored for the client, only on the topic when set.
+    async fn count_messages(&self, client_id: &str, topic: Option<&str>)
+        -> Result<u64, StoreError>;
+    /// Deletes a single message stored for the client, returning how many were
+    /// deleted.
+    async fn delete_message(
+        &self,
+        client_id: &str,
+        topic: &str,
+        message_id: &str,
+    ) -> Result<u64, StoreError>;
+    /// Deletes the messages stored for the client on the topic, returning how
+    /// many were deleted.
+    async fn delete_topic_messages(&self, client_id: &str, topic: &str) -> Result<u64, StoreError>;
+    /// Deletes every message stored for the client, returning how many were
+    /// deleted.
+    async fn delete_client_messages(&self, client_id: &str) -> Result<u64, StoreError>;
+    /// Deletes the messages expired at `now`, returning how many were deleted.
+    async fn delete_expired_messages(&self, now: DateTime<Utc>) -> Result<u64, StoreError>;
+    /// Checks that the store is reachable.
+    async fn ping(&self) -> Result<(), StoreError>;
+    /// Waits for the pending operations to complete and closes the
+    /// connections, the store can't be used afterwards.
+    async fn close(&self);
+}
+
+#[cfg(test)]
+mod test_split_topics_messages {
+    use super::*;
+
+    fn message(topic: &str, ts: i64) -> Message {
+        let cursor = MessageCursor {
+            ts: bson::DateTime::from_millis(ts).to_chrono(),
+            id: ts.to_string().into(),
+        };
+
+        Message {
+            id: None,
+            timestamp: bson::DateTime::from_millis(ts),
+            method: "publish".into(),
+            client_id: "client".into(),
+            topic: topic.into(),
+            message_id: ts.to_string().into(),
+            message: ts.to_string().into(),
+            tag: None,
+            expires_at: None,
+            cursor: Some(cursor),
+        }
+    }
+
+    fn origin(topic: &str, ts: Option<i64>) -> TopicOrigin {
+        TopicOrigin {
+            topic: topic.into(),
+            origin: ts.and_then(|ts| message(topic, ts).cursor),
+        }
+    }
+
+    #[test]
+    fn test_truncated_pages() {
+        let origins = [origin("a", None), origin("b", Some(1)), origin("c", None)];
+        let messages = vec![message("a", 2), message("a", 3), message("b", 4)];
+
+        let pages = split_topics_messages(&origins, messages, 2);
+
+        assert_eq!(pages[0].messages.len(), 2);
+        assert_eq!(pages[0].next_cursor, message("a", 3).cursor);
+        assert!(pages[1].messages.is_empty());
+        assert_eq!(pages[1].next_cursor, message("a", 3).cursor);
+        assert!(pages[2].messages.is_empty());
+        assert_eq!(pages[2].next_cursor, message("a", 3).cursor);
+    }
+
+    #[test]
+    fn test_truncated_page_keeps_later_origin() {
+        let origins = [origin("a", None), origin("b", Some(10))];
+        let messages = vec![message("a", 2), message("a", 3), message("b", 11)];
+
+        let pages = split_topics_messages(&origins, messages, 2);
+
+        assert!(pages[1].messages.is_empty());
+        assert_eq!(
+            pages[1].next_cursor,
+            message("b", 10).cursor,
+            "check the topic doesn't resume before its origin"
+        );
+    }
+
+    #[test]
+    fn test_complete_pages() {
+        let origins = [origin("a", None), origin("b", Some(10))];
+        let messages = vec![message("a", 2), message("b", 11)];
+
+        let pages = split_topics_messages(&origins, messages, 2);
+
+        assert_eq!(pages[0].messages.len(), 1);
+        assert_eq!(pages[1].messages.len(), 1);
+        assert!(pages.iter().all(|page| page.next_cursor.is_none()));
+    }
 }
@@ -1,6 +1,10 @@
 pub mod messages;
 pub mod mongo;
+pub mod postgres;
 pub mod registrations;
+pub mod signatures;
+mod sql;
+pub mod sqlite;
 
 #[derive(Debug, thiserror::Error)]
 pub enum StoreError {
@@ -8,6 +12,17 @@ pub enum StoreError {
     #[error("Cannot find {0} with specified identifier {1}")]
     NotFound(String, String),
 
+    /// The cursor doesn't point into this store, param is the reason
+    #[error("Invalid cursor: {0}")]
+    InvalidCursor(String),
+
+    /// A single write of a batch failed, param is the reason
+    #[error("Write failed: {0}")]
+    WriteFailed(String),
+
     #[error(transparent)]
     Database(#[from] wither::WitherError),
+
+    #[error(transparent)]
+    Sql(#[from] sqlx::Error),
 }
@@ -1,26 +1,87 @@
 use {
     crate::{
         config::Configuration,
+        invalidation::{Invalidation, InvalidationBus, InvalidationStream},
         store::{
-            messages::{Message, MessagesStore, StoreMessages},
+            messages::{
+                split_topics_messages,
+                Message,
+                MessageCursor,
+                MessageFilter,
+                MessagesStore,
+                NewMessage,
+                StoreMessages,
+                TopicOrigin,
+            },
             registrations::{Registration, RegistrationStore},
+            signatures::SignatureStore,
             StoreError,
         },
     },
     async_trait::async_trait,
-    chrono::Utc,
-    futures::TryStreamExt,
+    chrono::{DateTime, Utc},
+    futures::{StreamExt, TryStreamExt},
+    serde::{Deserialize, Serialize},
+    std::sync::Arc,
     wither::{
-        bson::{self, doc, Document},
+        bson::{self, doc, oid::ObjectId, Document},
         mongodb::{
-            options::{ClientOptions, FindOneAndUpdateOptions, FindOptions},
+            error::{BulkWriteFailure, ErrorKind, WriteError, WriteFailure},
+            options::{
+                ClientOptions,
+                FindOneAndUpdateOptions,
+                FindOptions,
+                InsertManyOptions,
+                ReturnDocument,
+            },
             Client,
             Database,
         },
         Model,
+        WitherError,
     },
 };
 
+/// The code of the writes violating a unique index.
+const DUPLICATE_KEY_ERROR_CODE: i32 = 11000;
+
+/// A registration change, broadcast to every instance through a change
+/// stream.
+#[derive(Clone, Debug, Model, Serialize, Deserialize)]
+#[model(
+    collection_name = "RegistrationInvalidations",
+    index(
+        keys = r#"doc!{"ts": 1}"#,
+        options = r#"doc!{"expireAfterSeconds": 3600}"#
+    )
+)]
+struct RegistrationInvalidation {
+    /// MongoDB's default `_id` field.
+    #[serde(rename = "_id", skip_serializing_if = "Option::is_none")]
+    id: Option<ObjectId>,
+    client_id: Arc<str>,
+    ts: bson::DateTime,
+}
+
+/// The signature of an accepted Relay request, kept to reject its replays on
+/// every instance.
+#[derive(Clone, Debug, Model, Serialize, Deserialize)]
+#[model(
+    collection_name = "RelaySignatures",
+    index(keys = r#"doc!{"signature": 1}"#, options = r#"doc!{"unique": true}"#),
+    index(
+        keys = r#"doc!{"expires_at": 1}"#,
+        options = r#"doc!{"expireAfterSeconds": 0}"#
+    )
+)]
+struct RelaySignature {
+    /// MongoDB's default `_id` field.
+    #[serde(rename = "_id", skip_serializing_if = "Option::is_none")]
+    id: Option<ObjectId>,
+    signature: Arc<str>,
+    expires_at: bson::DateTime,
+}
+
 #[derive(Clone)]
 pub struct MongoStore {
     db: Database,
@@ -28,7 +89,10 @@ pub struct MongoStore {
 
 impl MongoStore {
     pub async fn new(config: &Configuration) -> anyhow::Result<Self> {
-        let url = &config.mongo_address;
+        let url = config
+            .mongo_address
+            .as_deref()
+            .ok_or_else(|| anyhow::anyhow!("the mongo storage backend requires `MONGO_ADDRESS`"))?;
 
         let client_options = ClientOptions::parse(url).await?;
         let client = Client::with_options(client_options)?;
@@ -38,129 +102,444 @@ impl MongoStore {
 
         Message::sync(&db).await?;
         Registration::sync(&db).await?;
+        RegistrationInvalidation::sync(&db).await?;
+        RelaySignature::sync(&db).await?;
 
         Ok(Self { db })
     }
 
-    async fn get_message_timestamp(
-        &self,
-        topic: &str,
-        message_id: &str,
-    ) -> Result<bson::DateTime, StoreError> {
-        let filter = doc! {
-            "topic": &topic,
-            "message_id": message_id,
-        };
-
-        let cursor = Message::find(&self.db, filter, None).await?;
-        let origin: Vec<Message> = cursor.try_collect().await?;
-        let origin = origin.first().ok_or(StoreError::NotFound(
-            topic.to_string(),
-            message_id.to_string(),
-        ))?;
-
-        Ok(origin.timestamp)
-    }
-
     async fn get_messages(
         &self,
         topic: &str,
-        origin: Option<&str>,
+        filter: &MessageFilter,
+        origin: Option<&MessageCursor>,
         message_count: usize,
         comparator: &str,
         sort_order: i32,
     ) -> Result<StoreMessages, StoreError> {
-        let filter: Result<Document, StoreError> = match origin {
-            None => Ok(doc! {
-                "topic": &topic,
-            }),
-            Some(origin) => {
-                let ts = self.get_message_timestamp(topic, origin).await?;
-                Ok(doc! {
-                    "topic": &topic,
-                    "ts": { comparator: ts }
-                })
-            }
+        let mut query = doc! {
+            "topic": &topic,
         };
-        let filter = filter?;
+        if let Some(origin) = origin {
+            query.insert("$or", cursor_conditions(origin, comparator)?);
+        }
+
+        // The equality fields come first in the indexes, then the sorted `ts`
+        let mut ts_range = Document::new();
+        if let Some(since) = filter.since {
+            ts_range.insert("$gte", since);
+        }
+        if let Some(until) = filter.until {
+            ts_range.insert("$lt", until);
+        }
+        if !ts_range.is_empty() {
+            query.insert("ts", ts_range);
+        }
+        if let Some(method) = &filter.method {
+            query.insert("method", method.as_ref());
+        }
+        if let Some(client_id) = &filter.client_id {
+            query.insert("client_id", client_id.as_ref());
+        }
+        if let Some(tags) = &filter.tags {
+            query.insert(
+                "tag",
+                doc! { "$gte": i64::from(*tags.start()), "$lte": i64::from(*tags.end()) },
+            );
+        }
 
         let message_count: i64 = message_count as i64;
         let limit = -(message_count + 1);
         let options = FindOptions::builder()
-            .sort(doc! {"ts": sort_order})
+            .sort(doc! {"ts": sort_order, "_id": sort_order})
             .limit(limit)
             .build();
 
-        let cursor = Message::find(&self.db, filter, options).await?;
+        let cursor = Message::find(&self.db, query, options).await?;
 
         let mut messages: Vec<Message> = cursor.try_collect().await?;
+        for message in &mut messages {
+            message.cursor = message_cursor(message);
+        }
 
         if messages.len() > message_count as usize {
-            let next_id = messages.pop().map(|message| message.message_id);
-            return Ok(StoreMessages { messages, next_id });
+            messages.pop();
+            let next_cursor = messages.last().and_then(|message| message.cursor.clone());
+            return Ok(StoreMessages {
+                messages,
+                next_cursor,
+            });
         }
 
         Ok(StoreMessages {
             messages,
-            next_id: None,
+            next_cursor: None,
         })
     }
 }
 
+/// Returns the `$or` conditions matching the messages positioned after or
+/// before `origin`, depending on the `comparator`.
+fn cursor_conditions(
+    origin: &MessageCursor,
+    comparator: &str,
+) -> Result<Vec<Document>, StoreError> {
+    let id = ObjectId::parse_str(origin.id.as_ref())
+        .map_err(|e| StoreError::InvalidCursor(e.to_string()))?;
+
+    // The `_id` breaks the ties between identical timestamps
+    Ok(vec![
+        doc! { "ts": { comparator: origin.ts } },
+        doc! { "ts": origin.ts, "_id": { comparator: id } },
+    ])
+}
+
+fn message_cursor(message: &Message) -> Option<MessageCursor> {
+    message.id.map(|id| MessageCursor {
+        ts: message.timestamp.to_chrono(),
+        id: id.to_hex().into(),
+    })
+}
+
 #[async_trait]
 impl MessagesStore for MongoStore {
-    async fn upsert_message(
-        &self,
-        method: &str,
-        client_id: &str,
-        topic: &str,
-        message_id: &str,
-        message: &str,
-    ) -> Result<(), StoreError> {
+    async fn upsert_message(&self, message: &NewMessage) -> Result<MessageCursor, StoreError> {
         let filter = doc! {
-            "client_id": &client_id,
-            "topic": &topic,
-            "message_id": &message_id,
+            "client_id": message.client_id.as_ref(),
+            "topic": message.topic.as_ref(),
+            "message_id": message.message_id.as_ref(),
         };
 
         let update = doc! {
             "$set": {
                 "ts": Utc::now(),
-                "method": &method,
-                "client_id": &client_id,
-                "topic": &topic,
-                "message_id": &message_id,
-                "message": &message,
+                "method": message.method.as_ref(),
+                "client_id": message.client_id.as_ref(),
+                "topic": message.topic.as_ref(),
+                "message_id": message.message_id.as_ref(),
+                "message": message.message.as_ref(),
+                "tag": message.tag.map(i64::from),
+                "expires_at": message.expires_at,
             }
         };
 
-        let option = FindOneAndUpdateOptions::builder().upsert(true).build();
+        let option = FindOneAndUpdateOptions::builder()
+            .upsert(true)
+            .return_document(ReturnDocument::After)
+            .build();
 
-        match Message::find_one_and_update(&self.db, filter, update, option).await? {
-            Some(_) => Ok(()),
-            None => Ok(()),
+        Message::find_one_and_update(&self.db, filter, update, option)
+            .await?
+            .as_ref()
+            .and_then(message_cursor)
+            .ok_or(StoreError::NotFound(
+                message.topic.to_string(),
+                message.message_id.to_string(),
+            ))
+    }
+
+    async fn upsert_messages(
+        &self,
+        messages: &[NewMessage],
+    ) -> Result<Vec<Result<MessageCursor, StoreError>>, StoreError> {
+        // The `ObjectId`s are generated in order, so that the batch order is
+        // kept between the identical timestamps
+        let ts = bson::DateTime::from_chrono(Utc::now());
+        let ids: Vec<ObjectId> = messages.iter().map(|_| ObjectId::new()).collect();
+        let documents = messages.iter().zip(&ids).map(|(message, id)| {
+            doc! {
+                "_id": id,
+                "ts": ts,
+                "method": message.method.as_ref(),
+                "client_id": message.client_id.as_ref(),
+                "topic": message.topic.as_ref(),
+                "message_id": message.message_id.as_ref(),
+                "message": message.message.as_ref(),
+                "tag": message.tag.map(i64::from),
+                "expires_at": message.expires_at,
+            }
+        });
+
+        // Unordered, a failed message doesn't stop the next ones
+        let options = InsertManyOptions::builder().ordered(false).build();
+        let write_errors = match Message::collection(&self.db)
+            .insert_many(documents, options)
+            .await
+        {
+            Ok(_) => vec![],
+            Err(e) => match e.kind.as_ref() {
+                ErrorKind::BulkWrite(BulkWriteFailure {
+                    write_errors: Some(write_errors),
+                    write_concern_error: None,
+                    ..
+                }) => write_errors.clone(),
+                _ => return Err(WitherError::from(e).into()),
+            },
+        };
+
+        let mut results: Vec<Result<MessageCursor, StoreError>> = ids
+            .iter()
+            .map(|id| {
+                Ok(MessageCursor {
+                    ts: ts.to_chrono(),
+                    id: id.to_hex().into(),
+                })
+            })
+            .collect();
+
+        // The driver has no bulk upsert, the messages already stored are
+        // updated one by one instead
+        for write_error in write_errors {
+            results[write_error.index] = if write_error.code == DUPLICATE_KEY_ERROR_CODE {
+                self.upsert_message(&messages[write_error.index]).await
+            } else {
+                Err(StoreError::WriteFailed(write_error.message))
+            };
         }
+
+        Ok(results)
     }
 
     async fn get_messages_after(
         &self,
         topic: &str,
-        origin: Option<&str>,
+        filter: &MessageFilter,
+        origin: Option<&MessageCursor>,
         message_count: usize,
     ) -> Result<StoreMessages, StoreError> {
-        self.get_messages(topic, origin, message_count, "$gte", 1)
+        self.get_messages(topic, filter, origin, message_count, "$gt", 1)
             .await
     }
 
     async fn get_messages_before(
         &self,
         topic: &str,
-        origin: Option<&str>,
+        filter: &MessageFilter,
+        origin: Option<&MessageCursor>,
         message_count: usize,
     ) -> Result<StoreMessages, StoreError> {
-        self.get_messages(topic, origin, message_count, "$lte", -1)
+        self.get_messages(topic, filter, origin, message_count, "$lt", -1)
             .await
     }
+
+    async fn get_topics_messages_after(
+        &self,
+        origins: &[TopicOrigin],
+        message_count: usize,
+    ) -> Result<Vec<StoreMessages>, StoreError> {
+        if origins.is_empty() {
+            return Ok(vec![]);
+        }
+
+        // The topics loaded from their start share a single `$in` branch, the
+        // others each need their own position
+        let mut branches = vec![];
+        let mut from_start = vec![];
+        for TopicOrigin { topic, origin } in origins {
+            match origin {
+                None => from_start.push(topic.as_ref()),
+                Some(origin) => branches.push(doc! {
+                    "topic": topic.as_ref(),
+                    "$or": cursor_conditions(origin, "$gt")?,
+                }),
+            }
+        }
+        if !from_start.is_empty() {
+            branches.push(doc! { "topic": { "$in": from_start } });
+        }
+
+        let limit = -(message_count as i64 + 1);
+        let options = FindOptions::builder()
+            .sort(doc! {"ts": 1, "_id": 1})
+            .limit(limit)
+            .build();
+
+        let cursor = Message::find(&self.db, doc! { "$or": branches }, options).await?;
+
+        let mut messages: Vec<Message> = cursor.try_collect().await?;
+        for message in &mut messages {
+            message.cursor = message_cursor(message);
+        }
+
+        Ok(split_topics_messages(origins, messages, message_count))
+    }
+
+    async fn has_messages(&self, client_id: &str, topic: &str) -> Result<bool, StoreError> {
+        let filter = doc! {
+            "client_id": &client_id,
+            "topic": &topic,
+        };
+
+        let message = Message::find_one(&self.db, filter, None).await?;
+        Ok(message.is_some())
+    }
+
+    async fn has_message(
+        &self,
+        client_id: &str,
+        topic: &str,
+        message_id: &str,
+    ) -> Result<bool, StoreError> {
+        let filter = doc! {
+            "client_id": &client_id,
+            "topic": &topic,
+            "message_id": &message_id,
+        };
+
+        let message = Message::find_one(&self.db, filter, None).await?;
+        Ok(message.is_some())
+    }
+
+    async fn topics_with_messages(
+        &self,
+        client_id: &str,
+        topics: &[&str],
+    ) -> Result<Vec<Arc<str>>, StoreError> {
+        let filter = doc! {
+            "client_id": &client_id,
+            "topic": { "$in": topics },
+        };
+
+        let topics = Message::collection(&self.db)
+            .distinct("topic", filter, None)
+            .await
+            .map_err(WitherError::from)?;
+        Ok(topics
+            .iter()
+            .filter_map(|topic| topic.as_str().map(Arc::from))
+            .collect())
+    }
+
+    async fn count_messages(
+        &self,
+        client_id: &str,
+        topic: Option<&str>,
+    ) -> Result<u64, StoreError> {
+        let mut filter = doc! {
+            "client_id": &client_id,
+        };
+        if let Some(topic) = topic {
+            filter.insert("topic", topic);
+        }
+
+        let count = Message::collection(&self.db)
+            .count_documents(filter, None)
+            .await
+            .map_err(WitherError::from)?;
+        Ok(count)
+    }
+
+    async fn delete_message(
+        &self,
+        client_id: &str,
+        topic: &str,
+        message_id: &str,
+    ) -> Result<u64, StoreError> {
+        let filter = doc! {
+            "client_id": &client_id,
+            "topic": &topic,
+            "message_id": &message_id,
+        };
+
+        let result = Message::delete_many(&self.db, filter, None).await?;
+        Ok(result.deleted_count)
+    }
+
+    async fn delete_topic_messages(&self, client_id: &str, topic: &str) -> Result<u64, StoreError> {
+        let filter = doc! {
+            "client_id": &client_id,
+            "topic": &topic,
+        };
+
+        let result = Message::delete_many(&self.db, filter, None).await?;
+        Ok(result.deleted_count)
+    }
+
+    async fn delete_client_messages(&self, client_id: &str) -> Result<u64, StoreError> {
+        let filter = doc! {
+            "client_id": &client_id,
+        };
+
+        let result = Message::delete_many(&self.db, filter, None).await?;
+        Ok(result.deleted_count)
+    }
+
+    async fn delete_expired_messages(&self, now: DateTime<Utc>) -> Result<u64, StoreError> {
+        // Not a TTL index, so the expired messages are all deleted, and counted,
+        // here
+        let filter = doc! {
+            "expires_at": { "$lte": now },
+        };
+
+        let result = Message::delete_many(&self.db, filter, None).await?;
+        Ok(result.deleted_count)
+    }
+
+    async fn ping(&self) -> Result<(), StoreError> {
+        self.db
+            .run_command(doc! { "ping": 1 }, None)
+            .await
+            .map_err(WitherError::from)?;
+        Ok(())
+    }
+
+    async fn close(&self) {
+        // The driver waits for the pending operations and closes its
+        // connections once the client is dropped
+    }
+}
+
+#[async_trait]
+impl SignatureStore for MongoStore {
+    async fn record_signature(
+        &self,
+        signature: &str,
+        expires_at: DateTime<Utc>,
+    ) -> Result<bool, StoreError> {
+        let signature = doc! {
+            "signature": signature,
+            "expires_at": expires_at,
+        };
+
+        match RelaySignature::collection(&self.db)
+            .insert_one(signature, None)
+            .await
+        {
+            Ok(_) => Ok(true),
+            Err(e)
+                if matches!(
+                    e.kind.as_ref(),
+                    ErrorKind::Write(WriteFailure::WriteError(WriteError {
+                        code: DUPLICATE_KEY_ERROR_CODE,
+                        ..
+                    }))
+                ) =>
+            {
+                Ok(false)
+            }
+            Err(e) => Err(WitherError::from(e).into()),
+        }
+    }
+
+    async fn delete_signature(&self, signature: &str) -> Result<(), StoreError> {
+        let filter = doc! {
+            "signature": signature,
+        };
+
+        RelaySignature::delete_many(&self.db, filter, None).await?;
+        Ok(())
+    }
+
+    async fn delete_expired_signatures(&self, now: DateTime<Utc>) -> Result<u64, StoreError> {
+        // The TTL index removes expired signatures too, but only runs once a
+        // minute
+        let filter = doc! {
+            "expires_at": { "$lte": now },
+        };
+
+        let result = RelaySignature::delete_many(&self.db, filter, None).await?;
+        Ok(result.deleted_count)
+    }
 }
 
 #[async_trait]
@@ -168,19 +547,35 @@ impl RegistrationStore for MongoStore {
     async fn upsert_registration(
         &self,
         client_id: &str,
-        tags: Vec<&str>,
+        tags: Option<Vec<&str>>,
         relay_url: &str,
+        expires_at: Option<DateTime<Utc>>,
     ) -> Result<(), StoreError> {
         let filter = doc! {
             "client_id": &client_id,
         };
 
+        let now = Utc::now();
+        let mut set = doc! {
+            "client_id": &client_id,
+            "relay_url": &relay_url,
+            "updated_at": now,
+            "expires_at": expires_at,
+        };
+        let mut set_on_insert = doc! {
+            "created_at": now,
+            "topics": Vec::<&str>::new(),
+        };
+
+        // Tags are left untouched unless explicitly provided
+        match tags {
+            Some(tags) => set.insert("tags", tags),
+            None => set_on_insert.insert("tags", Vec::<&str>::new()),
+        };
+
         let update = doc! {
-            "$set": {
-                "client_id": &client_id,
-                "tags": tags,
-                "relay_url": &relay_url,
-            }
+            "$set": set,
+            "$setOnInsert": set_on_insert,
         };
 
         let option = FindOneAndUpdateOptions::builder().upsert(true).build();
@@ -191,15 +586,167 @@ impl RegistrationStore for MongoStore {
         }
     }
 
+    async fn update_registration(
+        &self,
+        client_id: &str,
+        add_tags: Vec<&str>,
+        remove_tags: Vec<&str>,
+        relay_url: &str,
+        expires_at: Option<DateTime<Utc>>,
+    ) -> Result<(), StoreError> {
+        update_registration_list(&self.db, client_id, "tags", add_tags, remove_tags, doc! {
+            "relay_url": relay_url,
+            "updated_at": Utc::now(),
+            "expires_at": expires_at,
+        })
+        .await
+    }
+
+    async fn update_registration_topics(
+        &self,
+        client_id: &str,
+        subscribe: Vec<&str>,
+        unsubscribe: Vec<&str>,
+    ) -> Result<(), StoreError> {
+        update_registration_list(
+            &self.db,
+            client_id,
+            "topics",
+            subscribe,
+            unsubscribe,
+            doc! {},
+        )
+        .await
+    }
+
     async fn get_registration(&self, client_id: &str) -> Result<Registration, StoreError> {
+        let registration =
+            Registration::find_one(&self.db, live_registration_filter(client_id), None).await?;
+        registration.ok_or(StoreError::NotFound(
+            "registration".to_string(),
+            client_id.to_string(),
+        ))
+    }
+
+    async fn delete_registration(&self, client_id: &str) -> Result<u64, StoreError> {
         let filter = doc! {
             "client_id": &client_id,
         };
 
-        let registration = Registration::find_one(&self.db, filter, None).await?;
-        registration.ok_or(StoreError::NotFound(
+        let result = Registration::delete_many(&self.db, filter, None).await?;
+        Ok(result.deleted_count)
+    }
+
+    async fn delete_expired_registrations(&self, now: DateTime<Utc>) -> Result<u64, StoreError> {
+        let filter = doc! {
+            "expires_at": { "$lte": now },
+        };
+
+        let result = Registration::delete_many(&self.db, filter, None).await?;
+        Ok(result.deleted_count)
+    }
+
+    async fn ping(&self) -> Result<(), StoreError> {
+        self.db
+            .run_command(doc! { "ping": 1 }, None)
+            .await
+            .map_err(WitherError::from)?;
+        Ok(())
+    }
+
+    async fn close(&self) {}
+}
+
+#[async_trait]
+impl InvalidationBus for MongoStore {
+    async fn publish(&self, client_id: &str) -> Result<(), StoreError> {
+        let invalidation = doc! {
+            "client_id": client_id,
+            "ts": Utc::now(),
+        };
+
+        RegistrationInvalidation::collection(&self.db)
+            .insert_one(invalidation, None)
+            .await
+            .map_err(WitherError::from)?;
+        Ok(())
+    }
+
+    async fn subscribe(&self) -> Result<InvalidationStream, StoreError> {
+        // Change streams require a replica set
+        let pipeline = [doc! { "$match": { "operationType": "insert" } }];
+        let events = RegistrationInvalidation::collection(&self.db)
+            .watch(pipeline, None)
+            .await
+            .map_err(WitherError::from)?;
+
+        let invalidations = events.map(|event| {
+            let event = event.map_err(WitherError::from)?;
+            let client_id = event
+                .full_document
+                .as_ref()
+                .and_then(|invalidation| invalidation.get_str("client_id").ok());
+
+            Ok(match client_id {
+                Some(client_id) => Invalidation::Registration(Arc::from(client_id)),
+                None => Invalidation::All,
+            })
+        });
+
+        Ok(invalidations.boxed())
+    }
+}
+
+/// Removes the `remove` values from the `field` list of the client's live
+/// registration, then adds the missing `add` ones along with the `set` fields.
+/// `$pull` and `$addToSet` can't update the same field at once, so these are
+/// two updates, each of them atomic.
+async fn update_registration_list(
+    db: &Database,
+    client_id: &str,
+    field: &str,
+    add: Vec<&str>,
+    remove: Vec<&str>,
+    set: Document,
+) -> Result<(), StoreError> {
+    let collection = Registration::collection(db);
+
+    let update = doc! {
+        "$pull": { field: { "$in": remove } },
+    };
+    let result = collection
+        .update_one(live_registration_filter(client_id), update, None)
+        .await
+        .map_err(WitherError::from)?;
+    if result.matched_count == 0 {
+        return Err(StoreError::NotFound(
             "registration".to_string(),
             client_id.to_string(),
-        ))
+        ));
+    }
+
+    let mut update = doc! {
+        "$addToSet": { field: { "$each": add } },
+    };
+    if !set.is_empty() {
+        update.insert("$set", set);
+    }
+    collection
+        .update_one(live_registration_filter(client_id), update, None)
+        .await
+        .map_err(WitherError::from)?;
+
+    Ok(())
+}
+
+/// Matches the client's registration unless it has expired, the expired
+/// registrations linger until the reaper deletes them.
+fn live_registration_filter(client_id: &str) -> Document {
+    doc! {
+        "client_id": client_id,
+        "$or": [
+            { "expires_at": null },
+            { "expires_at": { "$gt": Utc::now() } },
+        ],
     }
 }
@@ -0,0 +1,467 @@
+use {
+    crate::{
+        config::Configuration,
+        invalidation::{Invalidation, InvalidationBus, InvalidationStream},
+        store::{
+            messages::{
+                split_topics_messages,
+                Message,
+                MessageCursor,
+                MessageFilter,
+                MessagesStore,
+                NewMessage,
+                StoreMessages,
+                TopicOrigin,
+            },
+            registrations::{Registration, RegistrationStore},
+            signatures::SignatureStore,
+            sql::{
+                messages_page,
+                messages_query,
+                registration_updated,
+                topics_messages_query,
+                topics_with_messages_query,
+                update_registration_sql,
+                update_registration_topics_sql,
+                upsert_message_query,
+                upsert_registration_sql,
+                MessageRow,
+                RegistrationRow,
+                SELECT_REGISTRATION,
+            },
+            StoreError,
+        },
+    },
+    async_trait::async_trait,
+    chrono::{DateTime, Utc},
+    futures::{stream, StreamExt},
+    sqlx::{
+        postgres::{PgListener, PgPool, PgPoolOptions, Postgres},
+        Connection,
+    },
+    std::sync::Arc,
+};
+
+/// The notification channel of the registration changes.
+const INVALIDATION_CHANNEL: &str = "registration_invalidations";
+
+/// A store backed by a PostgreSQL database, the schema is managed by the
+/// versioned migrations in `migrations/postgres`.
+#[derive(Clone)]
+pub struct PostgresStore {
+    pool: PgPool,
+}
+
+impl PostgresStore {
+    pub async fn new(config: &Configuration) -> anyhow::Result<Self> {
+        let url = config.postgres_address.as_deref().ok_or_else(|| {
+            anyhow::anyhow!("the postgres storage backend requires `POSTGRES_ADDRESS`")
+        })?;
+
+        let pool = PgPoolOptions::new().connect(url).await?;
+
+        sqlx::migrate!("./migrations/postgres").run(&pool).await?;
+
+        Ok(Self { pool })
+    }
+
+    async fn get_messages(
+        &self,
+        topic: &str,
+        filter: &MessageFilter,
+        origin: Option<&MessageCursor>,
+        message_count: usize,
+        comparator: &str,
+        sort_order: &str,
+    ) -> Result<StoreMessages, StoreError> {
+        let rows = messages_query(topic, filter, origin, message_count, comparator, sort_order)?
+            .build_query_as::<MessageRow<Postgres>>()
+            .fetch_all(&self.pool)
+            .await?;
+
+        Ok(messages_page(
+            rows.into_iter().map(Message::from).collect(),
+            message_count,
+        ))
+    }
+}
+
+#[async_trait]
+impl MessagesStore for PostgresStore {
+    async fn upsert_message(&self, message: &NewMessage) -> Result<MessageCursor, StoreError> {
+        let row = upsert_message_query::<Postgres>(message)
+            .fetch_one(&self.pool)
+            .await?;
+
+        Ok(row.into())
+    }
+
+    async fn upsert_messages(
+        &self,
+        messages: &[NewMessage],
+    ) -> Result<Vec<Result<MessageCursor, StoreError>>, StoreError> {
+        // A single transaction with a savepoint per message, a message failing
+        // on its own is rolled back without the rest of the batch
+        let mut transaction = self.pool.begin().await?;
+
+        let mut cursors = Vec::with_capacity(messages.len());
+        for message in messages {
+            let mut savepoint = transaction.begin().await?;
+            // Fetching all the rows steps the statement to its end
+            match upsert_message_query::<Postgres>(message)
+                .fetch_all(&mut savepoint)
+                .await
+            {
+                Ok(rows) => {
+                    savepoint.commit().await?;
+                    let row = rows.into_iter().next().ok_or(sqlx::Error::RowNotFound)?;
+                    cursors.push(Ok(row.into()));
+                }
+                Err(e @ sqlx::Error::Database(_)) => {
+                    savepoint.rollback().await?;
+                    cursors.push(Err(e.into()));
+                }
+                Err(e) => return Err(e.into()),
+            }
+        }
+
+        transaction.commit().await?;
+
+        Ok(cursors)
+    }
+
+    async fn get_messages_after(
+        &self,
+        topic: &str,
+        filter: &MessageFilter,
+        origin: Option<&MessageCursor>,
+        message_count: usize,
+    ) -> Result<StoreMessages, StoreError> {
+        self.get_messages(topic, filter, origin, message_count, ">", "ASC")
+            .await
+    }
+
+    async fn get_messages_before(
+        &self,
+        topic: &str,
+        filter: &MessageFilter,
+        origin: Option<&MessageCursor>,
+        message_count: usize,
+    ) -> Result<StoreMessages, StoreError> {
+        self.get_messages(topic, filter, origin, message_count, "<", "DESC")
+            .await
+    }
+
+    async fn get_topics_messages_after(
+        &self,
+        origins: &[TopicOrigin],
+        message_count: usize,
+    ) -> Result<Vec<StoreMessages>, StoreError> {
+        if origins.is_empty() {
+            return Ok(vec![]);
+        }
+
+        let rows = topics_messages_query(origins, message_count)?
+            .build_query_as::<MessageRow<Postgres>>()
+            .fetch_all(&self.pool)
+            .await?;
+        let messages = rows.into_iter().map(Message::from).collect();
+
+        Ok(split_topics_messages(origins, messages, message_count))
+    }
+
+    async fn has_messages(&self, client_id: &str, topic: &str) -> Result<bool, StoreError> {
+        let exists = sqlx::query_scalar(
+            "SELECT EXISTS (SELECT 1 FROM messages WHERE client_id = $1 AND topic = $2)",
+        )
+        .bind(client_id)
+        .bind(topic)
+        .fetch_one(&self.pool)
+        .await?;
+
+        Ok(exists)
+    }
+
+    async fn has_message(
+        &self,
+        client_id: &str,
+        topic: &str,
+        message_id: &str,
+    ) -> Result<bool, StoreError> {
+        let exists = sqlx::query_scalar(
+            "SELECT EXISTS (SELECT 1 FROM messages WHERE client_id = $1 AND topic = $2 AND \
+             message_id = $3)",
+        )
+        .bind(client_id)
+        .bind(topic)
+        .bind(message_id)
+        .fetch_one(&self.pool)
+        .await?;
+
+        Ok(exists)
+    }
+
+    async fn topics_with_messages(
+        &self,
+        client_id: &str,
+        topics: &[&str],
+    ) -> Result<Vec<Arc<str>>, StoreError> {
+        // `IN ()` isn't valid SQL
+        if topics.is_empty() {
+            return Ok(vec![]);
+        }
+
+        let rows: Vec<(String,)> = topics_with_messages_query::<Postgres>(client_id, topics)
+            .build_query_as()
+            .fetch_all(&self.pool)
+            .await?;
+
+        Ok(rows.into_iter().map(|(topic,)| Arc::from(topic)).collect())
+    }
+
+    async fn count_messages(
+        &self,
+        client_id: &str,
+        topic: Option<&str>,
+    ) -> Result<u64, StoreError> {
+        let count: i64 = sqlx::query_scalar(
+            "SELECT COUNT(*) FROM messages WHERE client_id = $1 AND ($2::TEXT IS NULL OR topic = \
+             $2)",
+        )
+        .bind(client_id)
+        .bind(topic)
+        .fetch_one(&self.pool)
+        .await?;
+
+        Ok(count.try_into().unwrap_or_default())
+    }
+
+    async fn delete_message(
+        &self,
+        client_id: &str,
+        topic: &str,
+        message_id: &str,
+    ) -> Result<u64, StoreError> {
+        let result = sqlx::query(
+            "DELETE FROM messages WHERE client_id = $1 AND topic = $2 AND message_id = $3",
+        )
+        .bind(client_id)
+        .bind(topic)
+        .bind(message_id)
+        .execute(&self.pool)
+        .await?;
+
+        Ok(result.rows_affected())
+    }
+
+    async fn delete_topic_messages(&self, client_id: &str, topic: &str) -> Result<u64, StoreError> {
+        let result = sqlx::query("DELETE FROM messages WHERE client_id = $1 AND topic = $2")
+            .bind(client_id)
+            .bind(topic)
+            .execute(&self.pool)
+            .await?;
+
+        Ok(result.rows_affected())
+    }
+
+    async fn delete_client_messages(&self, client_id: &str) -> Result<u64, StoreError> {
+        let result = sqlx::query("DELETE FROM messages WHERE client_id = $1")
+            .bind(client_id)
+            .execute(&self.pool)
+            .await?;
+
+        Ok(result.rows_affected())
+    }
+
+    async fn delete_expired_messages(&self, now: DateTime<Utc>) -> Result<u64, StoreError> {
+        let result = sqlx::query("DELETE FROM messages WHERE expires_at <= $1")
+            .bind(now)
+            .execute(&self.pool)
+            .await?;
+
+        Ok(result.rows_affected())
+    }
+
+    async fn ping(&self) -> Result<(), StoreError> {
+        sqlx::query("SELECT 1").execute(&self.pool).await?;
+        Ok(())
+    }
+
+    async fn close(&self) {
+        self.pool.close().await;
+    }
+}
+
+#[async_trait]
+impl SignatureStore for PostgresStore {
+    async fn record_signature(
+        &self,
+        signature: &str,
+        expires_at: DateTime<Utc>,
+    ) -> Result<bool, StoreError> {
+        let result = sqlx::query(
+            "INSERT INTO relay_signatures (signature, expires_at) VALUES ($1, $2) ON CONFLICT \
+             (signature) DO NOTHING",
+        )
+        .bind(signature)
+        .bind(expires_at)
+        .execute(&self.pool)
+        .await?;
+
+        Ok(result.rows_affected() == 1)
+    }
+
+    async fn delete_signature(&self, signature: &str) -> Result<(), StoreError> {
+        sqlx::query("DELETE FROM relay_signatures WHERE signature = $1")
+            .bind(signature)
+            .execute(&self.pool)
+            .await?;
+
+        Ok(())
+    }
+
+    async fn delete_expired_signatures(&self, now: DateTime<Utc>) -> Result<u64, StoreError> {
+        let result = sqlx::query("DELETE FROM relay_signatures WHERE expires_at <= $1")
+            .bind(now)
+            .execute(&self.pool)
+            .await?;
+
+        Ok(result.rows_affected())
+    }
+}
+
+#[async_trait]
+impl RegistrationStore for PostgresStore {
+    async fn upsert_registration(
+        &self,
+        client_id: &str,
+        tags: Option<Vec<&str>>,
+        relay_url: &str,
+        expires_at: Option<DateTime<Utc>>,
+    ) -> Result<(), StoreError> {
+        sqlx::query(&upsert_registration_sql::<Postgres>())
+            .bind(client_id)
+            .bind(tags)
+            .bind(relay_url)
+            .bind(Utc::now())
+            .bind(expires_at)
+            .execute(&self.pool)
+            .await?;
+
+        Ok(())
+    }
+
+    async fn update_registration(
+        &self,
+        client_id: &str,
+        add_tags: Vec<&str>,
+        remove_tags: Vec<&str>,
+        relay_url: &str,
+        expires_at: Option<DateTime<Utc>>,
+    ) -> Result<(), StoreError> {
+        // The updated row is locked, concurrent updates apply on top of this one
+        let result = sqlx::query(&update_registration_sql::<Postgres>())
+            .bind(client_id)
+            .bind(add_tags)
+            .bind(remove_tags)
+            .bind(relay_url)
+            .bind(Utc::now())
+            .bind(expires_at)
+            .execute(&self.pool)
+            .await?;
+
+        registration_updated(result.rows_affected(), client_id)
+    }
+
+    async fn update_registration_topics(
+        &self,
+        client_id: &str,
+        subscribe: Vec<&str>,
+        unsubscribe: Vec<&str>,
+    ) -> Result<(), StoreError> {
+        let result = sqlx::query(&update_registration_topics_sql::<Postgres>())
+            .bind(client_id)
+            .bind(subscribe)
+            .bind(unsubscribe)
+            .bind(Utc::now())
+            .execute(&self.pool)
+            .await?;
+
+        registration_updated(result.rows_affected(), client_id)
+    }
+
+    async fn get_registration(&self, client_id: &str) -> Result<Registration, StoreError> {
+        let registration = sqlx::query_as::<_, RegistrationRow<Postgres>>(SELECT_REGISTRATION)
+            .bind(client_id)
+            .bind(Utc::now())
+            .fetch_optional(&self.pool)
+            .await?;
+
+        registration
+            .map(Registration::from)
+            .ok_or(StoreError::NotFound(
+                "registration".to_string(),
+                client_id.to_string(),
+            ))
+    }
+
+    async fn delete_registration(&self, client_id: &str) -> Result<u64, StoreError> {
+        let result = sqlx::query("DELETE FROM registrations WHERE client_id = $1")
+            .bind(client_id)
+            .execute(&self.pool)
+            .await?;
+
+        Ok(result.rows_affected())
+    }
+
+    async fn delete_expired_registrations(&self, now: DateTime<Utc>) -> Result<u64, StoreError> {
+        let result = sqlx::query("DELETE FROM registrations WHERE expires_at <= $1")
+            .bind(now)
+            .execute(&self.pool)
+            .await?;
+
+        Ok(result.rows_affected())
+    }
+
+    async fn ping(&self) -> Result<(), StoreError> {
+        sqlx::query("SELECT 1").execute(&self.pool).await?;
+        Ok(())
+    }
+
+    async fn close(&self) {
+        self.pool.close().await;
+    }
+}
+
+#[async_trait]
+impl InvalidationBus for PostgresStore {
+    async fn publish(&self, client_id: &str) -> Result<(), StoreError> {
+        sqlx::query("SELECT pg_notify($1, $2)")
+            .bind(INVALIDATION_CHANNEL)
+            .bind(client_id)
+            .execute(&self.pool)
+            .await?;
+
+        Ok(())
+    }
+
+    async fn subscribe(&self) -> Result<InvalidationStream, StoreError> {
+        let mut listener = PgListener::connect_with(&self.pool).await?;
+        listener.listen(INVALIDATION_CHANNEL).await?;
+
+        let invalidations = stream::unfold(listener, |mut listener| async move {
+            let invalidation = match listener.try_recv().await {
+                Ok(Some(notification)) => Ok(Invalidation::Registration(Arc::from(
+                    notification.payload(),
+                ))),
+                // The connection is reestablished by the next call, the
+                // notifications sent meanwhile are lost
+                Ok(None) => Ok(Invalidation::All),
+                Err(e) => Err(e.into()),
+            };
+            Some((invalidation, listener))
+        });
+
+        Ok(invalidations.boxed())
+    }
+}
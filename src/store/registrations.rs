@@ -1,10 +1,11 @@
 use {
     super::StoreError,
     async_trait::async_trait,
+    chrono::{DateTime, Utc},
     serde::{Deserialize, Serialize},
     std::sync::Arc,
     wither::{
-        bson::{doc, oid::ObjectId},
+        bson::{self, doc, oid::ObjectId},
         Model,
     },
 };
@@ -12,7 +13,8 @@ use {
 #[derive(Clone, Debug, Model, Serialize, Deserialize, PartialEq, Eq)]
 #[model(
     collection_name = "Registrations",
-    index(keys = r#"doc!{"client_id": 1}"#, options = r#"doc!{"unique": true}"#)
+    index(keys = r#"doc!{"client_id": 1}"#, options = r#"doc!{"unique": true}"#),
+    index(keys = r#"doc!{"expires_at": 1}"#)
 )]
 pub struct Registration {
     /// MongoDB's default `_id` field.
@@ -22,17 +24,68 @@ pub struct Registration {
     pub client_id: Arc<str>,
     /// The registered tags
     pub tags: Vec<Arc<str>>,
+    /// The topics the client subscribed to, only updated by its relay as
+    /// clients can't vouch for their own subscriptions
+    #[serde(default)]
+    pub topics: Vec<Arc<str>>,
     /// The registered relay_url
     pub relay_url: Arc<str>,
+    /// When the client first registered, unset for the registrations stored
+    /// before it was kept.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub created_at: Option<bson::DateTime>,
+    /// When the client last registered, unset for the registrations stored
+    /// before it was kept.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub updated_at: Option<bson::DateTime>,
+    /// When the registration expires, kept forever if unset.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub expires_at: Option<bson::DateTime>,
 }
 
 #[async_trait]
 pub trait RegistrationStore: 'static + Send + Sync {
+    /// Stores the client's registration, keeping its creation time and topics
+    /// and replacing its expiry. The tags are left untouched unless provided.
     async fn upsert_registration(
         &self,
         client_id: &str,
-        tags: Vec<&str>,
+        tags: Option<Vec<&str>>,
         relay_url: &str,
+        expires_at: Option<DateTime<Utc>>,
     ) -> Result<(), StoreError>;
+    /// Atomically removes the `remove_tags` from the client's registration and
+    /// adds the missing `add_tags`, so that concurrent updates don't lose each
+    /// other's tags, and replaces its relay and expiry. Unlike
+    /// `upsert_registration`, a deleted or expired registration isn't
+    /// recreated.
+    async fn update_registration(
+        &self,
+        client_id: &str,
+        add_tags: Vec<&str>,
+        remove_tags: Vec<&str>,
+        relay_url: &str,
+        expires_at: Option<DateTime<Utc>>,
+    ) -> Result<(), StoreError>;
+    /// Atomically removes the `unsubscribe` topics from the client's
+    /// registration and adds the missing `subscribe` ones. A deleted or expired
+    /// registration isn't recreated.
+    async fn update_registration_topics(
+        &self,
+        client_id: &str,
+        subscribe: Vec<&str>,
+        unsubscribe: Vec<&str>,
+    ) -> Result<(), StoreError>;
+    /// Returns the client's registration, unless it has expired.
     async fn get_registration(&self, client_id: &str) -> Result<Registration, StoreError>;
+    /// Deletes the client's registration, returning how many were deleted.
+    async fn delete_registration(&self, client_id: &str) -> Result<u64, StoreError>;
+    /// Deletes the registrations expired at `now`, returning how many were
+    /// deleted.
+    async fn delete_expired_registrations(&self, now: DateTime<Utc>) -> Result<u64, StoreError>;
+    /// Checks that the store is reachable.
+    async fn ping(&self) -> Result<(), StoreError>;
+    /// Waits for the pending operations to complete and closes the
+    /// connections, the store can't be used afterwards.
+    async fn close(&self);
 }
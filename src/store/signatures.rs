@@ -0,0 +1,23 @@
+use {
+    super::StoreError,
+    async_trait::async_trait,
+    chrono::{DateTime, Utc},
+};
+
+#[async_trait]
+pub trait SignatureStore: 'static + Send + Sync {
+    /// Records the signature of an accepted Relay request until `expires_at`,
+    /// returns `false` when it was already recorded, i.e. the request is
+    /// replayed, on this instance or another one.
+    async fn record_signature(
+        &self,
+        signature: &str,
+        expires_at: DateTime<Utc>,
+    ) -> Result<bool, StoreError>;
+    /// Forgets the signature of a failed request, so that the Relay can retry
+    /// it.
+    async fn delete_signature(&self, signature: &str) -> Result<(), StoreError>;
+    /// Deletes the signatures expired at `now`, returning how many were
+    /// deleted.
+    async fn delete_expired_signatures(&self, now: DateTime<Utc>) -> Result<u64, StoreError>;
+}
@@ -0,0 +1,436 @@
+//! The row mapping and query building shared by the SQL stores, the
+//! databases only differ in how they store the timestamps and lists.
+
+use {
+    crate::store::{
+        messages::{Message, MessageCursor, MessageFilter, NewMessage, StoreMessages, TopicOrigin},
+        registrations::Registration,
+        StoreError,
+    },
+    chrono::{DateTime, Utc},
+    sqlx::{
+        database::HasArguments,
+        postgres::Postgres,
+        query::QueryAs,
+        sqlite::Sqlite,
+        types::Json,
+        Database,
+        Decode,
+        Encode,
+        FromRow,
+        QueryBuilder,
+        Type,
+    },
+    std::sync::Arc,
+    wither::bson,
+};
+
+/// Stores a message, or replaces the one with the same ID, returning its
+/// position. SQLite accepts the numbered parameters too.
+pub(crate) const UPSERT_MESSAGE: &str =
+    "INSERT INTO messages (ts, method, client_id, topic, message_id, message, tag, expires_at) \
+     VALUES ($1, $2, $3, $4, $5, $6, $7, $8) ON CONFLICT (client_id, topic, message_id) DO UPDATE \
+     SET ts = excluded.ts, method = excluded.method, message = excluded.message, tag = \
+     excluded.tag, expires_at = excluded.expires_at RETURNING ts, id";
+
+pub(crate) const MESSAGE_COLUMNS: &str =
+    "id, ts, method, client_id, topic, message_id, message, tag, expires_at";
+
+/// Loads the client's registration unless it expired at the bound time.
+pub(crate) const SELECT_REGISTRATION: &str =
+    "SELECT client_id, tags, topics, relay_url, created_at, updated_at, expires_at FROM \
+     registrations WHERE client_id = $1 AND (expires_at IS NULL OR expires_at > $2)";
+
+/// How a SQL database stores the values the shared queries bind and read.
+pub(crate) trait SqlDialect: Database {
+    /// The column type of the timestamps.
+    type Timestamp: for<'q> Encode<'q, Self>
+        + for<'r> Decode<'r, Self>
+        + Type<Self>
+        + Send
+        + 'static;
+    /// The column type of the registration's tags and topics.
+    type List: for<'r> Decode<'r, Self> + Type<Self>;
+    /// The literal of an empty list.
+    const EMPTY_LIST: &'static str;
+
+    fn timestamp(ts: DateTime<Utc>) -> Self::Timestamp;
+    fn to_chrono(ts: Self::Timestamp) -> DateTime<Utc>;
+    fn list_values(list: Self::List) -> Vec<String>;
+    /// The expression of the registration's list `column` without the values
+    /// bound third, followed by the missing values bound second, in order.
+    fn updated_list(column: &str) -> String;
+}
+
+impl SqlDialect for Sqlite {
+    /// A JSON array.
+    type List = Json<Vec<String>>;
+    /// The number of milliseconds since Epoch.
+    type Timestamp = i64;
+
+    const EMPTY_LIST: &'static str = "'[]'";
+
+    fn timestamp(ts: DateTime<Utc>) -> i64 {
+        ts.timestamp_millis()
+    }
+
+    fn to_chrono(ts: i64) -> DateTime<Utc> {
+        bson::DateTime::from_millis(ts).to_chrono()
+    }
+
+    fn list_values(list: Json<Vec<String>>) -> Vec<String> {
+        list.0
+    }
+
+    fn updated_list(column: &str) -> String {
+        format!(
+            "(SELECT json_group_array(value) FROM (SELECT value FROM (SELECT value, 0 AS added, \
+             key FROM json_each(registrations.{column}) WHERE value NOT IN (SELECT value FROM \
+             json_each($3)) UNION ALL SELECT value, 1, key FROM json_each($2) WHERE value NOT IN \
+             (SELECT value FROM json_each(registrations.{column}))) ORDER BY added, key))"
+        )
+    }
+}
+
+impl SqlDialect for Postgres {
+    type List = Vec<String>;
+    type Timestamp = DateTime<Utc>;
+
+    const EMPTY_LIST: &'static str = "'{}'";
+
+    fn timestamp(ts: DateTime<Utc>) -> DateTime<Utc> {
+        ts
+    }
+
+    fn to_chrono(ts: DateTime<Utc>) -> DateTime<Utc> {
+        ts
+    }
+
+    fn list_values(list: Vec<String>) -> Vec<String> {
+        list
+    }
+
+    fn updated_list(column: &str) -> String {
+        format!(
+            "ARRAY(SELECT value FROM unnest(registrations.{column}) WITH ORDINALITY AS t(value, \
+             i) WHERE value <> ALL($3) ORDER BY i) || ARRAY(SELECT DISTINCT unnest($2::TEXT[]) \
+             EXCEPT SELECT unnest(registrations.{column}))"
+        )
+    }
+}
+
+#[derive(FromRow)]
+pub(crate) struct MessageRow<DB: SqlDialect> {
+    id: i64,
+    ts: DB::Timestamp,
+    method: String,
+    client_id: String,
+    topic: String,
+    message_id: String,
+    message: String,
+    tag: Option<i64>,
+    expires_at: Option<DB::Timestamp>,
+}
+
+impl<DB: SqlDialect> From<MessageRow<DB>> for Message {
+    fn from(row: MessageRow<DB>) -> Self {
+        let cursor = MessageCursor::from(CursorRow::<DB> {
+            ts: row.ts,
+            id: row.id,
+        });
+
+        Message {
+            id: None,
+            timestamp: bson::DateTime::from_chrono(cursor.ts),
+            method: row.method.into(),
+            client_id: row.client_id.into(),
+            topic: row.topic.into(),
+            message_id: row.message_id.into(),
+            message: row.message.into(),
+            tag: row.tag.and_then(|tag| u32::try_from(tag).ok()),
+            expires_at: row
+                .expires_at
+                .map(|expires_at| bson::DateTime::from_chrono(DB::to_chrono(expires_at))),
+            cursor: Some(cursor),
+        }
+    }
+}
+
+/// The position of a message.
+#[derive(FromRow)]
+pub(crate) struct CursorRow<DB: SqlDialect> {
+    ts: DB::Timestamp,
+    id: i64,
+}
+
+impl<DB: SqlDialect> From<CursorRow<DB>> for MessageCursor {
+    fn from(row: CursorRow<DB>) -> Self {
+        MessageCursor {
+            ts: DB::to_chrono(row.ts),
+            id: row.id.to_string().into(),
+        }
+    }
+}
+
+#[derive(FromRow)]
+pub(crate) struct RegistrationRow<DB: SqlDialect> {
+    client_id: String,
+    tags: DB::List,
+    topics: DB::List,
+    relay_url: String,
+    created_at: Option<DB::Timestamp>,
+    updated_at: Option<DB::Timestamp>,
+    expires_at: Option<DB::Timestamp>,
+}
+
+impl<DB: SqlDialect> From<RegistrationRow<DB>> for Registration {
+    fn from(row: RegistrationRow<DB>) -> Self {
+        let to_bson = |ts: DB::Timestamp| bson::DateTime::from_chrono(DB::to_chrono(ts));
+
+        Registration {
+            id: None,
+            client_id: row.client_id.into(),
+            tags: DB::list_values(row.tags)
+                .into_iter()
+                .map(Arc::from)
+                .collect(),
+            topics: DB::list_values(row.topics)
+                .into_iter()
+                .map(Arc::from)
+                .collect(),
+            relay_url: row.relay_url.into(),
+            created_at: row.created_at.map(to_bson),
+            updated_at: row.updated_at.map(to_bson),
+            expires_at: row.expires_at.map(to_bson),
+        }
+    }
+}
+
+/// Builds the upsert of the message, stored at the current time.
+pub(crate) fn upsert_message_query<'q, DB>(
+    message: &'q NewMessage,
+) -> QueryAs<'q, DB, CursorRow<DB>, <DB as HasArguments<'q>>::Arguments>
+where
+    DB: SqlDialect,
+    Option<i64>: Encode<'q, DB> + Type<DB>,
+    Option<DB::Timestamp>: Encode<'q, DB> + Type<DB>,
+    &'q str: Encode<'q, DB> + Type<DB>,
+    CursorRow<DB>: for<'r> FromRow<'r, DB::Row>,
+{
+    sqlx::query_as(UPSERT_MESSAGE)
+        .bind(DB::timestamp(Utc::now()))
+        .bind(message.method.as_ref())
+        .bind(message.client_id.as_ref())
+        .bind(message.topic.as_ref())
+        .bind(message.message_id.as_ref())
+        .bind(message.message.as_ref())
+        .bind(message.tag.map(i64::from))
+        .bind(message.expires_at.map(DB::timestamp))
+}
+
+/// Stores the client's registration, the tags are left untouched unless
+/// explicitly provided and the topics are kept.
+pub(crate) fn upsert_registration_sql<DB: SqlDialect>() -> String {
+    format!(
+        "INSERT INTO registrations (client_id, tags, topics, relay_url, created_at, updated_at, \
+         expires_at) VALUES ($1, COALESCE($2, {empty}), {empty}, $3, $4, $4, $5) ON CONFLICT \
+         (client_id) DO UPDATE SET tags = COALESCE($2, registrations.tags), relay_url = \
+         excluded.relay_url, updated_at = excluded.updated_at, expires_at = excluded.expires_at",
+        empty = DB::EMPTY_LIST,
+    )
+}
+
+/// Updates the client's live registration without recreating it: the tags
+/// bound second are added and the ones bound third removed, and its relay and
+/// expiry are replaced.
+pub(crate) fn update_registration_sql<DB: SqlDialect>() -> String {
+    format!(
+        "UPDATE registrations SET tags = {tags}, relay_url = $4, updated_at = $5, expires_at = $6 \
+         WHERE client_id = $1 AND (expires_at IS NULL OR expires_at > $5)",
+        tags = DB::updated_list("tags"),
+    )
+}
+
+/// Updates the topics of the client's live registration: the topics bound
+/// second are added and the ones bound third removed.
+pub(crate) fn update_registration_topics_sql<DB: SqlDialect>() -> String {
+    format!(
+        "UPDATE registrations SET topics = {topics} WHERE client_id = $1 AND (expires_at IS NULL \
+         OR expires_at > $4)",
+        topics = DB::updated_list("topics"),
+    )
+}
+
+/// Fails when an update didn't find the client's registration.
+pub(crate) fn registration_updated(rows_affected: u64, client_id: &str) -> Result<(), StoreError> {
+    if rows_affected == 0 {
+        return Err(StoreError::NotFound(
+            "registration".to_string(),
+            client_id.to_string(),
+        ));
+    }
+
+    Ok(())
+}
+
+/// Builds the query of a page of the topic's messages from `origin`, one
+/// more message is loaded to tell whether there is a next page.
+pub(crate) fn messages_query<'q, DB>(
+    topic: &'q str,
+    filter: &'q MessageFilter,
+    origin: Option<&MessageCursor>,
+    message_count: usize,
+    comparator: &str,
+    sort_order: &str,
+) -> Result<QueryBuilder<'q, DB>, StoreError>
+where
+    DB: SqlDialect,
+    i64: Encode<'q, DB> + Type<DB>,
+    &'q str: Encode<'q, DB> + Type<DB>,
+{
+    let mut query = QueryBuilder::new(format!(
+        "SELECT {MESSAGE_COLUMNS} FROM messages WHERE topic = "
+    ));
+    query.push_bind(topic);
+    if let Some(origin) = origin {
+        // The `id` breaks the ties between identical timestamps
+        query
+            .push(format!(" AND (ts, id) {comparator} ("))
+            .push_bind(DB::timestamp(origin.ts))
+            .push(", ")
+            .push_bind(parse_cursor_id(origin)?)
+            .push(")");
+    }
+    push_filter(&mut query, filter);
+
+    query
+        .push(format!(" ORDER BY ts {sort_order}, id {sort_order} LIMIT "))
+        .push_bind(message_count as i64 + 1);
+
+    Ok(query)
+}
+
+/// Returns the page of the messages loaded by `messages_query`.
+pub(crate) fn messages_page(mut messages: Vec<Message>, message_count: usize) -> StoreMessages {
+    if messages.len() > message_count {
+        messages.pop();
+        let next_cursor = messages.last().and_then(|message| message.cursor.clone());
+        return StoreMessages {
+            messages,
+            next_cursor,
+        };
+    }
+
+    StoreMessages {
+        messages,
+        next_cursor: None,
+    }
+}
+
+/// Builds the query of the messages following each topic's origin, in a
+/// single page split by `split_topics_messages`.
+pub(crate) fn topics_messages_query<'q, DB>(
+    origins: &'q [TopicOrigin],
+    message_count: usize,
+) -> Result<QueryBuilder<'q, DB>, StoreError>
+where
+    DB: SqlDialect,
+    i64: Encode<'q, DB> + Type<DB>,
+    &'q str: Encode<'q, DB> + Type<DB>,
+{
+    let mut query = QueryBuilder::new(format!("SELECT {MESSAGE_COLUMNS} FROM messages WHERE "));
+    let mut branches = query.separated(" OR ");
+    // The topics loaded from their start share a single `IN` branch, the
+    // others each need their own position
+    let from_start: Vec<&str> = origins
+        .iter()
+        .filter(|origin| origin.origin.is_none())
+        .map(|origin| origin.topic.as_ref())
+        .collect();
+    if !from_start.is_empty() {
+        branches.push("topic IN (");
+        for (index, topic) in from_start.into_iter().enumerate() {
+            if index > 0 {
+                branches.push_unseparated(", ");
+            }
+            branches.push_bind_unseparated(topic);
+        }
+        branches.push_unseparated(")");
+    }
+    for TopicOrigin { topic, origin } in origins {
+        if let Some(origin) = origin {
+            branches
+                .push("(topic = ")
+                .push_bind_unseparated(topic.as_ref())
+                .push_unseparated(" AND (ts, id) > (")
+                .push_bind_unseparated(DB::timestamp(origin.ts))
+                .push_unseparated(", ")
+                .push_bind_unseparated(parse_cursor_id(origin)?)
+                .push_unseparated("))");
+        }
+    }
+
+    query
+        .push(" ORDER BY ts ASC, id ASC LIMIT ")
+        .push_bind(message_count as i64 + 1);
+
+    Ok(query)
+}
+
+/// Builds the query of which of the non-empty `topics` the client has
+/// messages in.
+pub(crate) fn topics_with_messages_query<'q, DB>(
+    client_id: &'q str,
+    topics: &[&'q str],
+) -> QueryBuilder<'q, DB>
+where
+    DB: SqlDialect,
+    &'q str: Encode<'q, DB> + Type<DB>,
+{
+    let mut query = QueryBuilder::new("SELECT DISTINCT topic FROM messages WHERE client_id = ");
+    query.push_bind(client_id).push(" AND topic IN (");
+    let mut separated = query.separated(", ");
+    for topic in topics {
+        separated.push_bind(*topic);
+    }
+    separated.push_unseparated(")");
+
+    query
+}
+
+/// Appends the filter conditions to a query on the messages.
+fn push_filter<'q, DB>(query: &mut QueryBuilder<'q, DB>, filter: &'q MessageFilter)
+where
+    DB: SqlDialect,
+    i64: Encode<'q, DB> + Type<DB>,
+    &'q str: Encode<'q, DB> + Type<DB>,
+{
+    if let Some(since) = filter.since {
+        query.push(" AND ts >= ").push_bind(DB::timestamp(since));
+    }
+    if let Some(until) = filter.until {
+        query.push(" AND ts < ").push_bind(DB::timestamp(until));
+    }
+    if let Some(method) = &filter.method {
+        query.push(" AND method = ").push_bind(method.as_ref());
+    }
+    if let Some(client_id) = &filter.client_id {
+        query
+            .push(" AND client_id = ")
+            .push_bind(client_id.as_ref());
+    }
+    if let Some(tags) = &filter.tags {
+        query
+            .push(" AND tag BETWEEN ")
+            .push_bind(i64::from(*tags.start()))
+            .push(" AND ")
+            .push_bind(i64::from(*tags.end()));
+    }
+}
+
+fn parse_cursor_id(cursor: &MessageCursor) -> Result<i64, StoreError> {
+    cursor
+        .id
+        .parse()
+        .map_err(|_| StoreError::InvalidCursor(format!("unknown message ID {}", cursor.id)))
+}
@@ -0,0 +1,429 @@
+use {
+    crate::{
+        config::Configuration,
+        store::{
+            messages::{
+                split_topics_messages,
+                Message,
+                MessageCursor,
+                MessageFilter,
+                MessagesStore,
+                NewMessage,
+                StoreMessages,
+                TopicOrigin,
+            },
+            registrations::{Registration, RegistrationStore},
+            signatures::SignatureStore,
+            sql::{
+                messages_page,
+                messages_query,
+                registration_updated,
+                topics_messages_query,
+                topics_with_messages_query,
+                update_registration_sql,
+                update_registration_topics_sql,
+                upsert_message_query,
+                upsert_registration_sql,
+                MessageRow,
+                RegistrationRow,
+                SELECT_REGISTRATION,
+            },
+            StoreError,
+        },
+    },
+    async_trait::async_trait,
+    chrono::{DateTime, Utc},
+    sqlx::{
+        sqlite::{SqliteConnectOptions, SqlitePool, SqlitePoolOptions},
+        types::Json,
+        Connection,
+        Sqlite,
+    },
+    std::{str::FromStr, sync::Arc},
+};
+
+/// An embedded store backed by a SQLite database file, for deployments that
+/// don't want to run a MongoDB instance.
+#[derive(Clone)]
+pub struct SqliteStore {
+    pool: SqlitePool,
+}
+
+impl SqliteStore {
+    pub async fn new(config: &Configuration) -> anyhow::Result<Self> {
+        let options =
+            SqliteConnectOptions::from_str(&config.sqlite_address)?.create_if_missing(true);
+        let pool = SqlitePoolOptions::new().connect_with(options).await?;
+
+        sqlx::migrate!("./migrations/sqlite").run(&pool).await?;
+
+        Ok(Self { pool })
+    }
+
+    async fn get_messages(
+        &self,
+        topic: &str,
+        filter: &MessageFilter,
+        origin: Option<&MessageCursor>,
+        message_count: usize,
+        comparator: &str,
+        sort_order: &str,
+    ) -> Result<StoreMessages, StoreError> {
+        let rows = messages_query(topic, filter, origin, message_count, comparator, sort_order)?
+            .build_query_as::<MessageRow<Sqlite>>()
+            .fetch_all(&self.pool)
+            .await?;
+
+        Ok(messages_page(
+            rows.into_iter().map(Message::from).collect(),
+            message_count,
+        ))
+    }
+}
+
+#[async_trait]
+impl MessagesStore for SqliteStore {
+    async fn upsert_message(&self, message: &NewMessage) -> Result<MessageCursor, StoreError> {
+        // Fetching all the rows steps the statement to its end, `fetch_one`
+        // leaves the write pending on its connection, unseen by the others
+        let rows = upsert_message_query::<Sqlite>(message)
+            .fetch_all(&self.pool)
+            .await?;
+        let row = rows.into_iter().next().ok_or(sqlx::Error::RowNotFound)?;
+
+        Ok(row.into())
+    }
+
+    async fn upsert_messages(
+        &self,
+        messages: &[NewMessage],
+    ) -> Result<Vec<Result<MessageCursor, StoreError>>, StoreError> {
+        // A single transaction with a savepoint per message, a message failing
+        // on its own is rolled back without the rest of the batch
+        let mut transaction = self.pool.begin().await?;
+
+        let mut cursors = Vec::with_capacity(messages.len());
+        for message in messages {
+            let mut savepoint = transaction.begin().await?;
+            // Fetching all the rows steps the statement to its end
+            match upsert_message_query::<Sqlite>(message)
+                .fetch_all(&mut savepoint)
+                .await
+            {
+                Ok(rows) => {
+                    savepoint.commit().await?;
+                    let row = rows.into_iter().next().ok_or(sqlx::Error::RowNotFound)?;
+                    cursors.push(Ok(row.into()));
+                }
+                Err(e @ sqlx::Error::Database(_)) => {
+                    savepoint.rollback().await?;
+                    cursors.push(Err(e.into()));
+                }
+                Err(e) => return Err(e.into()),
+            }
+        }
+
+        transaction.commit().await?;
+
+        Ok(cursors)
+    }
+
+    async fn get_messages_after(
+        &self,
+        topic: &str,
+        filter: &MessageFilter,
+        origin: Option<&MessageCursor>,
+        message_count: usize,
+    ) -> Result<StoreMessages, StoreError> {
+        self.get_messages(topic, filter, origin, message_count, ">", "ASC")
+            .await
+    }
+
+    async fn get_messages_before(
+        &self,
+        topic: &str,
+        filter: &MessageFilter,
+        origin: Option<&MessageCursor>,
+        message_count: usize,
+    ) -> Result<StoreMessages, StoreError> {
+        self.get_messages(topic, filter, origin, message_count, "<", "DESC")
+            .await
+    }
+
+    async fn get_topics_messages_after(
+        &self,
+        origins: &[TopicOrigin],
+        message_count: usize,
+    ) -> Result<Vec<StoreMessages>, StoreError> {
+        if origins.is_empty() {
+            return Ok(vec![]);
+        }
+
+        let rows = topics_messages_query(origins, message_count)?
+            .build_query_as::<MessageRow<Sqlite>>()
+            .fetch_all(&self.pool)
+            .await?;
+        let messages = rows.into_iter().map(Message::from).collect();
+
+        Ok(split_topics_messages(origins, messages, message_count))
+    }
+
+    async fn has_messages(&self, client_id: &str, topic: &str) -> Result<bool, StoreError> {
+        let row: Option<i64> =
+            sqlx::query_scalar("SELECT 1 FROM messages WHERE client_id = ? AND topic = ? LIMIT 1")
+                .bind(client_id)
+                .bind(topic)
+                .fetch_optional(&self.pool)
+                .await?;
+
+        Ok(row.is_some())
+    }
+
+    async fn has_message(
+        &self,
+        client_id: &str,
+        topic: &str,
+        message_id: &str,
+    ) -> Result<bool, StoreError> {
+        let row: Option<i64> = sqlx::query_scalar(
+            "SELECT 1 FROM messages WHERE client_id = ? AND topic = ? AND message_id = ?",
+        )
+        .bind(client_id)
+        .bind(topic)
+        .bind(message_id)
+        .fetch_optional(&self.pool)
+        .await?;
+
+        Ok(row.is_some())
+    }
+
+    async fn topics_with_messages(
+        &self,
+        client_id: &str,
+        topics: &[&str],
+    ) -> Result<Vec<Arc<str>>, StoreError> {
+        // `IN ()` isn't valid SQL
+        if topics.is_empty() {
+            return Ok(vec![]);
+        }
+
+        let rows: Vec<(String,)> = topics_with_messages_query::<Sqlite>(client_id, topics)
+            .build_query_as()
+            .fetch_all(&self.pool)
+            .await?;
+
+        Ok(rows.into_iter().map(|(topic,)| Arc::from(topic)).collect())
+    }
+
+    async fn count_messages(
+        &self,
+        client_id: &str,
+        topic: Option<&str>,
+    ) -> Result<u64, StoreError> {
+        let count: i64 = sqlx::query_scalar(
+            "SELECT COUNT(*) FROM messages WHERE client_id = ?1 AND (?2 IS NULL OR topic = ?2)",
+        )
+        .bind(client_id)
+        .bind(topic)
+        .fetch_one(&self.pool)
+        .await?;
+
+        Ok(count.try_into().unwrap_or_default())
+    }
+
+    async fn delete_message(
+        &self,
+        client_id: &str,
+        topic: &str,
+        message_id: &str,
+    ) -> Result<u64, StoreError> {
+        let result = sqlx::query(
+            "DELETE FROM messages WHERE client_id = ? AND topic = ? AND message_id = ?",
+        )
+        .bind(client_id)
+        .bind(topic)
+        .bind(message_id)
+        .execute(&self.pool)
+        .await?;
+
+        Ok(result.rows_affected())
+    }
+
+    async fn delete_topic_messages(&self, client_id: &str, topic: &str) -> Result<u64, StoreError> {
+        let result = sqlx::query("DELETE FROM messages WHERE client_id = ? AND topic = ?")
+            .bind(client_id)
+            .bind(topic)
+            .execute(&self.pool)
+            .await?;
+
+        Ok(result.rows_affected())
+    }
+
+    async fn delete_client_messages(&self, client_id: &str) -> Result<u64, StoreError> {
+        let result = sqlx::query("DELETE FROM messages WHERE client_id = ?")
+            .bind(client_id)
+            .execute(&self.pool)
+            .await?;
+
+        Ok(result.rows_affected())
+    }
+
+    async fn delete_expired_messages(&self, now: DateTime<Utc>) -> Result<u64, StoreError> {
+        let result = sqlx::query("DELETE FROM messages WHERE expires_at <= ?")
+            .bind(now.timestamp_millis())
+            .execute(&self.pool)
+            .await?;
+
+        Ok(result.rows_affected())
+    }
+
+    async fn ping(&self) -> Result<(), StoreError> {
+        sqlx::query("SELECT 1").execute(&self.pool).await?;
+        Ok(())
+    }
+
+    async fn close(&self) {
+        self.pool.close().await;
+    }
+}
+
+#[async_trait]
+impl SignatureStore for SqliteStore {
+    async fn record_signature(
+        &self,
+        signature: &str,
+        expires_at: DateTime<Utc>,
+    ) -> Result<bool, StoreError> {
+        let result = sqlx::query(
+            "INSERT INTO relay_signatures (signature, expires_at) VALUES (?, ?) ON CONFLICT \
+             (signature) DO NOTHING",
+        )
+        .bind(signature)
+        .bind(expires_at.timestamp_millis())
+        .execute(&self.pool)
+        .await?;
+
+        Ok(result.rows_affected() == 1)
+    }
+
+    async fn delete_signature(&self, signature: &str) -> Result<(), StoreError> {
+        sqlx::query("DELETE FROM relay_signatures WHERE signature = ?")
+            .bind(signature)
+            .execute(&self.pool)
+            .await?;
+
+        Ok(())
+    }
+
+    async fn delete_expired_signatures(&self, now: DateTime<Utc>) -> Result<u64, StoreError> {
+        let result = sqlx::query("DELETE FROM relay_signatures WHERE expires_at <= ?")
+            .bind(now.timestamp_millis())
+            .execute(&self.pool)
+            .await?;
+
+        Ok(result.rows_affected())
+    }
+}
+
+#[async_trait]
+impl RegistrationStore for SqliteStore {
+    async fn upsert_registration(
+        &self,
+        client_id: &str,
+        tags: Option<Vec<&str>>,
+        relay_url: &str,
+        expires_at: Option<DateTime<Utc>>,
+    ) -> Result<(), StoreError> {
+        sqlx::query(&upsert_registration_sql::<Sqlite>())
+            .bind(client_id)
+            .bind(tags.map(Json))
+            .bind(relay_url)
+            .bind(Utc::now().timestamp_millis())
+            .bind(expires_at.map(|expires_at| expires_at.timestamp_millis()))
+            .execute(&self.pool)
+            .await?;
+
+        Ok(())
+    }
+
+    async fn update_registration(
+        &self,
+        client_id: &str,
+        add_tags: Vec<&str>,
+        remove_tags: Vec<&str>,
+        relay_url: &str,
+        expires_at: Option<DateTime<Utc>>,
+    ) -> Result<(), StoreError> {
+        // A single statement, so concurrent updates can't interleave
+        let result = sqlx::query(&update_registration_sql::<Sqlite>())
+            .bind(client_id)
+            .bind(Json(add_tags))
+            .bind(Json(remove_tags))
+            .bind(relay_url)
+            .bind(Utc::now().timestamp_millis())
+            .bind(expires_at.map(|expires_at| expires_at.timestamp_millis()))
+            .execute(&self.pool)
+            .await?;
+
+        registration_updated(result.rows_affected(), client_id)
+    }
+
+    async fn update_registration_topics(
+        &self,
+        client_id: &str,
+        subscribe: Vec<&str>,
+        unsubscribe: Vec<&str>,
+    ) -> Result<(), StoreError> {
+        let result = sqlx::query(&update_registration_topics_sql::<Sqlite>())
+            .bind(client_id)
+            .bind(Json(subscribe))
+            .bind(Json(unsubscribe))
+            .bind(Utc::now().timestamp_millis())
+            .execute(&self.pool)
+            .await?;
+
+        registration_updated(result.rows_affected(), client_id)
+    }
+
+    async fn get_registration(&self, client_id: &str) -> Result<Registration, StoreError> {
+        let registration = sqlx::query_as::<_, RegistrationRow<Sqlite>>(SELECT_REGISTRATION)
+            .bind(client_id)
+            .bind(Utc::now().timestamp_millis())
+            .fetch_optional(&self.pool)
+            .await?;
+
+        registration
+            .map(Registration::from)
+            .ok_or(StoreError::NotFound(
+                "registration".to_string(),
+                client_id.to_string(),
+            ))
+    }
+
+    async fn delete_registration(&self, client_id: &str) -> Result<u64, StoreError> {
+        let result = sqlx::query("DELETE FROM registrations WHERE client_id = ?")
+            .bind(client_id)
+            .execute(&self.pool)
+            .await?;
+
+        Ok(result.rows_affected())
+    }
+
+    async fn delete_expired_registrations(&self, now: DateTime<Utc>) -> Result<u64, StoreError> {
+        let result = sqlx::query("DELETE FROM registrations WHERE expires_at <= ?")
+            .bind(now.timestamp_millis())
+            .execute(&self.pool)
+            .await?;
+
+        Ok(result.rows_affected())
+    }
+
+    async fn ping(&self) -> Result<(), StoreError> {
+        sqlx::query("SELECT 1").execute(&self.pool).await?;
+        Ok(())
+    }
+
+    async fn close(&self) {
+        self.pool.close().await;
+    }
+}
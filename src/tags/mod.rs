@@ -1,24 +1,251 @@
+use std::{collections::HashSet, ops::RangeInclusive, str::FromStr};
+
 const TAG_WILDCARD: char = '*';
+const TAG_PREFIX: char = '%';
+const TAG_RANGE_SEPARATOR: char = '-';
+const TAG_NEGATION: char = '!';
+/// The number of digits of `u32::MAX`.
+const TAG_MAX_DIGITS: usize = 10;
+
+#[derive(Debug, Clone, PartialEq, Eq, thiserror::Error)]
+pub enum TagPatternError {
+    #[error("the pattern is empty")]
+    Empty,
+
+    #[error("`{0}` is neither a digit nor a `*` wildcard")]
+    InvalidCharacter(char),
+
+    #[error("`{0}` is not a valid range, expected `<min>-<max>` with min <= max")]
+    InvalidRange(String),
+}
+
+/// A validated tag pattern, one of:
+/// - `1234`, `12*4`: the tags of the same length, `*` matching any digit.
+/// - `1000-1999`: the tags in the inclusive range.
+/// - `12%`, `1*%`: the tags starting with the digits, whatever their length.
+///
+/// Prefixed with `!`, the pattern matches the tags it otherwise doesn't.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TagPattern {
+    negated: bool,
+    kind: PatternKind,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum PatternKind {
+    Digits(Box<str>),
+    Prefix(Box<str>),
+    Range(RangeInclusive<u32>),
+}
+
+impl TagPattern {
+    pub fn is_negated(&self) -> bool {
+        self.negated
+    }
+
+    /// Returns the tags matched by the pattern when they form a single range,
+    /// i.e. for the exact tags, the ranges and the digits followed only by
+    /// wildcards (`11**` being `1100-1199`).
+    pub fn as_range(&self) -> Option<RangeInclusive<u32>> {
+        if self.negated {
+            return None;
+        }
 
-/// Matches a tag against a pattern.
-/// The pattern can contain '*' as a wildcard to match any number.
+        match &self.kind {
+            PatternKind::Range(range) => Some(range.clone()),
+            PatternKind::Digits(pattern) => {
+                let digits = pattern.trim_end_matches(TAG_WILDCARD);
+                if digits.contains(TAG_WILDCARD) || (digits.starts_with('0') && pattern.len() > 1) {
+                    return None;
+                }
+
+                // Tags are formatted without leading zeros
+                let scale = 10u64.checked_pow((pattern.len() - digits.len()) as u32)?;
+                let (min, max) = if digits.is_empty() {
+                    (if scale == 10 { 0 } else { scale / 10 }, scale - 1)
+                } else {
+                    let digits = digits.parse::<u64>().ok()?;
+                    let min = digits.checked_mul(scale)?;
+                    (min, min.checked_add(scale - 1)?)
+                };
+
+                Some(u32::try_from(min).ok()?..=u32::try_from(max).unwrap_or(u32::MAX))
+            }
+            PatternKind::Prefix(_) => None,
+        }
+    }
+
+    pub fn matches(&self, tag: u32) -> bool {
+        let mut buffer = [0; TAG_MAX_DIGITS];
+        let digits = tag_digits(tag, &mut buffer);
+
+        let matched = match &self.kind {
+            PatternKind::Digits(pattern) => {
+                pattern.len() == digits.len() && match_digits(digits, pattern.as_bytes())
+            }
+            PatternKind::Prefix(pattern) => {
+                pattern.len() <= digits.len() && match_digits(digits, pattern.as_bytes())
+            }
+            PatternKind::Range(range) => range.contains(&tag),
+        };
+
+        matched != self.negated
+    }
+}
+
+impl FromStr for TagPattern {
+    type Err = TagPatternError;
+
+    fn from_str(pattern: &str) -> Result<Self, Self::Err> {
+        let (negated, pattern) = match pattern.strip_prefix(TAG_NEGATION) {
+            Some(pattern) => (true, pattern),
+            None => (false, pattern),
+        };
+
+        let kind = if let Some((min, max)) = pattern.split_once(TAG_RANGE_SEPARATOR) {
+            let invalid = || TagPatternError::InvalidRange(pattern.to_string());
+            let min = parse_number(min).ok_or_else(invalid)?;
+            let max = parse_number(max).ok_or_else(invalid)?;
+            if min > max {
+                return Err(invalid());
+            }
+            PatternKind::Range(min..=max)
+        } else if let Some(prefix) = pattern.strip_suffix(TAG_PREFIX) {
+            // An empty prefix matches every tag
+            check_digits(prefix)?;
+            PatternKind::Prefix(prefix.into())
+        } else {
+            if pattern.is_empty() {
+                return Err(TagPatternError::Empty);
+            }
+            check_digits(pattern)?;
+            PatternKind::Digits(pattern.into())
+        };
+
+        Ok(TagPattern { negated, kind })
+    }
+}
+
+fn check_digits(pattern: &str) -> Result<(), TagPatternError> {
+    match pattern
+        .chars()
+        .find(|c| !c.is_ascii_digit() && *c != TAG_WILDCARD)
+    {
+        Some(c) => Err(TagPatternError::InvalidCharacter(c)),
+        None => Ok(()),
+    }
+}
+
+/// Parses a plain number, without the sign `u32::from_str` accepts.
+fn parse_number(number: &str) -> Option<u32> {
+    if number.is_empty() || !number.chars().all(|c| c.is_ascii_digit()) {
+        return None;
+    }
+    number.parse().ok()
+}
+
+/// Matches a tag against a pattern, a malformed pattern matches no tag.
 pub fn match_tag(tag: u32, pattern: &str) -> bool {
-    let tag = tag.to_string();
-    let mut tag_chars = tag.chars();
-    let mut pattern_chars = pattern.chars();
+    pattern
+        .parse::<TagPattern>()
+        .is_ok_and(|pattern| pattern.matches(tag))
+}
 
-    loop {
-        match (tag_chars.next(), pattern_chars.next()) {
-            (Some(tc), Some(pc)) => {
-                if tc != pc && pc != TAG_WILDCARD {
-                    return false;
+/// A list of tag patterns compiled once to match many tags: a tag must match
+/// one of the positive patterns, and the negated ones exclude the tags they
+/// don't match. Malformed patterns are ignored.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct TagMatcher {
+    include: CompiledPatterns,
+    exclude: CompiledPatterns,
+}
+
+impl TagMatcher {
+    pub fn new<'a>(patterns: impl IntoIterator<Item = &'a str>) -> Self {
+        let mut matcher = TagMatcher::default();
+        for pattern in patterns {
+            if let Ok(TagPattern { negated, kind }) = pattern.parse() {
+                let patterns = if negated {
+                    &mut matcher.exclude
+                } else {
+                    &mut matcher.include
+                };
+                patterns.push(kind);
+            }
+        }
+
+        matcher
+    }
+
+    pub fn matches(&self, tag: u32) -> bool {
+        // The digits are only formatted once for all the patterns
+        let mut buffer = [0; TAG_MAX_DIGITS];
+        let digits = tag_digits(tag, &mut buffer);
+
+        self.include.matches(tag, digits) && !self.exclude.matches(tag, digits)
+    }
+}
+
+/// The patterns of a [`TagMatcher`], grouped by the cheapest way to match them.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+struct CompiledPatterns {
+    /// The patterns without wildcards.
+    exact: HashSet<u32>,
+    ranges: Vec<RangeInclusive<u32>>,
+    digits: Vec<Box<[u8]>>,
+    prefixes: Vec<Box<[u8]>>,
+}
+
+impl CompiledPatterns {
+    fn push(&mut self, kind: PatternKind) {
+        match kind {
+            // A leading zero never matches, as tags are formatted without any
+            PatternKind::Digits(digits)
+                if !digits.contains(TAG_WILDCARD) && !digits.starts_with('0') =>
+            {
+                if let Ok(tag) = digits.parse() {
+                    self.exact.insert(tag);
                 }
             }
-            (Some(_), None) => return false,
-            (None, Some(_)) => return false,
-            (None, None) => return true,
+            PatternKind::Digits(digits) => self.digits.push(digits.as_bytes().into()),
+            PatternKind::Prefix(prefix) => self.prefixes.push(prefix.as_bytes().into()),
+            PatternKind::Range(range) => self.ranges.push(range),
         }
     }
+
+    fn matches(&self, tag: u32, digits: &[u8]) -> bool {
+        self.exact.contains(&tag)
+            || self.ranges.iter().any(|range| range.contains(&tag))
+            || self
+                .digits
+                .iter()
+                .any(|pattern| pattern.len() == digits.len() && match_digits(digits, pattern))
+            || self
+                .prefixes
+                .iter()
+                .any(|pattern| pattern.len() <= digits.len() && match_digits(digits, pattern))
+    }
+}
+
+/// Formats the tag's decimal digits into the buffer, without allocating.
+fn tag_digits(mut tag: u32, buffer: &mut [u8; TAG_MAX_DIGITS]) -> &[u8] {
+    let mut start = TAG_MAX_DIGITS;
+    loop {
+        start -= 1;
+        buffer[start] = b'0' + (tag % 10) as u8;
+        tag /= 10;
+        if tag == 0 {
+            return &buffer[start..];
+        }
+    }
+}
+
+/// Matches the first digits of the tag against the pattern's.
+fn match_digits(digits: &[u8], pattern: &[u8]) -> bool {
+    digits
+        .iter()
+        .zip(pattern)
+        .all(|(dc, pc)| dc == pc || *pc == TAG_WILDCARD as u8)
 }
 
 #[cfg(test)]
@@ -60,4 +287,126 @@ mod test_match_tag {
         assert!(!match_tag(1234, "12*"));
         assert!(!match_tag(1234, "*23"));
     }
+
+    #[test]
+    fn test_range() {
+        assert!(match_tag(1000, "1000-1999"));
+        assert!(match_tag(1500, "1000-1999"));
+        assert!(match_tag(1999, "1000-1999"));
+        assert!(!match_tag(999, "1000-1999"));
+        assert!(!match_tag(2000, "1000-1999"));
+    }
+
+    #[test]
+    fn test_prefix() {
+        assert!(match_tag(12, "12%"));
+        assert!(match_tag(1234, "12%"));
+        assert!(match_tag(1299999, "1*%"));
+        assert!(match_tag(1234, "%"));
+        assert!(!match_tag(1, "12%"));
+        assert!(!match_tag(1334, "12%"));
+    }
+
+    #[test]
+    fn test_negation() {
+        assert!(!match_tag(1234, "!1234"));
+        assert!(match_tag(1235, "!1234"));
+        assert!(match_tag(999, "!1000-1999"));
+        assert!(!match_tag(1234, "!12%"));
+    }
+
+    #[test]
+    fn test_as_range() {
+        let range = |pattern: &str| pattern.parse::<TagPattern>().unwrap().as_range();
+
+        assert_eq!(range("1100"), Some(1100..=1100));
+        assert_eq!(range("1100-1199"), Some(1100..=1199));
+        assert_eq!(range("11**"), Some(1100..=1199));
+        assert_eq!(range("****"), Some(1000..=9999));
+        assert_eq!(range("*"), Some(0..=9));
+        assert_eq!(range("0"), Some(0..=0));
+        assert_eq!(range("429496729*"), Some(4294967290..=u32::MAX));
+        assert_eq!(range("0123"), None);
+        assert_eq!(range("1*34"), None);
+        assert_eq!(range("11%"), None);
+        assert_eq!(range("!1100"), None);
+        assert_eq!(range("5*********"), None);
+        assert_eq!(range("99999999999999999999"), None);
+    }
+
+    #[test]
+    fn test_malformed() {
+        for pattern in [
+            "", "!", "12a4", "1-", "-1", "2-1", "1*-2", "+1-2", "1%2", "12%%",
+        ] {
+            assert!(
+                pattern.parse::<TagPattern>().is_err(),
+                "check `{pattern}` is rejected"
+            );
+            assert!(!match_tag(1234, pattern));
+        }
+    }
+}
+
+#[cfg(test)]
+mod test_tag_matcher {
+    use super::*;
+
+    #[test]
+    fn test_exclusions() {
+        let matcher = TagMatcher::new(["40%", "!4050", "!4100-4199"]);
+
+        assert!(matcher.matches(4001));
+        assert!(!matcher.matches(4050));
+        assert!(!matcher.matches(4150));
+        assert!(!matcher.matches(5000));
+        assert!(!TagMatcher::new(["!4050"]).matches(4001));
+        assert!(!TagMatcher::new([]).matches(4001));
+    }
+
+    #[test]
+    fn test_malformed_ignored() {
+        assert!(TagMatcher::new(["4x01", "4001"]).matches(4001));
+    }
+
+    #[test]
+    fn test_leading_zero() {
+        assert!(!TagMatcher::new(["0123"]).matches(123));
+        assert!(TagMatcher::new(["0"]).matches(0));
+    }
+
+    #[test]
+    fn test_same_as_patterns() {
+        let patterns = [
+            "1234",
+            "12*4",
+            "*",
+            "1000-1999",
+            "12%",
+            "1*%",
+            "!1500",
+            "0",
+            "4294967295",
+        ];
+        let matcher = TagMatcher::new(patterns);
+
+        for tag in [
+            0,
+            1,
+            12,
+            999,
+            1000,
+            1204,
+            1234,
+            1500,
+            1999,
+            123456,
+            u32::MAX,
+        ] {
+            let parsed: Vec<TagPattern> = patterns.iter().map(|p| p.parse().unwrap()).collect();
+            let expected = parsed.iter().any(|p| !p.is_negated() && p.matches(tag))
+                && parsed.iter().all(|p| !p.is_negated() || p.matches(tag));
+            assert_eq!(matcher.matches(tag), expected, "check tag {tag}");
+        }
+    }
 }
@@ -64,6 +64,8 @@ resource "aws_ecs_task_definition" "app_task_definition" {
         { name = "PUBLIC_URL", value = "http://localhost:8080" }, // TODO: Change this to the actual public URL
         { name = "LOG_LEVEL", value = var.log_level },
         { name = "MONGO_ADDRESS", value = var.docdb-connection_url },
+        { name = "CURSOR_SECRET", value = var.cursor_secret },
+        { name = "TRUSTED_PROXY_HOPS", value = "1" }, // The load balancer
         { name = "TELEMETRY_PROMETHEUS_PORT", value = "8081" }
       ],
       dependsOn = [
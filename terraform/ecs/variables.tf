@@ -73,3 +73,9 @@ variable "docdb-connection_url" {
   description = "The connection URL of the DocumentDB instance to use."
   type        = string
 }
+
+variable "cursor_secret" {
+  description = "The key signing the pagination cursors."
+  type        = string
+  sensitive   = true
+}
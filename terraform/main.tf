@@ -90,6 +90,7 @@ module "ecs" {
   allowed_app_ingress_cidr_blocks = module.vpc.vpc_cidr_block
   allowed_lb_ingress_cidr_blocks  = module.vpc.vpc_cidr_block
   docdb-connection_url            = module.history_docdb.connection_url
+  cursor_secret                   = var.cursor_secret
 }
 
 ################################################################################
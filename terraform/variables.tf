@@ -21,6 +21,12 @@ variable "grafana_endpoint" {
   type        = string
 }
 
+variable "cursor_secret" {
+  description = "The key signing the pagination cursors."
+  type        = string
+  sensitive   = true
+}
+
 variable "image_version" {
   description = "Optional override for the Docker image version to deploy. Default is `latest`"
   type        = string
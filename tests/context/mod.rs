@@ -1,10 +1,20 @@
 use {
-    self::{server::Gilgamesh, store::PersistentStorage},
+    self::{
+        server::Gilgamesh,
+        store::{PersistentStorage, PostgresStorage, SqliteStorage},
+    },
+    crate::storage::mocks::{
+        messages::MockMessageStore,
+        registrations::MockRegistrationStore,
+        signatures::MockSignatureStore,
+    },
     async_trait::async_trait,
+    gilgamesh::invalidation::LocalInvalidationBus,
+    std::sync::Arc,
     test_context::AsyncTestContext,
 };
 
-mod server;
+pub mod server;
 mod store;
 
 pub struct ServerContext {
@@ -23,6 +33,74 @@ impl AsyncTestContext for ServerContext {
     }
 }
 
+/// Two instances sharing their stores and invalidation bus.
+pub struct ClusterContext {
+    pub first: Gilgamesh,
+    pub second: Gilgamesh,
+}
+
+#[async_trait]
+impl AsyncTestContext for ClusterContext {
+    async fn setup() -> Self {
+        let first = Gilgamesh::start_with(
+            Arc::new(MockMessageStore::new()),
+            Arc::new(MockRegistrationStore::new()),
+            Arc::new(MockSignatureStore::new()),
+            Arc::new(LocalInvalidationBus::new()),
+        )
+        .await;
+        let second = Gilgamesh::start_with(
+            first.message_store.clone(),
+            first.registration_store.clone(),
+            first.signature_store.clone(),
+            first.invalidation_bus.clone(),
+        )
+        .await;
+
+        Self { first, second }
+    }
+
+    async fn teardown(mut self) {
+        self.first.shutdown().await;
+        self.second.shutdown().await;
+    }
+}
+
+/// The limits of [`LimitedServerContext`].
+pub const TEST_RATE_LIMIT: u32 = 3;
+pub const TEST_CLIENT_MESSAGE_QUOTA: u64 = 3;
+pub const TEST_TOPIC_MESSAGE_QUOTA: u64 = 2;
+
+/// A server throttling the clients and limiting their stored messages.
+pub struct LimitedServerContext {
+    pub server: Gilgamesh,
+}
+
+#[async_trait]
+impl AsyncTestContext for LimitedServerContext {
+    async fn setup() -> Self {
+        let server = Gilgamesh::start_configured(
+            Arc::new(MockMessageStore::new()),
+            Arc::new(MockRegistrationStore::new()),
+            Arc::new(MockSignatureStore::new()),
+            Arc::new(LocalInvalidationBus::new()),
+            |config| {
+                config.rate_limit_max_requests = Some(TEST_RATE_LIMIT);
+                config.client_message_quota = Some(TEST_CLIENT_MESSAGE_QUOTA);
+                config.topic_message_quota = Some(TEST_TOPIC_MESSAGE_QUOTA);
+                // The quotas have room again once messages expire
+                config.message_max_age = Some(3600);
+            },
+        )
+        .await;
+        Self { server }
+    }
+
+    async fn teardown(mut self) {
+        self.server.shutdown().await;
+    }
+}
+
 #[derive(Clone)]
 pub struct StoreContext {
     pub storage: PersistentStorage,
@@ -39,3 +117,35 @@ impl AsyncTestContext for StoreContext {
         self.storage.shutdown().await;
     }
 }
+
+pub struct SqliteStoreContext {
+    pub storage: SqliteStorage,
+}
+
+#[async_trait]
+impl AsyncTestContext for SqliteStoreContext {
+    async fn setup() -> Self {
+        let storage = SqliteStorage::init().await;
+        Self { storage }
+    }
+
+    async fn teardown(mut self) {
+        self.storage.shutdown().await;
+    }
+}
+
+pub struct PostgresStoreContext {
+    pub storage: PostgresStorage,
+}
+
+#[async_trait]
+impl AsyncTestContext for PostgresStoreContext {
+    async fn setup() -> Self {
+        let storage = PostgresStorage::init().await;
+        Self { storage }
+    }
+
+    async fn teardown(mut self) {
+        self.storage.shutdown().await;
+    }
+}
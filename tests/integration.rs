@@ -11,11 +11,14 @@ use relay_rpc::{
 mod context;
 mod messages;
 mod metrics;
+mod rate_limit;
 mod registration;
+mod relay;
 mod simple;
 mod storage;
 
 const TEST_RELAY_URL: &str = "https://history.walletconnect.com";
+const TEST_CURSOR_SECRET: &str = "test-cursor-secret";
 
 pub type ErrorResult<T> = Result<T, TestError>;
 
@@ -28,7 +31,16 @@ pub enum TestError {
     Gilgamesh(#[from] gilgamesh::error::Error),
 }
 
+/// Returns a topic or message ID, 32 bytes encoded as hex.
+fn test_hex_id(id: u64) -> String {
+    format!("{id:064x}")
+}
+
 fn get_client_jwt() -> (String, ClientId) {
+    get_client_jwt_for(TEST_RELAY_URL)
+}
+
+fn get_client_jwt_for(aud: &str) -> (String, ClientId) {
     let mut rng = StdRng::from_entropy();
     let keypair = Keypair::generate(&mut rng);
 
@@ -36,7 +48,7 @@ fn get_client_jwt() -> (String, ClientId) {
     let client_id = ClientId::from(random_client_id);
 
     let jwt = relay_rpc::auth::AuthToken::new(client_id.to_string())
-        .aud(TEST_RELAY_URL.to_string())
+        .aud(aud.to_string())
         .as_jwt(&keypair)
         .unwrap()
         .to_string();
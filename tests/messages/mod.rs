@@ -1,28 +1,41 @@
 use {
-    crate::{context::ServerContext, get_client_jwt, TEST_RELAY_URL},
+    crate::{
+        context::ServerContext,
+        get_client_jwt,
+        test_hex_id,
+        TEST_CURSOR_SECRET,
+        TEST_RELAY_URL,
+    },
     axum::http,
     chrono::Utc,
     gilgamesh::{
+        cursor::CursorCodec,
         handlers::{
+            delete_messages::DeleteMessagesResponse,
             get_messages::{Direction, GetMessagesResponse},
+            get_topics_messages::{GetTopicsMessagesBody, GetTopicsMessagesResponse, TopicQuery},
             save_message::HistoryPayload,
+            save_message_batch::{BatchItemStatus, BatchResponse, MAX_BATCH_SIZE},
+        },
+        store::{
+            messages::{Message, MessageCursor},
+            registrations::Registration,
         },
-        store::{messages::Message, registrations::Registration},
     },
-    std::sync::Arc,
+    std::{sync::Arc, time::Duration},
     test_context::test_context,
 };
 
 const TEST_METHOD: &str = "publish";
 const TEST_CLIENT_ID: &str = "12345";
-const TEST_MESSAGE_ID: &str = "67890";
-const TEST_TOPIC: &str = "test-topic";
+const TEST_MESSAGE_ID: &str = "0000000000000000000000000000000000000000000000000000000000067890";
+const TEST_TOPIC: &str = "7e577091c0000000000000000000000000000000000000000000000000000000";
 const TEST_MESSAGE: &str = "test-message";
 
 #[test_context(ServerContext)]
 #[tokio::test]
 async fn test_get_message_no_origin_no_count_no_direction(ctx: &mut ServerContext) {
-    let (jwt, _) = get_client_jwt();
+    let (jwt, client_id) = get_client_jwt();
 
     ctx.server
         .message_store
@@ -30,10 +43,13 @@ async fn test_get_message_no_origin_no_count_no_direction(ctx: &mut ServerContex
             id: None,
             timestamp: Utc::now().into(),
             method: Arc::from(TEST_METHOD),
-            client_id: Arc::from(TEST_CLIENT_ID),
+            client_id: client_id.clone().into_value(),
             message_id: Arc::from(TEST_MESSAGE_ID),
             topic: Arc::from(TEST_TOPIC),
             message: Arc::from(TEST_MESSAGE),
+            tag: None,
+            expires_at: None,
+            cursor: None,
         })
         .await;
 
@@ -57,10 +73,10 @@ async fn test_get_message_no_origin_no_count_no_direction(ctx: &mut ServerContex
 
     assert_eq!(response.topic.as_ref(), TEST_TOPIC);
     assert_eq!(response.direction, Direction::Forward);
-    assert_eq!(response.next_id.unwrap().as_ref(), "after");
+    assert_eq!(next_cursor_id(&response).as_ref(), "after");
 
     assert_eq!(response.messages.len(), 1);
-    assert_eq!(response.messages[0].client_id.as_ref(), TEST_CLIENT_ID);
+    assert_eq!(response.messages[0].client_id, client_id.into_value());
     assert_eq!(response.messages[0].topic.as_ref(), TEST_TOPIC);
     assert_eq!(response.messages[0].message_id.as_ref(), TEST_MESSAGE_ID);
     assert_eq!(response.messages[0].message.as_ref(), TEST_MESSAGE);
@@ -69,7 +85,7 @@ async fn test_get_message_no_origin_no_count_no_direction(ctx: &mut ServerContex
 #[test_context(ServerContext)]
 #[tokio::test]
 async fn test_get_message_origin_count_forward(ctx: &mut ServerContext) {
-    let (jwt, _) = get_client_jwt();
+    let (jwt, client_id) = get_client_jwt();
 
     ctx.server
         .message_store
@@ -77,10 +93,13 @@ async fn test_get_message_origin_count_forward(ctx: &mut ServerContext) {
             id: None,
             timestamp: Utc::now().into(),
             method: Arc::from(TEST_METHOD),
-            client_id: Arc::from(TEST_CLIENT_ID),
+            client_id: client_id.clone().into_value(),
             message_id: Arc::from(TEST_MESSAGE_ID),
             topic: Arc::from(TEST_TOPIC),
             message: Arc::from(TEST_MESSAGE),
+            tag: None,
+            expires_at: None,
+            cursor: None,
         })
         .await;
 
@@ -89,7 +108,7 @@ async fn test_get_message_origin_count_forward(ctx: &mut ServerContext) {
         .get(format!("http://{}/messages", ctx.server.public_addr))
         .query(&[
             ("topic", TEST_TOPIC),
-            ("originId", "1"),
+            ("originId", &test_cursor("1")),
             ("messageCount", "2"),
             ("direction", "forward"),
         ])
@@ -109,10 +128,10 @@ async fn test_get_message_origin_count_forward(ctx: &mut ServerContext) {
 
     assert_eq!(response.topic.as_ref(), TEST_TOPIC);
     assert_eq!(response.direction, Direction::Forward);
-    assert_eq!(response.next_id.unwrap().as_ref(), "after");
+    assert_eq!(next_cursor_id(&response).as_ref(), "after");
 
     assert_eq!(response.messages.len(), 1);
-    assert_eq!(response.messages[0].client_id.as_ref(), TEST_CLIENT_ID);
+    assert_eq!(response.messages[0].client_id, client_id.into_value());
     assert_eq!(response.messages[0].topic.as_ref(), TEST_TOPIC);
     assert_eq!(response.messages[0].message_id.as_ref(), TEST_MESSAGE_ID);
     assert_eq!(response.messages[0].message.as_ref(), TEST_MESSAGE);
@@ -121,7 +140,7 @@ async fn test_get_message_origin_count_forward(ctx: &mut ServerContext) {
 #[test_context(ServerContext)]
 #[tokio::test]
 async fn test_get_message_origin_count_backward(ctx: &mut ServerContext) {
-    let (jwt, _) = get_client_jwt();
+    let (jwt, client_id) = get_client_jwt();
 
     ctx.server
         .message_store
@@ -129,10 +148,13 @@ async fn test_get_message_origin_count_backward(ctx: &mut ServerContext) {
             id: None,
             timestamp: Utc::now().into(),
             method: Arc::from(TEST_METHOD),
-            client_id: Arc::from(TEST_CLIENT_ID),
+            client_id: client_id.clone().into_value(),
             message_id: Arc::from(TEST_MESSAGE_ID),
             topic: Arc::from(TEST_TOPIC),
             message: Arc::from(TEST_MESSAGE),
+            tag: None,
+            expires_at: None,
+            cursor: None,
         })
         .await;
 
@@ -141,7 +163,7 @@ async fn test_get_message_origin_count_backward(ctx: &mut ServerContext) {
         .get(format!("http://{}/messages", ctx.server.public_addr))
         .query(&[
             ("topic", TEST_TOPIC),
-            ("originId", "1"),
+            ("originId", &test_cursor("1")),
             ("messageCount", "2"),
             ("direction", "backward"),
         ])
@@ -161,15 +183,411 @@ async fn test_get_message_origin_count_backward(ctx: &mut ServerContext) {
 
     assert_eq!(response.topic.as_ref(), TEST_TOPIC);
     assert_eq!(response.direction, Direction::Backward);
-    assert_eq!(response.next_id.unwrap().as_ref(), "before");
+    assert_eq!(next_cursor_id(&response).as_ref(), "before");
 
     assert_eq!(response.messages.len(), 1);
-    assert_eq!(response.messages[0].client_id.as_ref(), TEST_CLIENT_ID);
+    assert_eq!(response.messages[0].client_id, client_id.into_value());
     assert_eq!(response.messages[0].topic.as_ref(), TEST_TOPIC);
     assert_eq!(response.messages[0].message_id.as_ref(), TEST_MESSAGE_ID);
     assert_eq!(response.messages[0].message.as_ref(), TEST_MESSAGE);
 }
 
+#[test_context(ServerContext)]
+#[tokio::test]
+async fn test_get_message_subscribed_topic(ctx: &mut ServerContext) {
+    let (jwt, client_id) = get_client_jwt();
+
+    ctx.server
+        .registration_store
+        .registrations
+        .insert(client_id.to_string(), Registration {
+            id: None,
+            client_id: client_id.clone().into_value(),
+            tags: vec![],
+            topics: vec![Arc::from(TEST_TOPIC)],
+            relay_url: Arc::from(TEST_RELAY_URL),
+            created_at: None,
+            updated_at: None,
+            expires_at: None,
+        })
+        .await;
+
+    ctx.server
+        .message_store
+        .test_add(Message {
+            id: None,
+            timestamp: Utc::now().into(),
+            method: Arc::from(TEST_METHOD),
+            client_id: Arc::from(TEST_CLIENT_ID),
+            message_id: Arc::from(TEST_MESSAGE_ID),
+            topic: Arc::from(TEST_TOPIC),
+            message: Arc::from(TEST_MESSAGE),
+            tag: None,
+            expires_at: None,
+            cursor: None,
+        })
+        .await;
+
+    // The subscribed topics grant access to other clients' messages
+    let client = reqwest::Client::new();
+    let response = client
+        .get(format!("http://{}/messages", ctx.server.public_addr))
+        .query(&[("topic", TEST_TOPIC)])
+        .header(http::header::AUTHORIZATION, format!("Bearer {jwt}"))
+        .send()
+        .await
+        .expect("Call failed");
+
+    assert_eq!(
+        response.status(),
+        http::StatusCode::OK,
+        "Response status was invalid: {:?} - {:?}",
+        response.status(),
+        response.text().await
+    );
+}
+
+#[test_context(ServerContext)]
+#[tokio::test]
+async fn test_get_message_unauthorized_topic(ctx: &mut ServerContext) {
+    let (jwt, _) = get_client_jwt();
+
+    ctx.server
+        .message_store
+        .test_add(Message {
+            id: None,
+            timestamp: Utc::now().into(),
+            method: Arc::from(TEST_METHOD),
+            client_id: Arc::from(TEST_CLIENT_ID),
+            message_id: Arc::from(TEST_MESSAGE_ID),
+            topic: Arc::from(TEST_TOPIC),
+            message: Arc::from(TEST_MESSAGE),
+            tag: None,
+            expires_at: None,
+            cursor: None,
+        })
+        .await;
+
+    let client = reqwest::Client::new();
+    let response = client
+        .get(format!("http://{}/messages", ctx.server.public_addr))
+        .query(&[("topic", TEST_TOPIC)])
+        .header(http::header::AUTHORIZATION, format!("Bearer {jwt}"))
+        .send()
+        .await
+        .expect("Call failed");
+
+    assert_eq!(
+        response.status(),
+        http::StatusCode::FORBIDDEN,
+        "Response status was invalid: {:?} - {:?}",
+        response.status(),
+        response.text().await
+    );
+}
+
+#[test_context(ServerContext)]
+#[tokio::test]
+async fn test_get_message_invalid_jwt(ctx: &mut ServerContext) {
+    let client = reqwest::Client::new();
+    let response = client
+        .get(format!("http://{}/messages", ctx.server.public_addr))
+        .query(&[("topic", TEST_TOPIC)])
+        .header(http::header::AUTHORIZATION, "Bearer not-a-jwt")
+        .send()
+        .await
+        .expect("Call failed");
+
+    assert_eq!(
+        response.status(),
+        http::StatusCode::UNAUTHORIZED,
+        "Response status was invalid: {:?} - {:?}",
+        response.status(),
+        response.text().await
+    );
+}
+
+#[test_context(ServerContext)]
+#[tokio::test]
+async fn test_get_message_missing_jwt(ctx: &mut ServerContext) {
+    let client = reqwest::Client::new();
+    let response = client
+        .get(format!("http://{}/messages", ctx.server.public_addr))
+        .query(&[("topic", TEST_TOPIC)])
+        .send()
+        .await
+        .expect("Call failed");
+
+    assert_eq!(
+        response.status(),
+        http::StatusCode::UNAUTHORIZED,
+        "Response status was invalid: {:?}",
+        response.status()
+    );
+
+    let body: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(body["errors"][0]["name"], "authentication_failed");
+    assert_eq!(body["fields"][0]["location"], "header");
+}
+
+#[test_context(ServerContext)]
+#[tokio::test]
+async fn test_get_message_tampered_cursor(ctx: &mut ServerContext) {
+    let (jwt, client_id) = get_client_jwt();
+    add_message(ctx, client_id.as_ref(), TEST_TOPIC, TEST_MESSAGE_ID).await;
+
+    let forged = CursorCodec::new(b"another-secret").encode(&MessageCursor {
+        ts: Utc::now(),
+        id: Arc::from("1"),
+    });
+
+    for origin_id in [forged.as_str(), "1"] {
+        let client = reqwest::Client::new();
+        let response = client
+            .get(format!("http://{}/messages", ctx.server.public_addr))
+            .query(&[("topic", TEST_TOPIC), ("originId", origin_id)])
+            .header(http::header::AUTHORIZATION, format!("Bearer {jwt}"))
+            .send()
+            .await
+            .expect("Call failed");
+
+        assert_eq!(
+            response.status(),
+            http::StatusCode::BAD_REQUEST,
+            "Response status was invalid: {:?} - {:?}",
+            response.status(),
+            response.text().await
+        );
+    }
+}
+
+#[test_context(ServerContext)]
+#[tokio::test]
+async fn test_get_message_filtered(ctx: &mut ServerContext) {
+    let (jwt, client_id) = get_client_jwt();
+    add_message(ctx, client_id.as_ref(), TEST_TOPIC, "1").await;
+    add_message(ctx, TEST_CLIENT_ID, TEST_TOPIC, "2").await;
+
+    let client = reqwest::Client::new();
+    let response = client
+        .get(format!("http://{}/messages", ctx.server.public_addr))
+        .query(&[
+            ("topic", TEST_TOPIC),
+            ("clientId", client_id.as_ref()),
+            ("method", TEST_METHOD),
+        ])
+        .header(http::header::AUTHORIZATION, format!("Bearer {jwt}"))
+        .send()
+        .await
+        .expect("Call failed");
+
+    assert!(
+        response.status().is_success(),
+        "Response was not successful: {:?} - {:?}",
+        response.status(),
+        response.text().await
+    );
+
+    let response: GetMessagesResponse = response.json().await.unwrap();
+    let ids: Vec<&str> = response
+        .messages
+        .iter()
+        .map(|m| m.message_id.as_ref())
+        .collect();
+    assert_eq!(ids, ["1"]);
+}
+
+#[test_context(ServerContext)]
+#[tokio::test]
+async fn test_get_message_tag_filter(ctx: &mut ServerContext) {
+    let (jwt, client_id) = get_client_jwt();
+    for (message_id, tag) in [("1", 1100), ("2", 1199), ("3", 4000)] {
+        ctx.server
+            .message_store
+            .test_add(Message {
+                id: None,
+                timestamp: Utc::now().into(),
+                method: Arc::from(TEST_METHOD),
+                client_id: client_id.clone().into_value(),
+                message_id: Arc::from(message_id),
+                topic: Arc::from(TEST_TOPIC),
+                message: Arc::from(TEST_MESSAGE),
+                tag: Some(tag),
+                expires_at: None,
+                cursor: None,
+            })
+            .await;
+    }
+
+    for (tag, status, expected) in [
+        ("11**", http::StatusCode::OK, vec!["1", "2"]),
+        ("1100-1150", http::StatusCode::OK, vec!["1"]),
+        ("4000", http::StatusCode::OK, vec!["3"]),
+        ("1*00", http::StatusCode::BAD_REQUEST, vec![]),
+        ("!4000", http::StatusCode::BAD_REQUEST, vec![]),
+    ] {
+        let client = reqwest::Client::new();
+        let response = client
+            .get(format!("http://{}/messages", ctx.server.public_addr))
+            .query(&[("topic", TEST_TOPIC), ("tag", tag)])
+            .header(http::header::AUTHORIZATION, format!("Bearer {jwt}"))
+            .send()
+            .await
+            .expect("Call failed");
+
+        assert_eq!(response.status(), status, "check status of `{tag}`");
+        if status.is_success() {
+            let response: GetMessagesResponse = response.json().await.unwrap();
+            let mut ids: Vec<&str> = response
+                .messages
+                .iter()
+                .map(|m| m.message_id.as_ref())
+                .collect();
+            ids.sort();
+            assert_eq!(ids, expected, "check messages of `{tag}`");
+        }
+    }
+}
+
+#[test_context(ServerContext)]
+#[tokio::test]
+async fn test_get_message_invalid_time_range(ctx: &mut ServerContext) {
+    let (jwt, client_id) = get_client_jwt();
+    add_message(ctx, client_id.as_ref(), TEST_TOPIC, TEST_MESSAGE_ID).await;
+
+    let client = reqwest::Client::new();
+    let response = client
+        .get(format!("http://{}/messages", ctx.server.public_addr))
+        .query(&[("topic", TEST_TOPIC), ("since", "2000"), ("until", "1000")])
+        .header(http::header::AUTHORIZATION, format!("Bearer {jwt}"))
+        .send()
+        .await
+        .expect("Call failed");
+
+    assert_eq!(
+        response.status(),
+        http::StatusCode::BAD_REQUEST,
+        "Response status was invalid: {:?} - {:?}",
+        response.status(),
+        response.text().await
+    );
+}
+
+#[test_context(ServerContext)]
+#[tokio::test]
+async fn test_get_topics_messages(ctx: &mut ServerContext) {
+    const OTHER_TOPIC: &str = "other-test-topic";
+    let (jwt, client_id) = get_client_jwt();
+    add_message(ctx, client_id.as_ref(), TEST_TOPIC, "1").await;
+    add_message(ctx, client_id.as_ref(), OTHER_TOPIC, "2").await;
+
+    let client = reqwest::Client::new();
+    let response = client
+        .post(format!("http://{}/messages/topics", ctx.server.public_addr))
+        .header(http::header::AUTHORIZATION, format!("Bearer {jwt}"))
+        .json(&GetTopicsMessagesBody {
+            topics: vec![
+                TopicQuery {
+                    topic: Arc::from(OTHER_TOPIC),
+                    origin_id: None,
+                },
+                TopicQuery {
+                    topic: Arc::from(TEST_TOPIC),
+                    origin_id: Some(Arc::from(test_cursor("1"))),
+                },
+            ],
+            message_count: Default::default(),
+        })
+        .send()
+        .await
+        .expect("Call failed");
+
+    assert!(
+        response.status().is_success(),
+        "Response was not successful: {:?} - {:?}",
+        response.status(),
+        response.text().await
+    );
+
+    let response: GetTopicsMessagesResponse = response.json().await.unwrap();
+    let pages: Vec<(&str, Vec<&str>)> = response
+        .topics
+        .iter()
+        .map(|page| {
+            let ids = page
+                .messages
+                .iter()
+                .map(|m| m.message_id.as_ref())
+                .collect();
+            (page.topic.as_ref(), ids)
+        })
+        .collect();
+    assert_eq!(pages, [(OTHER_TOPIC, vec!["2"]), (TEST_TOPIC, vec!["1"])]);
+}
+
+#[test_context(ServerContext)]
+#[tokio::test]
+async fn test_get_topics_messages_invalid_topics(ctx: &mut ServerContext) {
+    let (jwt, client_id) = get_client_jwt();
+    add_message(ctx, client_id.as_ref(), TEST_TOPIC, TEST_MESSAGE_ID).await;
+
+    let topic_query = |topic: &str| TopicQuery {
+        topic: Arc::from(topic),
+        origin_id: None,
+    };
+    for (topics, status) in [
+        (
+            vec![topic_query(TEST_TOPIC), topic_query("unauthorized-topic")],
+            http::StatusCode::FORBIDDEN,
+        ),
+        (
+            vec![topic_query(TEST_TOPIC), topic_query(TEST_TOPIC)],
+            http::StatusCode::BAD_REQUEST,
+        ),
+    ] {
+        let client = reqwest::Client::new();
+        let response = client
+            .post(format!("http://{}/messages/topics", ctx.server.public_addr))
+            .header(http::header::AUTHORIZATION, format!("Bearer {jwt}"))
+            .json(&GetTopicsMessagesBody {
+                topics,
+                message_count: Default::default(),
+            })
+            .send()
+            .await
+            .expect("Call failed");
+
+        assert_eq!(
+            response.status(),
+            status,
+            "Response status was invalid: {:?} - {:?}",
+            response.status(),
+            response.text().await
+        );
+    }
+}
+
+fn cursor_codec() -> CursorCodec {
+    CursorCodec::new(TEST_CURSOR_SECRET.as_bytes())
+}
+
+/// Returns a cursor signed like the server's, pointing at the `id` message.
+fn test_cursor(id: &str) -> String {
+    cursor_codec().encode(&MessageCursor {
+        ts: Utc::now(),
+        id: Arc::from(id),
+    })
+}
+
+/// Returns the store ID of the response's next cursor.
+fn next_cursor_id(response: &GetMessagesResponse) -> Arc<str> {
+    let next_id = response.next_id.as_deref().expect("check next_id");
+    cursor_codec()
+        .decode(next_id)
+        .expect("check next_id cursor")
+        .id
+}
+
 #[test_context(ServerContext)]
 #[tokio::test]
 async fn test_save_message_saved(ctx: &mut ServerContext) {
@@ -180,7 +598,11 @@ async fn test_save_message_saved(ctx: &mut ServerContext) {
         id: None,
         client_id: client_id.clone().into_value(),
         tags: tags.clone(),
+        topics: vec![],
         relay_url: Arc::from(TEST_RELAY_URL),
+        created_at: None,
+        updated_at: None,
+        expires_at: None,
     };
 
     ctx.server
@@ -196,7 +618,7 @@ async fn test_save_message_saved(ctx: &mut ServerContext) {
         .json(&HistoryPayload {
             method: Arc::from(TEST_METHOD),
             client_id: client_id.clone().into_value(),
-            message_id: Arc::from(format!("{TEST_MESSAGE_ID}-1")),
+            message_id: Arc::from(test_hex_id(1)),
             topic: Arc::from(TEST_TOPIC),
             tag: 4000,
             message: Arc::from(TEST_MESSAGE),
@@ -218,7 +640,7 @@ async fn test_save_message_saved(ctx: &mut ServerContext) {
         .json(&HistoryPayload {
             method: Arc::from(TEST_METHOD),
             client_id: client_id.clone().into_value(),
-            message_id: Arc::from(format!("{TEST_MESSAGE_ID}-2")),
+            message_id: Arc::from(test_hex_id(2)),
             topic: Arc::from(TEST_TOPIC),
             tag: 5123,
             message: Arc::from(TEST_MESSAGE),
@@ -238,31 +660,24 @@ async fn test_save_message_saved(ctx: &mut ServerContext) {
     let msg = ctx
         .server
         .message_store
-        .test_get(
-            client_id.value(),
-            TEST_TOPIC,
-            format!("{TEST_MESSAGE_ID}-1").as_str(),
-        )
+        .test_get(client_id.value(), TEST_TOPIC, test_hex_id(1).as_str())
         .await
         .unwrap();
     assert_eq!(msg.client_id.as_ref(), client_id.to_string());
     assert_eq!(msg.topic.as_ref(), TEST_TOPIC);
-    assert_eq!(msg.message_id.as_ref(), format!("{TEST_MESSAGE_ID}-1"));
+    assert_eq!(msg.message_id.as_ref(), test_hex_id(1));
     assert_eq!(msg.message.as_ref(), TEST_MESSAGE);
+    assert_eq!(msg.tag, Some(4000));
 
     let msg = ctx
         .server
         .message_store
-        .test_get(
-            client_id.value(),
-            TEST_TOPIC,
-            format!("{TEST_MESSAGE_ID}-2").as_str(),
-        )
+        .test_get(client_id.value(), TEST_TOPIC, test_hex_id(2).as_str())
         .await
         .unwrap();
     assert_eq!(msg.client_id.as_ref(), client_id.to_string());
     assert_eq!(msg.topic.as_ref(), TEST_TOPIC);
-    assert_eq!(msg.message_id.as_ref(), format!("{TEST_MESSAGE_ID}-2"));
+    assert_eq!(msg.message_id.as_ref(), test_hex_id(2));
     assert_eq!(msg.message.as_ref(), TEST_MESSAGE);
 }
 
@@ -276,7 +691,11 @@ async fn test_save_message_filtered_out(ctx: &mut ServerContext) {
         id: None,
         client_id: client_id.clone().into_value(),
         tags: tags.clone(),
+        topics: vec![],
         relay_url: Arc::from(TEST_RELAY_URL),
+        created_at: None,
+        updated_at: None,
+        expires_at: None,
     };
 
     ctx.server
@@ -292,7 +711,7 @@ async fn test_save_message_filtered_out(ctx: &mut ServerContext) {
         .json(&HistoryPayload {
             method: Arc::from(TEST_METHOD),
             client_id: client_id.clone().into_value(),
-            message_id: Arc::from(format!("{TEST_MESSAGE_ID}-1")),
+            message_id: Arc::from(test_hex_id(1)),
             topic: Arc::from(TEST_TOPIC),
             tag: 4123,
             message: Arc::from(TEST_MESSAGE),
@@ -314,7 +733,7 @@ async fn test_save_message_filtered_out(ctx: &mut ServerContext) {
         .json(&HistoryPayload {
             method: Arc::from(TEST_METHOD),
             client_id: client_id.clone().into_value(),
-            message_id: Arc::from(format!("{TEST_MESSAGE_ID}-2")),
+            message_id: Arc::from(test_hex_id(2)),
             topic: Arc::from(TEST_TOPIC),
             tag: 5123,
             message: Arc::from(TEST_MESSAGE),
@@ -334,27 +753,19 @@ async fn test_save_message_filtered_out(ctx: &mut ServerContext) {
     let msg = ctx
         .server
         .message_store
-        .test_get(
-            client_id.value(),
-            TEST_TOPIC,
-            format!("{TEST_MESSAGE_ID}-1").as_str(),
-        )
+        .test_get(client_id.value(), TEST_TOPIC, test_hex_id(1).as_str())
         .await;
     assert!(msg.is_none());
 
     let msg = ctx
         .server
         .message_store
-        .test_get(
-            client_id.value(),
-            TEST_TOPIC,
-            format!("{TEST_MESSAGE_ID}-2").as_str(),
-        )
+        .test_get(client_id.value(), TEST_TOPIC, test_hex_id(2).as_str())
         .await
         .unwrap();
     assert_eq!(msg.client_id, client_id.into_value());
     assert_eq!(msg.topic.as_ref(), TEST_TOPIC);
-    assert_eq!(msg.message_id.as_ref(), format!("{TEST_MESSAGE_ID}-2"));
+    assert_eq!(msg.message_id.as_ref(), test_hex_id(2));
     assert_eq!(msg.message.as_ref(), TEST_MESSAGE);
 }
 
@@ -368,7 +779,11 @@ async fn test_save_message_no_registration(ctx: &mut ServerContext) {
         id: None,
         client_id: client_id.clone().into_value(),
         tags: tags.clone(),
+        topics: vec![],
         relay_url: Arc::from(TEST_RELAY_URL),
+        created_at: None,
+        updated_at: None,
+        expires_at: None,
     };
 
     ctx.server
@@ -408,3 +823,512 @@ async fn test_save_message_no_registration(ctx: &mut ServerContext) {
         .await;
     assert!(msg.is_none());
 }
+
+#[test_context(ServerContext)]
+#[tokio::test]
+async fn test_save_message_invalid_payload(ctx: &mut ServerContext) {
+    let client = reqwest::Client::new();
+    let response = client
+        .post(format!("http://{}/messages", ctx.server.public_addr))
+        .json(&HistoryPayload {
+            method: Arc::from("unknown"),
+            client_id: Arc::from(TEST_CLIENT_ID),
+            message_id: Arc::from("67890"),
+            topic: Arc::from(TEST_TOPIC),
+            tag: 4000,
+            message: Arc::from("m".repeat(1024 * 1024 + 1)),
+        })
+        .send()
+        .await
+        .expect("Call failed");
+
+    assert_eq!(
+        response.status(),
+        http::StatusCode::BAD_REQUEST,
+        "Response status was invalid: {:?}",
+        response.status()
+    );
+
+    let body: serde_json::Value = response.json().await.unwrap();
+    let fields: Vec<&str> = body["fields"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|field| field["field"].as_str().unwrap())
+        .collect();
+    assert_eq!(fields, ["method", "messageId", "message"]);
+    assert!(ctx.server.message_store.test_get_messages().is_empty());
+}
+
+#[test_context(ServerContext)]
+#[tokio::test]
+async fn test_save_message_too_large(ctx: &mut ServerContext) {
+    let client = reqwest::Client::new();
+    let response = client
+        .post(format!("http://{}/messages", ctx.server.public_addr))
+        .json(&HistoryPayload {
+            method: Arc::from("publish"),
+            client_id: Arc::from(TEST_CLIENT_ID),
+            message_id: Arc::from(test_hex_id(1)),
+            topic: Arc::from(TEST_TOPIC),
+            tag: 4000,
+            message: Arc::from("m".repeat(2 * 1024 * 1024)),
+        })
+        .send()
+        .await
+        .expect("Call failed");
+
+    assert_eq!(
+        response.status(),
+        http::StatusCode::PAYLOAD_TOO_LARGE,
+        "Response status was invalid: {:?}",
+        response.status()
+    );
+    assert!(ctx.server.message_store.test_get_messages().is_empty());
+}
+
+#[test_context(ServerContext)]
+#[tokio::test]
+async fn test_delete_message(ctx: &mut ServerContext) {
+    let (jwt, client_id) = get_client_jwt();
+
+    add_message(ctx, client_id.as_ref(), TEST_TOPIC, "1").await;
+    add_message(ctx, client_id.as_ref(), TEST_TOPIC, "2").await;
+
+    let response = delete_messages(ctx, &jwt, &[("topic", TEST_TOPIC), ("messageId", "1")]).await;
+    assert_eq!(response.deleted, 1);
+
+    let store = &ctx.server.message_store;
+    assert!(store
+        .test_get(client_id.as_ref(), TEST_TOPIC, "1")
+        .await
+        .is_none());
+    assert!(store
+        .test_get(client_id.as_ref(), TEST_TOPIC, "2")
+        .await
+        .is_some());
+}
+
+#[test_context(ServerContext)]
+#[tokio::test]
+async fn test_delete_topic_messages(ctx: &mut ServerContext) {
+    let (jwt, client_id) = get_client_jwt();
+    let other_topic = format!("{TEST_TOPIC}-other");
+
+    add_message(ctx, client_id.as_ref(), TEST_TOPIC, "1").await;
+    add_message(ctx, client_id.as_ref(), TEST_TOPIC, "2").await;
+    add_message(ctx, client_id.as_ref(), &other_topic, "3").await;
+
+    let response = delete_messages(ctx, &jwt, &[("topic", TEST_TOPIC)]).await;
+    assert_eq!(response.deleted, 2);
+
+    let messages = ctx.server.message_store.test_get_messages();
+    assert_eq!(messages.len(), 1);
+    assert_eq!(messages[0].topic.as_ref(), other_topic);
+}
+
+#[test_context(ServerContext)]
+#[tokio::test]
+async fn test_delete_client_messages(ctx: &mut ServerContext) {
+    let (jwt, client_id) = get_client_jwt();
+
+    add_message(ctx, client_id.as_ref(), TEST_TOPIC, "1").await;
+    add_message(ctx, client_id.as_ref(), &format!("{TEST_TOPIC}-other"), "2").await;
+    add_message(ctx, TEST_CLIENT_ID, TEST_TOPIC, "3").await;
+
+    let response = delete_messages(ctx, &jwt, &[]).await;
+    assert_eq!(response.deleted, 2);
+
+    let messages = ctx.server.message_store.test_get_messages();
+    assert_eq!(messages.len(), 1);
+    assert_eq!(messages[0].client_id.as_ref(), TEST_CLIENT_ID);
+}
+
+#[test_context(ServerContext)]
+#[tokio::test]
+async fn test_delete_message_without_topic(ctx: &mut ServerContext) {
+    let (jwt, client_id) = get_client_jwt();
+
+    add_message(ctx, client_id.as_ref(), TEST_TOPIC, "1").await;
+
+    let client = reqwest::Client::new();
+    let response = client
+        .delete(format!("http://{}/messages", ctx.server.public_addr))
+        .query(&[("messageId", "1")])
+        .header(http::header::AUTHORIZATION, format!("Bearer {jwt}"))
+        .send()
+        .await
+        .expect("Call failed");
+
+    assert_eq!(
+        response.status(),
+        http::StatusCode::BAD_REQUEST,
+        "Response status was invalid: {:?} - {:?}",
+        response.status(),
+        response.text().await
+    );
+    assert_eq!(ctx.server.message_store.test_get_messages().len(), 1);
+}
+
+async fn add_message(ctx: &mut ServerContext, client_id: &str, topic: &str, message_id: &str) {
+    ctx.server
+        .message_store
+        .test_add(Message {
+            id: None,
+            timestamp: Utc::now().into(),
+            method: Arc::from(TEST_METHOD),
+            client_id: Arc::from(client_id),
+            message_id: Arc::from(message_id),
+            topic: Arc::from(topic),
+            message: Arc::from(TEST_MESSAGE),
+            tag: None,
+            expires_at: None,
+            cursor: None,
+        })
+        .await;
+}
+
+async fn delete_messages(
+    ctx: &mut ServerContext,
+    jwt: &str,
+    query: &[(&str, &str)],
+) -> DeleteMessagesResponse {
+    let client = reqwest::Client::new();
+    let response = client
+        .delete(format!("http://{}/messages", ctx.server.public_addr))
+        .query(query)
+        .header(http::header::AUTHORIZATION, format!("Bearer {jwt}"))
+        .send()
+        .await
+        .expect("Call failed");
+
+    assert!(
+        response.status().is_success(),
+        "Response was not successful: {:?} - {:?}",
+        response.status(),
+        response.text().await
+    );
+
+    response.json().await.expect("Failed to parse response")
+}
+
+#[test_context(ServerContext)]
+#[tokio::test]
+async fn test_save_message_batch(ctx: &mut ServerContext) {
+    let (_, client_id) = get_client_jwt();
+
+    let registration = Registration {
+        id: None,
+        client_id: client_id.clone().into_value(),
+        tags: vec![Arc::from("4000"), Arc::from("5***")],
+        topics: vec![],
+        relay_url: Arc::from(TEST_RELAY_URL),
+        created_at: None,
+        updated_at: None,
+        expires_at: None,
+    };
+
+    ctx.server
+        .registration_store
+        .registrations
+        .insert(client_id.to_string(), registration)
+        .await;
+
+    let payload = |client_id: Arc<str>, message_id: u64, tag: u32| HistoryPayload {
+        method: Arc::from(TEST_METHOD),
+        client_id,
+        message_id: Arc::from(test_hex_id(message_id)),
+        topic: Arc::from(TEST_TOPIC),
+        tag,
+        message: Arc::from(TEST_MESSAGE),
+    };
+
+    let client = reqwest::Client::new();
+    let response = client
+        .post(format!("http://{}/messages/batch", ctx.server.public_addr))
+        .json(&vec![
+            payload(client_id.clone().into_value(), 1, 4000),
+            payload(client_id.clone().into_value(), 2, 4123),
+            payload(Arc::from(TEST_CLIENT_ID), 3, 4000),
+            payload(client_id.clone().into_value(), 4, 5123),
+        ])
+        .send()
+        .await
+        .expect("Call failed");
+
+    assert!(
+        response.status().is_success(),
+        "Response was not successful: {:?} - {:?}",
+        response.status(),
+        response.text().await
+    );
+
+    let response: BatchResponse = response.json().await.expect("Failed to parse response");
+    let statuses: Vec<_> = response
+        .items
+        .iter()
+        .map(|item| (item.message_id.to_string(), item.status))
+        .collect();
+    assert_eq!(statuses, [
+        (test_hex_id(1), BatchItemStatus::Stored),
+        (test_hex_id(2), BatchItemStatus::FilteredOut),
+        (test_hex_id(3), BatchItemStatus::NotRegistered),
+        (test_hex_id(4), BatchItemStatus::Stored),
+    ]);
+
+    let store = &ctx.server.message_store;
+    assert!(store
+        .test_get(client_id.value(), TEST_TOPIC, &test_hex_id(1))
+        .await
+        .is_some());
+    assert!(store
+        .test_get(client_id.value(), TEST_TOPIC, &test_hex_id(2))
+        .await
+        .is_none());
+    assert!(store
+        .test_get(TEST_CLIENT_ID, TEST_TOPIC, &test_hex_id(3))
+        .await
+        .is_none());
+    assert!(store
+        .test_get(client_id.value(), TEST_TOPIC, &test_hex_id(4))
+        .await
+        .is_some());
+}
+
+#[test_context(ServerContext)]
+#[tokio::test]
+async fn test_save_message_batch_too_large(ctx: &mut ServerContext) {
+    let payloads: Vec<_> = (0..=MAX_BATCH_SIZE)
+        .map(|n| HistoryPayload {
+            method: Arc::from(TEST_METHOD),
+            client_id: Arc::from(TEST_CLIENT_ID),
+            message_id: Arc::from(n.to_string()),
+            topic: Arc::from(TEST_TOPIC),
+            tag: 4000,
+            message: Arc::from(TEST_MESSAGE),
+        })
+        .collect();
+
+    let client = reqwest::Client::new();
+    let response = client
+        .post(format!("http://{}/messages/batch", ctx.server.public_addr))
+        .json(&payloads)
+        .send()
+        .await
+        .expect("Call failed");
+
+    assert_eq!(
+        response.status(),
+        http::StatusCode::PAYLOAD_TOO_LARGE,
+        "Response status was invalid: {:?} - {:?}",
+        response.status(),
+        response.text().await
+    );
+}
+
+#[test_context(ServerContext)]
+#[tokio::test]
+async fn test_save_message_batch_invalid_payload(ctx: &mut ServerContext) {
+    let payloads: Vec<_> = [TEST_TOPIC, "not-a-topic"]
+        .into_iter()
+        .map(|topic| HistoryPayload {
+            method: Arc::from(TEST_METHOD),
+            client_id: Arc::from(TEST_CLIENT_ID),
+            message_id: Arc::from(TEST_MESSAGE_ID),
+            topic: Arc::from(topic),
+            tag: 4000,
+            message: Arc::from(TEST_MESSAGE),
+        })
+        .collect();
+
+    let client = reqwest::Client::new();
+    let response = client
+        .post(format!("http://{}/messages/batch", ctx.server.public_addr))
+        .json(&payloads)
+        .send()
+        .await
+        .expect("Call failed");
+
+    assert_eq!(
+        response.status(),
+        http::StatusCode::BAD_REQUEST,
+        "Response status was invalid: {:?}",
+        response.status()
+    );
+
+    let body: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(body["fields"][0]["field"], "[1].topic");
+    assert!(ctx.server.message_store.test_get_messages().is_empty());
+}
+
+#[test_context(ServerContext)]
+#[tokio::test]
+async fn test_stream_messages(ctx: &mut ServerContext) {
+    let (jwt, client_id) = get_client_jwt();
+
+    let registration = Registration {
+        id: None,
+        client_id: client_id.clone().into_value(),
+        tags: vec![Arc::from("4000")],
+        topics: vec![],
+        relay_url: Arc::from(TEST_RELAY_URL),
+        created_at: None,
+        updated_at: None,
+        expires_at: None,
+    };
+
+    ctx.server
+        .registration_store
+        .registrations
+        .insert(client_id.to_string(), registration)
+        .await;
+
+    // The client may only stream the topics it stored messages on
+    ctx.server
+        .message_store
+        .test_add(Message {
+            id: None,
+            timestamp: Utc::now().into(),
+            method: Arc::from(TEST_METHOD),
+            client_id: client_id.clone().into_value(),
+            message_id: Arc::from(test_hex_id(1)),
+            topic: Arc::from(TEST_TOPIC),
+            message: Arc::from(TEST_MESSAGE),
+            tag: None,
+            expires_at: None,
+            cursor: None,
+        })
+        .await;
+
+    let client = reqwest::Client::new();
+    let mut stream = client
+        .get(format!("http://{}/messages/stream", ctx.server.public_addr))
+        .query(&[("topic", TEST_TOPIC)])
+        .header(http::header::AUTHORIZATION, format!("Bearer {jwt}"))
+        .send()
+        .await
+        .expect("Call failed");
+
+    assert!(
+        stream.status().is_success(),
+        "Response was not successful: {:?}",
+        stream.status(),
+    );
+
+    let response = client
+        .post(format!("http://{}/messages", ctx.server.public_addr))
+        .json(&HistoryPayload {
+            method: Arc::from(TEST_METHOD),
+            client_id: client_id.clone().into_value(),
+            message_id: Arc::from(TEST_MESSAGE_ID),
+            topic: Arc::from(TEST_TOPIC),
+            tag: 4000,
+            message: Arc::from(TEST_MESSAGE),
+        })
+        .send()
+        .await
+        .expect("Call failed");
+    assert!(response.status().is_success());
+
+    let mut events = String::new();
+    while !events.contains("\n\n") {
+        let chunk = tokio::time::timeout(Duration::from_secs(5), stream.chunk())
+            .await
+            .expect("Timed out waiting for the message")
+            .expect("Failed to read the stream")
+            .expect("Stream closed");
+        events.push_str(&String::from_utf8_lossy(&chunk));
+    }
+
+    assert!(events.contains("event:message"), "check event: {events}");
+    assert!(events.contains(TEST_MESSAGE), "check event data: {events}");
+
+    // The event ID is the message's cursor, to resume from when reconnecting
+    let event_id = events
+        .lines()
+        .find_map(|line| line.strip_prefix("id:"))
+        .expect("check event id");
+    let cursor = cursor_codec().decode(event_id).expect("check event cursor");
+    assert!(cursor.id.ends_with(TEST_MESSAGE_ID), "check event cursor");
+}
+
+#[test_context(ServerContext)]
+#[tokio::test]
+async fn test_stream_messages_unauthorized_topic(ctx: &mut ServerContext) {
+    let (jwt, _) = get_client_jwt();
+
+    let client = reqwest::Client::new();
+    let response = client
+        .get(format!("http://{}/messages/stream", ctx.server.public_addr))
+        .query(&[("topic", TEST_TOPIC)])
+        .header(http::header::AUTHORIZATION, format!("Bearer {jwt}"))
+        .send()
+        .await
+        .expect("Call failed");
+
+    assert_eq!(
+        response.status(),
+        http::StatusCode::FORBIDDEN,
+        "Response status was invalid: {:?} - {:?}",
+        response.status(),
+        response.text().await
+    );
+}
+
+#[test_context(ServerContext)]
+#[tokio::test]
+async fn test_stream_messages_shutdown(ctx: &mut ServerContext) {
+    let (jwt, client_id) = get_client_jwt();
+
+    ctx.server
+        .registration_store
+        .registrations
+        .insert(client_id.to_string(), Registration {
+            id: None,
+            client_id: client_id.clone().into_value(),
+            tags: vec![Arc::from("4000")],
+            topics: vec![],
+            relay_url: Arc::from(TEST_RELAY_URL),
+            created_at: None,
+            updated_at: None,
+            expires_at: None,
+        })
+        .await;
+
+    // The client may only stream the topics it stored messages on
+    ctx.server
+        .message_store
+        .test_add(Message {
+            id: None,
+            timestamp: Utc::now().into(),
+            method: Arc::from(TEST_METHOD),
+            client_id: client_id.clone().into_value(),
+            message_id: Arc::from(test_hex_id(1)),
+            topic: Arc::from(TEST_TOPIC),
+            message: Arc::from(TEST_MESSAGE),
+            tag: None,
+            expires_at: None,
+            cursor: None,
+        })
+        .await;
+
+    let client = reqwest::Client::new();
+    let mut stream = client
+        .get(format!("http://{}/messages/stream", ctx.server.public_addr))
+        .query(&[("topic", TEST_TOPIC)])
+        .header(http::header::AUTHORIZATION, format!("Bearer {jwt}"))
+        .send()
+        .await
+        .expect("Call failed");
+    assert!(stream.status().is_success());
+
+    // The server stops accepting new connections first
+    ctx.server.shutdown().await;
+
+    // Then ends it rather than waiting for the shutdown timeout
+    let closed = tokio::time::timeout(Duration::from_millis(500), async {
+        while let Ok(Some(_)) = stream.chunk().await {}
+    })
+    .await;
+    assert!(closed.is_ok(), "The stream was not closed on shutdown");
+}
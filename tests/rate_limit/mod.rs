@@ -0,0 +1,233 @@
+use {
+    crate::{
+        context::{
+            server::Gilgamesh,
+            LimitedServerContext,
+            TEST_CLIENT_MESSAGE_QUOTA,
+            TEST_RATE_LIMIT,
+            TEST_TOPIC_MESSAGE_QUOTA,
+        },
+        get_client_jwt,
+        storage::mocks::{
+            messages::MockMessageStore,
+            registrations::MockRegistrationStore,
+            signatures::MockSignatureStore,
+        },
+        test_hex_id,
+        TEST_RELAY_URL,
+    },
+    axum::http,
+    gilgamesh::{
+        handlers::{
+            save_message::HistoryPayload,
+            save_message_batch::{BatchItemStatus, BatchResponse},
+        },
+        invalidation::LocalInvalidationBus,
+        store::registrations::Registration,
+    },
+    relay_rpc::domain::ClientId,
+    std::sync::Arc,
+    test_context::test_context,
+};
+
+async fn register(ctx: &LimitedServerContext, client_id: &ClientId) {
+    ctx.server
+        .registration_store
+        .registrations
+        .insert(client_id.to_string(), Registration {
+            id: None,
+            client_id: client_id.clone().into_value(),
+            tags: vec![Arc::from("4000")],
+            topics: vec![],
+            relay_url: Arc::from(TEST_RELAY_URL),
+            created_at: None,
+            updated_at: None,
+            expires_at: None,
+        })
+        .await;
+}
+
+fn payload(client_id: &ClientId, topic: u64, message_id: u64) -> HistoryPayload {
+    HistoryPayload {
+        method: Arc::from("publish"),
+        client_id: client_id.clone().into_value(),
+        topic: Arc::from(test_hex_id(topic)),
+        message_id: Arc::from(test_hex_id(message_id)),
+        tag: 4000,
+        message: Arc::from("test-message"),
+    }
+}
+
+#[test_context(LimitedServerContext)]
+#[tokio::test]
+async fn test_rate_limit(ctx: &mut LimitedServerContext) {
+    let client = reqwest::Client::new();
+    let get_registration = |jwt: &str| {
+        client
+            .get(format!("http://{}/register", ctx.server.public_addr))
+            .header(http::header::AUTHORIZATION, format!("Bearer {jwt}"))
+            .send()
+    };
+
+    let (jwt, _) = get_client_jwt();
+    for _ in 0..TEST_RATE_LIMIT {
+        let response = get_registration(&jwt).await.expect("Call failed");
+        assert_eq!(response.status(), http::StatusCode::NOT_FOUND);
+    }
+
+    let response = get_registration(&jwt).await.expect("Call failed");
+    assert_eq!(response.status(), http::StatusCode::TOO_MANY_REQUESTS);
+    assert_eq!(
+        response.headers()[http::header::RETRY_AFTER],
+        (60 / TEST_RATE_LIMIT).to_string()
+    );
+
+    // The other clients have their own limit
+    let (other_jwt, _) = get_client_jwt();
+    let response = get_registration(&other_jwt).await.expect("Call failed");
+    assert_eq!(response.status(), http::StatusCode::NOT_FOUND);
+
+    // The requests without a valid JWT are limited by IP address
+    for _ in 0..TEST_RATE_LIMIT {
+        let response = get_registration("invalid").await.expect("Call failed");
+        assert_eq!(response.status(), http::StatusCode::UNAUTHORIZED);
+    }
+    let response = get_registration("invalid").await.expect("Call failed");
+    assert_eq!(response.status(), http::StatusCode::TOO_MANY_REQUESTS);
+}
+
+#[test_context(LimitedServerContext)]
+#[tokio::test]
+async fn test_message_quota(ctx: &mut LimitedServerContext) {
+    let (_, client_id) = get_client_jwt();
+    register(ctx, &client_id).await;
+
+    let client = reqwest::Client::new();
+    let save_message = |payload: HistoryPayload| {
+        client
+            .post(format!("http://{}/messages", ctx.server.public_addr))
+            .json(&payload)
+            .send()
+    };
+
+    for (topic, message_id, status) in [
+        (0xa, 1, http::StatusCode::OK),
+        (0xa, 2, http::StatusCode::OK),
+        // Over the topic quota
+        (0xa, 3, http::StatusCode::TOO_MANY_REQUESTS),
+        (0xb, 4, http::StatusCode::OK),
+        // Over the client quota
+        (0xc, 5, http::StatusCode::TOO_MANY_REQUESTS),
+        // Replacing a stored message takes no room
+        (0xa, 1, http::StatusCode::OK),
+    ] {
+        let response = save_message(payload(&client_id, topic, message_id))
+            .await
+            .expect("Call failed");
+        assert_eq!(response.status(), status, "message {message_id}");
+
+        if status == http::StatusCode::TOO_MANY_REQUESTS {
+            assert_eq!(response.headers()[http::header::RETRY_AFTER], "60");
+        }
+    }
+
+    assert_eq!(
+        ctx.server.message_store.test_get_messages().len() as u64,
+        TEST_CLIENT_MESSAGE_QUOTA
+    );
+}
+
+#[test_context(LimitedServerContext)]
+#[tokio::test]
+async fn test_batch_message_quota(ctx: &mut LimitedServerContext) {
+    let (_, client_id) = get_client_jwt();
+    register(ctx, &client_id).await;
+
+    let client = reqwest::Client::new();
+    let response = client
+        .post(format!("http://{}/messages/batch", ctx.server.public_addr))
+        .json(&vec![
+            payload(&client_id, 0xa, 1),
+            payload(&client_id, 0xa, 2),
+            payload(&client_id, 0xa, 3),
+            payload(&client_id, 0xb, 4),
+            payload(&client_id, 0xb, 5),
+            payload(&client_id, 0xa, 1),
+        ])
+        .send()
+        .await
+        .expect("Call failed");
+
+    assert!(
+        response.status().is_success(),
+        "Response was not successful: {:?} - {:?}",
+        response.status(),
+        response.text().await
+    );
+
+    let response: BatchResponse = response.json().await.expect("Failed to parse response");
+    let statuses: Vec<_> = response
+        .items
+        .iter()
+        .map(|item| (item.message_id.to_string(), item.status))
+        .collect();
+    assert_eq!(statuses, [
+        (test_hex_id(1), BatchItemStatus::Stored),
+        (test_hex_id(2), BatchItemStatus::Stored),
+        (test_hex_id(3), BatchItemStatus::QuotaExceeded),
+        (test_hex_id(4), BatchItemStatus::Stored),
+        (test_hex_id(5), BatchItemStatus::QuotaExceeded),
+        (test_hex_id(1), BatchItemStatus::Stored),
+    ]);
+
+    let topic_messages = ctx
+        .server
+        .message_store
+        .test_get_messages()
+        .into_iter()
+        .filter(|message| *message.topic == test_hex_id(0xa))
+        .count() as u64;
+    assert_eq!(topic_messages, TEST_TOPIC_MESSAGE_QUOTA);
+}
+
+#[tokio::test]
+async fn test_message_quota_without_expiry() {
+    let ctx = LimitedServerContext {
+        server: Gilgamesh::start_configured(
+            Arc::new(MockMessageStore::new()),
+            Arc::new(MockRegistrationStore::new()),
+            Arc::new(MockSignatureStore::new()),
+            Arc::new(LocalInvalidationBus::new()),
+            |config| config.client_message_quota = Some(1),
+        )
+        .await,
+    };
+    let (_, client_id) = get_client_jwt();
+    register(&ctx, &client_id).await;
+
+    let client = reqwest::Client::new();
+    for (message_id, status) in [
+        (1, http::StatusCode::OK),
+        (2, http::StatusCode::TOO_MANY_REQUESTS),
+    ] {
+        let response = client
+            .post(format!("http://{}/messages", ctx.server.public_addr))
+            .json(&payload(&client_id, 0xa, message_id))
+            .send()
+            .await
+            .expect("Call failed");
+        assert_eq!(response.status(), status, "message {message_id}");
+    }
+
+    // The messages never expire, only deleting them makes room
+    let response = client
+        .post(format!("http://{}/messages", ctx.server.public_addr))
+        .json(&payload(&client_id, 0xa, 2))
+        .send()
+        .await
+        .expect("Call failed");
+    assert!(!response.headers().contains_key(http::header::RETRY_AFTER));
+
+    let mut server = ctx.server;
+    server.shutdown().await;
+}
@@ -1,9 +1,20 @@
 use {
-    crate::{context::ServerContext, get_client_jwt, TEST_RELAY_URL},
+    crate::{
+        context::{ClusterContext, ServerContext},
+        get_client_jwt,
+        get_client_jwt_for,
+        test_hex_id,
+        TEST_RELAY_URL,
+    },
     axum::http,
-    gilgamesh::{handlers::register::RegisterPayload, store::registrations::Registration},
-    std::sync::Arc,
+    futures::future::join_all,
+    gilgamesh::{
+        handlers::{register::RegisterPayload, save_message::HistoryPayload},
+        store::registrations::Registration,
+    },
+    std::{collections::HashSet, sync::Arc},
     test_context::test_context,
+    tokio::time::{sleep, Duration},
 };
 
 #[test_context(ServerContext)]
@@ -42,6 +53,174 @@ async fn test_register_new(ctx: &mut ServerContext) {
         .is_some())
 }
 
+#[test_context(ServerContext)]
+#[tokio::test]
+async fn test_register_jwt_audience(ctx: &mut ServerContext) {
+    let client = reqwest::Client::new();
+    let public_url = format!("http://{}", ctx.server.public_addr);
+
+    for (aud, status) in [
+        (public_url.as_str(), http::StatusCode::OK),
+        (
+            "https://history.example.com",
+            http::StatusCode::UNAUTHORIZED,
+        ),
+    ] {
+        let (jwt, client_id) = get_client_jwt_for(aud);
+        let payload = RegisterPayload {
+            tags: Some(vec![Arc::from("4000")]),
+            append_tags: None,
+            remove_tags: None,
+            relay_url: Arc::from(TEST_RELAY_URL),
+        };
+
+        let response = client
+            .post(format!("{public_url}/register"))
+            .json(&payload)
+            .header(http::header::AUTHORIZATION, format!("Bearer {jwt}"))
+            .send()
+            .await
+            .expect("Call failed");
+
+        assert_eq!(
+            response.status(),
+            status,
+            "Response status was invalid for {aud}: {:?}",
+            response.text().await
+        );
+        assert_eq!(
+            ctx.server
+                .registration_store
+                .registrations
+                .get(client_id.value().as_ref())
+                .is_some(),
+            status.is_success()
+        );
+    }
+}
+
+#[test_context(ServerContext)]
+#[tokio::test]
+async fn test_register_invalid_tag_patterns(ctx: &mut ServerContext) {
+    let (jwt, client_id) = get_client_jwt();
+
+    let payload = RegisterPayload {
+        tags: Some(vec![
+            Arc::from("4000"),
+            Arc::from("40a0"),
+            Arc::from("1000-1999"),
+            Arc::from("2000-1000"),
+        ]),
+        append_tags: None,
+        remove_tags: None,
+        relay_url: Arc::from(TEST_RELAY_URL),
+    };
+
+    let client = reqwest::Client::new();
+    let response = client
+        .post(format!("http://{}/register", ctx.server.public_addr))
+        .json(&payload)
+        .header(http::header::AUTHORIZATION, format!("Bearer {jwt}"))
+        .send()
+        .await
+        .expect("Call failed");
+
+    assert_eq!(
+        response.status(),
+        http::StatusCode::BAD_REQUEST,
+        "Response status was invalid: {:?}",
+        response.status()
+    );
+
+    let body: serde_json::Value = response.json().await.unwrap();
+    let fields: Vec<&str> = body["fields"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|field| field["field"].as_str().unwrap())
+        .collect();
+    assert_eq!(fields, ["tags[1]", "tags[3]"]);
+
+    assert!(ctx
+        .server
+        .registration_store
+        .registrations
+        .get(client_id.value().as_ref())
+        .is_none())
+}
+
+#[test_context(ServerContext)]
+#[tokio::test]
+async fn test_register_relay_url(ctx: &mut ServerContext) {
+    let client = reqwest::Client::new();
+
+    for relay_url in ["https://relay.example.com", "history.walletconnect.com"] {
+        let (jwt, client_id) = get_client_jwt();
+        let payload = RegisterPayload {
+            tags: Some(vec![Arc::from("4000")]),
+            append_tags: None,
+            remove_tags: None,
+            relay_url: Arc::from(relay_url),
+        };
+
+        let response = client
+            .post(format!("http://{}/register", ctx.server.public_addr))
+            .json(&payload)
+            .header(http::header::AUTHORIZATION, format!("Bearer {jwt}"))
+            .send()
+            .await
+            .expect("Call failed");
+
+        assert_eq!(
+            response.status(),
+            http::StatusCode::BAD_REQUEST,
+            "Response status was invalid for {relay_url}: {:?}",
+            response.status()
+        );
+        let body: serde_json::Value = response.json().await.unwrap();
+        assert_eq!(body["fields"][0]["field"], "relayUrl");
+
+        assert!(ctx
+            .server
+            .registration_store
+            .registrations
+            .get(client_id.value().as_ref())
+            .is_none());
+    }
+
+    // The WebSocket URL of an allowed relay is stored as its HTTP origin
+    let (jwt, client_id) = get_client_jwt();
+    let payload = RegisterPayload {
+        tags: Some(vec![Arc::from("4000")]),
+        append_tags: None,
+        remove_tags: None,
+        relay_url: Arc::from("wss://history.walletconnect.com/?projectId=1234"),
+    };
+
+    let response = client
+        .post(format!("http://{}/register", ctx.server.public_addr))
+        .json(&payload)
+        .header(http::header::AUTHORIZATION, format!("Bearer {jwt}"))
+        .send()
+        .await
+        .expect("Call failed");
+
+    assert!(
+        response.status().is_success(),
+        "Response was not successful: {:?} - {:?}",
+        response.status(),
+        response.text().await
+    );
+
+    let registration = ctx
+        .server
+        .registration_store
+        .registrations
+        .get(client_id.value().as_ref())
+        .unwrap();
+    assert_eq!(registration.relay_url.as_ref(), TEST_RELAY_URL);
+}
+
 #[test_context(ServerContext)]
 #[tokio::test]
 async fn test_register(ctx: &mut ServerContext) {
@@ -124,7 +303,11 @@ async fn test_register(ctx: &mut ServerContext) {
                 id: None,
                 client_id: client_id.clone().into_value(),
                 tags: test.start.clone(),
+                topics: vec![],
                 relay_url: relay_url.clone(),
+                created_at: None,
+                updated_at: None,
+                expires_at: None,
             })
             .await;
 
@@ -279,7 +462,11 @@ async fn test_get_registration(ctx: &mut ServerContext) {
         id: None,
         client_id: client_id.clone().into_value(),
         tags: tags.clone(),
+        topics: vec![],
         relay_url: Arc::from(TEST_RELAY_URL),
+        created_at: None,
+        updated_at: None,
+        expires_at: None,
     };
 
     ctx.server
@@ -316,3 +503,169 @@ async fn test_get_registration(ctx: &mut ServerContext) {
     assert_eq!(payload.tags.unwrap(), tags);
     assert_eq!(payload.relay_url.as_ref(), TEST_RELAY_URL);
 }
+
+#[test_context(ServerContext)]
+#[tokio::test]
+async fn test_delete_registration(ctx: &mut ServerContext) {
+    let (jwt, client_id) = get_client_jwt();
+
+    let payload = RegisterPayload {
+        tags: Some(vec![Arc::from("4000")]),
+        append_tags: None,
+        remove_tags: None,
+        relay_url: Arc::from(TEST_RELAY_URL),
+    };
+
+    let client = reqwest::Client::new();
+    let response = client
+        .post(format!("http://{}/register", ctx.server.public_addr))
+        .json(&payload)
+        .header(http::header::AUTHORIZATION, format!("Bearer {jwt}"))
+        .send()
+        .await
+        .expect("Call failed");
+    assert!(response.status().is_success());
+
+    let response = client
+        .delete(format!("http://{}/register", ctx.server.public_addr))
+        .header(http::header::AUTHORIZATION, format!("Bearer {jwt}"))
+        .send()
+        .await
+        .expect("Call failed");
+
+    assert!(
+        response.status().is_success(),
+        "Response was not successful: {:?} - {:?}",
+        response.status(),
+        response.text().await
+    );
+    assert!(ctx
+        .server
+        .registration_store
+        .registrations
+        .get(client_id.as_ref())
+        .is_none());
+
+    // Nothing left to delete
+    let response = client
+        .delete(format!("http://{}/register", ctx.server.public_addr))
+        .header(http::header::AUTHORIZATION, format!("Bearer {jwt}"))
+        .send()
+        .await
+        .expect("Call failed");
+    assert_eq!(response.status(), http::StatusCode::NOT_FOUND);
+}
+
+#[test_context(ServerContext)]
+#[tokio::test]
+async fn test_register_concurrent_updates(ctx: &mut ServerContext) {
+    let (jwt, client_id) = get_client_jwt();
+    let client = reqwest::Client::new();
+
+    let register = |payload: RegisterPayload| {
+        client
+            .post(format!("http://{}/register", ctx.server.public_addr))
+            .json(&payload)
+            .header(http::header::AUTHORIZATION, format!("Bearer {jwt}"))
+            .send()
+    };
+    let update = |append_tags: Option<Vec<Arc<str>>>, remove_tags: Option<Vec<Arc<str>>>| {
+        register(RegisterPayload {
+            tags: None,
+            append_tags,
+            remove_tags,
+            relay_url: Arc::from(TEST_RELAY_URL),
+        })
+    };
+
+    let response = register(RegisterPayload {
+        tags: Some(vec![Arc::from("4000")]),
+        append_tags: None,
+        remove_tags: None,
+        relay_url: Arc::from(TEST_RELAY_URL),
+    })
+    .await
+    .expect("Call failed");
+    assert!(response.status().is_success());
+
+    let tags: Vec<Arc<str>> = (4001..4021).map(|tag| Arc::from(tag.to_string())).collect();
+    let responses = join_all(tags.iter().map(|tag| update(Some(vec![tag.clone()]), None))).await;
+    for response in responses {
+        assert!(response.expect("Call failed").status().is_success());
+    }
+
+    // None of the concurrent updates is lost
+    let registration = ctx
+        .server
+        .registration_store
+        .registrations
+        .get(client_id.as_ref())
+        .unwrap();
+    let registered: HashSet<Arc<str>> = registration.tags.into_iter().collect();
+    let mut expected: HashSet<Arc<str>> = tags.iter().cloned().collect();
+    expected.insert(Arc::from("4000"));
+    assert_eq!(registered, expected);
+
+    let responses = join_all(tags.iter().map(|tag| update(None, Some(vec![tag.clone()])))).await;
+    for response in responses {
+        assert!(response.expect("Call failed").status().is_success());
+    }
+
+    let registration = ctx
+        .server
+        .registration_store
+        .registrations
+        .get(client_id.as_ref())
+        .unwrap();
+    assert_eq!(registration.tags, vec![Arc::<str>::from("4000")]);
+}
+
+#[test_context(ClusterContext)]
+#[tokio::test]
+async fn test_register_invalidates_other_instances(ctx: &mut ClusterContext) {
+    let (jwt, client_id) = get_client_jwt();
+    let client = reqwest::Client::new();
+
+    let register = |tags: &str| {
+        client
+            .post(format!("http://{}/register", ctx.first.public_addr))
+            .json(&RegisterPayload {
+                tags: Some(vec![Arc::from(tags)]),
+                append_tags: None,
+                remove_tags: None,
+                relay_url: Arc::from(TEST_RELAY_URL),
+            })
+            .header(http::header::AUTHORIZATION, format!("Bearer {jwt}"))
+            .send()
+    };
+    let save_message = |message_id: u64| {
+        client
+            .post(format!("http://{}/messages", ctx.second.public_addr))
+            .json(&HistoryPayload {
+                method: Arc::from("publish"),
+                client_id: client_id.clone().into_value(),
+                message_id: Arc::from(test_hex_id(message_id)),
+                topic: Arc::from(test_hex_id(0)),
+                tag: 4000,
+                message: Arc::from("message"),
+            })
+            .send()
+    };
+
+    let response = register("4000").await.expect("Call failed");
+    assert!(response.status().is_success());
+
+    // The second instance caches the registration
+    let response = save_message(1).await.expect("Call failed");
+    assert!(response.status().is_success());
+    assert_eq!(ctx.second.message_store.test_get_messages().len(), 1);
+
+    let response = register("5000").await.expect("Call failed");
+    assert!(response.status().is_success());
+    sleep(Duration::from_millis(100)).await;
+
+    // The message no longer matches the registered tags
+    let response = save_message(2).await.expect("Call failed");
+    assert!(response.status().is_success());
+    assert_eq!(ctx.second.message_store.test_get_messages().len(), 1);
+}
@@ -0,0 +1,439 @@
+use {
+    crate::{
+        context::server::Gilgamesh,
+        get_client_jwt,
+        storage::mocks::{
+            messages::MockMessageStore,
+            registrations::MockRegistrationStore,
+            signatures::MockSignatureStore,
+        },
+        test_hex_id,
+    },
+    axum::{extract::State, http, routing::get, Router},
+    chrono::Utc,
+    ed25519_dalek::{Keypair, PublicKey, SecretKey, Signer},
+    gilgamesh::{
+        handlers::{
+            save_message::HistoryPayload,
+            save_message_batch::{BatchItemStatus, BatchResponse},
+            subscriptions::SubscriptionsPayload,
+        },
+        invalidation::LocalInvalidationBus,
+        relay::{
+            signature::{SIGNATURE_HEADER_NAME, TIMESTAMP_HEADER_NAME},
+            RelayClient,
+        },
+        store::{messages::MessagesStore, registrations::Registration},
+    },
+    std::{
+        net::TcpListener,
+        sync::{
+            atomic::{AtomicUsize, Ordering},
+            Arc,
+            Mutex,
+        },
+    },
+};
+
+/// A fake Relay serving its public key and counting the fetches.
+#[derive(Clone)]
+struct MockRelay {
+    public_key: Arc<Mutex<PublicKey>>,
+    fetches: Arc<AtomicUsize>,
+}
+
+impl MockRelay {
+    fn start(public_key: PublicKey) -> (Self, String) {
+        let relay = MockRelay {
+            public_key: Arc::new(Mutex::new(public_key)),
+            fetches: Arc::new(AtomicUsize::new(0)),
+        };
+
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        let app = Router::new()
+            .route("/public-key", get(public_key_handler))
+            .with_state(relay.clone());
+        tokio::spawn(
+            axum::Server::from_tcp(listener)
+                .unwrap()
+                .serve(app.into_make_service()),
+        );
+
+        (relay, url)
+    }
+
+    fn rotate(&self, public_key: PublicKey) {
+        *self.public_key.lock().unwrap() = public_key;
+    }
+
+    fn fetches(&self) -> usize {
+        self.fetches.load(Ordering::SeqCst)
+    }
+}
+
+async fn public_key_handler(State(relay): State<MockRelay>) -> String {
+    relay.fetches.fetch_add(1, Ordering::SeqCst);
+    hex::encode(relay.public_key.lock().unwrap().as_bytes())
+}
+
+fn public_key(seed: u8) -> PublicKey {
+    PublicKey::from(&SecretKey::from_bytes(&[seed; 32]).unwrap())
+}
+
+/// Signs the body the way the Relay does, with the key of `public_key(seed)`.
+fn sign(seed: u8, timestamp: i64, body: &str) -> String {
+    let keypair = Keypair {
+        secret: SecretKey::from_bytes(&[seed; 32]).unwrap(),
+        public: public_key(seed),
+    };
+    let signature = keypair.sign(format!("{}.{}.{}", timestamp, body.len(), body).as_bytes());
+
+    hex::encode(signature.to_bytes())
+}
+
+#[tokio::test]
+async fn test_public_key_cache_shared_between_clones() {
+    let (relay, url) = MockRelay::start(public_key(1));
+    let client = RelayClient::new(url);
+
+    assert_eq!(client.public_key().await.unwrap(), public_key(1));
+    assert_eq!(client.clone().public_key().await.unwrap(), public_key(1));
+    assert_eq!(relay.fetches(), 1, "check the key was fetched once");
+}
+
+#[tokio::test]
+async fn test_public_key_rotation() {
+    let (relay, url) = MockRelay::start(public_key(1));
+    let client = RelayClient::new(url);
+    let stale = client.public_key().await.unwrap();
+
+    relay.rotate(public_key(2));
+    assert_eq!(
+        client.public_key().await.unwrap(),
+        stale,
+        "check cached key"
+    );
+
+    let refreshed = client.refresh_public_key(stale).await.unwrap();
+    assert_eq!(refreshed, public_key(2));
+    assert_eq!(client.public_key().await.unwrap(), public_key(2));
+
+    // Already refreshed by a concurrent request
+    assert_eq!(
+        client.refresh_public_key(stale).await.unwrap(),
+        public_key(2)
+    );
+    assert_eq!(relay.fetches(), 2, "check the key was fetched twice");
+}
+
+#[tokio::test]
+async fn test_public_key_refresh_rate_limited() {
+    let (relay, url) = MockRelay::start(public_key(1));
+    let client = RelayClient::new(url);
+    let stale = client.public_key().await.unwrap();
+
+    relay.rotate(public_key(2));
+    let refreshed = client.refresh_public_key(stale).await.unwrap();
+    assert_eq!(refreshed, public_key(2));
+
+    // Refreshed too recently to be fetched again
+    relay.rotate(public_key(3));
+    assert_eq!(
+        client.refresh_public_key(refreshed).await.unwrap(),
+        refreshed
+    );
+    assert_eq!(client.public_key().await.unwrap(), public_key(2));
+    assert_eq!(relay.fetches(), 2, "check the key was fetched twice");
+}
+
+/// Starts an instance validating the signatures of the Relay at `relay_url`.
+async fn start_signed_server(
+    relay_url: &str,
+    message_store: Arc<MockMessageStore>,
+    registration_store: Arc<MockRegistrationStore>,
+    signature_store: Arc<MockSignatureStore>,
+    client_message_quota: Option<u64>,
+) -> Gilgamesh {
+    let relay_url = relay_url.to_string();
+    Gilgamesh::start_configured(
+        message_store,
+        registration_store,
+        signature_store,
+        Arc::new(LocalInvalidationBus::new()),
+        move |config| {
+            config.relay_url = relay_url;
+            config.allowed_relay_urls = vec![];
+            config.validate_signatures = true;
+            config.client_message_quota = client_message_quota;
+        },
+    )
+    .await
+}
+
+async fn register(registration_store: &MockRegistrationStore, client_id: &str, relay_url: &str) {
+    registration_store
+        .registrations
+        .insert(client_id.to_string(), Registration {
+            id: None,
+            client_id: Arc::from(client_id),
+            tags: vec![Arc::from("4000")],
+            topics: vec![],
+            relay_url: Arc::from(relay_url),
+            created_at: None,
+            updated_at: None,
+            expires_at: None,
+        })
+        .await;
+}
+
+fn message_body(client_id: &str, message_id: u64) -> String {
+    serde_json::to_string(&HistoryPayload {
+        method: Arc::from("publish"),
+        client_id: Arc::from(client_id),
+        message_id: Arc::from(test_hex_id(message_id)),
+        topic: Arc::from(test_hex_id(2)),
+        tag: 4000,
+        message: Arc::from("test-message"),
+    })
+    .unwrap()
+}
+
+/// Sends the body to `path`, signed by `public_key(1)` at `timestamp`.
+async fn send_signed(
+    server: &Gilgamesh,
+    path: &str,
+    body: &str,
+    timestamp: i64,
+) -> reqwest::Response {
+    reqwest::Client::new()
+        .post(format!("http://{}{path}", server.public_addr))
+        .header(http::header::CONTENT_TYPE, "application/json")
+        .header(SIGNATURE_HEADER_NAME, sign(1, timestamp, body))
+        .header(TIMESTAMP_HEADER_NAME, timestamp.to_string())
+        .body(body.to_string())
+        .send()
+        .await
+        .expect("Call failed")
+}
+
+async fn post_signed(
+    server: &Gilgamesh,
+    path: &str,
+    body: &str,
+    timestamp: i64,
+) -> http::StatusCode {
+    send_signed(server, path, body, timestamp).await.status()
+}
+
+#[tokio::test]
+async fn test_replayed_signature_rejected_by_every_instance() {
+    let (_relay, url) = MockRelay::start(public_key(1));
+    let message_store = Arc::new(MockMessageStore::new());
+    let registration_store = Arc::new(MockRegistrationStore::new());
+    let signature_store = Arc::new(MockSignatureStore::new());
+
+    let mut servers = vec![];
+    for _ in 0..2 {
+        servers.push(
+            start_signed_server(
+                &url,
+                message_store.clone(),
+                registration_store.clone(),
+                signature_store.clone(),
+                None,
+            )
+            .await,
+        );
+    }
+
+    let client_id = "replayed-client";
+    register(&registration_store, client_id, &url).await;
+
+    let body = message_body(client_id, 1);
+    let timestamp = Utc::now().timestamp();
+    let mut statuses = vec![];
+    for server in [&servers[0], &servers[0], &servers[1]] {
+        statuses.push(post_signed(server, "/messages", &body, timestamp).await);
+    }
+
+    assert_eq!(
+        statuses,
+        [
+            http::StatusCode::OK,
+            http::StatusCode::UNAUTHORIZED,
+            http::StatusCode::UNAUTHORIZED,
+        ],
+        "check the replays are rejected by both instances"
+    );
+
+    for server in &mut servers {
+        server.shutdown().await;
+    }
+}
+
+#[tokio::test]
+async fn test_failed_request_retried() {
+    let (_relay, url) = MockRelay::start(public_key(1));
+    let message_store = Arc::new(MockMessageStore::new());
+    let registration_store = Arc::new(MockRegistrationStore::new());
+    let mut server = start_signed_server(
+        &url,
+        message_store.clone(),
+        registration_store.clone(),
+        Arc::new(MockSignatureStore::new()),
+        Some(1),
+    )
+    .await;
+
+    let client_id = "retrying-client";
+    register(&registration_store, client_id, &url).await;
+
+    let timestamp = Utc::now().timestamp();
+    assert_eq!(
+        post_signed(&server, "/messages", &message_body(client_id, 1), timestamp).await,
+        http::StatusCode::OK
+    );
+
+    // Over the quota, the Relay retries once there's room
+    let body = message_body(client_id, 3);
+    assert_eq!(
+        post_signed(&server, "/messages", &body, timestamp).await,
+        http::StatusCode::TOO_MANY_REQUESTS
+    );
+    message_store
+        .delete_client_messages(client_id)
+        .await
+        .unwrap();
+    assert_eq!(
+        post_signed(&server, "/messages", &body, timestamp).await,
+        http::StatusCode::OK,
+        "check the retry isn't rejected as a replay"
+    );
+
+    // Only the failed requests are released
+    assert_eq!(
+        post_signed(&server, "/messages", &body, timestamp).await,
+        http::StatusCode::UNAUTHORIZED
+    );
+
+    server.shutdown().await;
+}
+
+#[tokio::test]
+async fn test_subscriptions_authorize_topic() {
+    let (_relay, url) = MockRelay::start(public_key(1));
+    let registration_store = Arc::new(MockRegistrationStore::new());
+    let mut server = start_signed_server(
+        &url,
+        Arc::new(MockMessageStore::new()),
+        registration_store.clone(),
+        Arc::new(MockSignatureStore::new()),
+        None,
+    )
+    .await;
+
+    let (jwt, client_id) = get_client_jwt();
+    register(&registration_store, client_id.as_ref(), &url).await;
+
+    let topic = test_hex_id(2);
+    let get_messages = || {
+        reqwest::Client::new()
+            .get(format!("http://{}/messages", server.public_addr))
+            .query(&[("topic", &topic)])
+            .header(http::header::AUTHORIZATION, format!("Bearer {jwt}"))
+            .send()
+    };
+    let subscriptions_body = |subscribe: Vec<Arc<str>>, unsubscribe: Vec<Arc<str>>| {
+        serde_json::to_string(&SubscriptionsPayload {
+            client_id: client_id.clone().into_value(),
+            subscribe,
+            unsubscribe,
+        })
+        .unwrap()
+    };
+
+    assert_eq!(
+        get_messages().await.unwrap().status(),
+        http::StatusCode::FORBIDDEN
+    );
+
+    let timestamp = Utc::now().timestamp();
+    let body = subscriptions_body(vec![Arc::from(topic.as_str())], vec![]);
+    assert_eq!(
+        post_signed(&server, "/subscriptions", &body, timestamp).await,
+        http::StatusCode::OK
+    );
+    assert_eq!(get_messages().await.unwrap().status(), http::StatusCode::OK);
+
+    let body = subscriptions_body(vec![], vec![Arc::from(topic.as_str())]);
+    assert_eq!(
+        post_signed(&server, "/subscriptions", &body, timestamp).await,
+        http::StatusCode::OK
+    );
+    assert_eq!(
+        get_messages().await.unwrap().status(),
+        http::StatusCode::FORBIDDEN
+    );
+
+    // The topics must be hex IDs
+    let body = subscriptions_body(vec![Arc::from("not-a-topic")], vec![]);
+    assert_eq!(
+        post_signed(&server, "/subscriptions", &body, timestamp).await,
+        http::StatusCode::BAD_REQUEST
+    );
+
+    server.shutdown().await;
+}
+
+#[tokio::test]
+async fn test_batch_relay_mismatch() {
+    let (_relay, url) = MockRelay::start(public_key(1));
+    let other_url = "https://other-relay.example.com";
+    let registration_store = Arc::new(MockRegistrationStore::new());
+    let relay_url = url.clone();
+    let mut server = Gilgamesh::start_configured(
+        Arc::new(MockMessageStore::new()),
+        registration_store.clone(),
+        Arc::new(MockSignatureStore::new()),
+        Arc::new(LocalInvalidationBus::new()),
+        move |config| {
+            config.relay_url = relay_url;
+            config.allowed_relay_urls = vec![other_url.to_string()];
+            config.validate_signatures = true;
+        },
+    )
+    .await;
+
+    register(&registration_store, "local-client", &url).await;
+    register(&registration_store, "other-client", other_url).await;
+
+    // The client of the other relay doesn't fail the whole batch
+    let body = format!(
+        "[{},{}]",
+        message_body("local-client", 1),
+        message_body("other-client", 3)
+    );
+    let response = send_signed(&server, "/messages/batch", &body, Utc::now().timestamp()).await;
+    assert_eq!(response.status(), http::StatusCode::OK);
+
+    let response: BatchResponse = response.json().await.expect("Failed to parse response");
+    let statuses: Vec<_> = response
+        .items
+        .iter()
+        .map(|item| (item.client_id.to_string(), item.status))
+        .collect();
+    assert_eq!(statuses, [
+        ("local-client".to_string(), BatchItemStatus::Stored),
+        ("other-client".to_string(), BatchItemStatus::RelayMismatch),
+    ]);
+
+    // A single message is still rejected
+    let body = message_body("other-client", 5);
+    assert_eq!(
+        post_signed(&server, "/messages", &body, Utc::now().timestamp()).await,
+        http::StatusCode::FORBIDDEN
+    );
+
+    server.shutdown().await;
+}
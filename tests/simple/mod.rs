@@ -1,4 +1,10 @@
-use {crate::context::ServerContext, test_context::test_context};
+use {
+    crate::{context::ServerContext, TEST_RELAY_URL},
+    axum::http,
+    gilgamesh::handlers::health::{ComponentStatus, ReadinessResponse},
+    std::sync::atomic::Ordering,
+    test_context::test_context,
+};
 
 #[test_context(ServerContext)]
 #[tokio::test]
@@ -9,3 +15,51 @@ async fn test_health(ctx: &mut ServerContext) {
         .status();
     assert!(body.is_success());
 }
+
+#[test_context(ServerContext)]
+#[tokio::test]
+async fn test_ready(ctx: &mut ServerContext) {
+    let response = reqwest::get(format!("http://{}/health/ready", ctx.server.public_addr))
+        .await
+        .expect("Failed to call /health/ready");
+    assert_eq!(response.status(), http::StatusCode::OK);
+
+    let body: ReadinessResponse = response.json().await.expect("Failed to parse response");
+    assert_eq!(body.status, ComponentStatus::Ok);
+    assert_eq!(body.name, "gilgamesh");
+    assert_eq!(body.messages_store.status, ComponentStatus::Ok);
+    assert_eq!(body.registration_store.status, ComponentStatus::Ok);
+
+    // The test servers don't validate signatures
+    let relay = body
+        .relays
+        .iter()
+        .find(|relay| relay.url == TEST_RELAY_URL)
+        .expect("check relay");
+    assert_eq!(relay.public_key, ComponentStatus::Disabled);
+}
+
+#[test_context(ServerContext)]
+#[tokio::test]
+async fn test_not_ready(ctx: &mut ServerContext) {
+    ctx.server
+        .message_store
+        .is_unavailable
+        .store(true, Ordering::SeqCst);
+
+    let response = reqwest::get(format!("http://{}/health/ready", ctx.server.public_addr))
+        .await
+        .expect("Failed to call /health/ready");
+    assert_eq!(response.status(), http::StatusCode::SERVICE_UNAVAILABLE);
+
+    let body: ReadinessResponse = response.json().await.expect("Failed to parse response");
+    assert_eq!(body.status, ComponentStatus::Unavailable);
+    assert_eq!(body.messages_store.status, ComponentStatus::Unavailable);
+    assert_eq!(body.registration_store.status, ComponentStatus::Ok);
+
+    // The server is still alive
+    let response = reqwest::get(format!("http://{}/health", ctx.server.public_addr))
+        .await
+        .expect("Failed to call /health");
+    assert!(response.status().is_success());
+}
@@ -1,27 +1,27 @@
+//! The tests of the messages stores, run against each backend by
+//! `store_tests!`.
+
 use {
-    crate::context::StoreContext,
     ::function_name::named,
-    gilgamesh::store::messages::MessagesStore,
-    std::time,
-    test_context::test_context,
+    chrono::{Duration, Utc},
+    gilgamesh::store::{
+        messages::{MessageCursor, MessageFilter, MessagesStore, NewMessage, TopicOrigin},
+        registrations::RegistrationStore,
+        StoreError,
+    },
+    std::{sync::Arc, time},
 };
 
 const TEST_CLIENT_ID: &str = "12345";
 const TEST_QUERY_SIZE: usize = 3;
 
-// NOTE: Requires the dev MongoDB container (see `ops/docker-compose.yml`).
 #[named]
-#[test_context(StoreContext)]
-#[tokio::test]
-#[cfg_attr(not(feature = "storage-tests"), ignore)]
-async fn test_after_no_origin(ctx: &StoreContext) {
+pub async fn test_after_no_origin(store: &impl MessagesStore) {
     let topic = function_name!();
-    fill_store(ctx, TEST_CLIENT_ID, topic, 20).await;
+    let cursors = fill_store(store, TEST_CLIENT_ID, topic, 20).await;
 
-    let result = ctx
-        .storage
-        .store
-        .get_messages_after(topic, None, TEST_QUERY_SIZE)
+    let result = store
+        .get_messages_after(topic, &MessageFilter::default(), None, TEST_QUERY_SIZE)
         .await
         .unwrap();
 
@@ -41,24 +41,27 @@ async fn test_after_no_origin(ctx: &StoreContext) {
         )
     }
 
-    assert_eq!(result.next_id.unwrap().as_ref(), "4", "Check next_id");
+    assert_eq!(
+        result.next_cursor.as_ref(),
+        Some(&cursors[TEST_QUERY_SIZE - 1]),
+        "Check next_cursor"
+    );
 }
 
-// NOTE: Requires the dev MongoDB container (see `ops/docker-compose.yml`).
 #[named]
-#[test_context(StoreContext)]
-#[tokio::test]
-#[cfg_attr(not(feature = "storage-tests"), ignore)]
-async fn test_after_origin(ctx: &StoreContext) {
+pub async fn test_after_origin(store: &impl MessagesStore) {
     let topic = function_name!();
-    fill_store(ctx, TEST_CLIENT_ID, topic, 20).await;
+    let cursors = fill_store(store, TEST_CLIENT_ID, topic, 20).await;
 
     let origin = 4;
 
-    let result = ctx
-        .storage
-        .store
-        .get_messages_after(topic, Some(&origin.to_string()), TEST_QUERY_SIZE)
+    let result = store
+        .get_messages_after(
+            topic,
+            &MessageFilter::default(),
+            Some(&cursors[origin - 1]),
+            TEST_QUERY_SIZE,
+        )
         .await
         .unwrap();
 
@@ -71,35 +74,34 @@ async fn test_after_origin(ctx: &StoreContext) {
     for n in 0..TEST_QUERY_SIZE {
         assert_eq!(
             result.messages.get(n).unwrap().message_id.as_ref(),
-            (origin + n).to_string(),
+            (origin + 1 + n).to_string(),
             "check that item #{} equals expected {}",
             n,
-            origin + n
+            origin + 1 + n
         )
     }
 
     assert_eq!(
-        result.next_id.unwrap().as_ref(),
-        (origin + TEST_QUERY_SIZE).to_string(),
-        "Check next_id"
+        result.next_cursor.as_ref(),
+        Some(&cursors[origin + TEST_QUERY_SIZE - 1]),
+        "Check next_cursor"
     );
 }
 
-// NOTE: Requires the dev MongoDB container (see `ops/docker-compose.yml`).
 #[named]
-#[test_context(StoreContext)]
-#[tokio::test]
-#[cfg_attr(not(feature = "storage-tests"), ignore)]
-async fn test_after_origin_overflow(ctx: &StoreContext) {
+pub async fn test_after_origin_overflow(store: &impl MessagesStore) {
     let topic = function_name!();
-    fill_store(ctx, TEST_CLIENT_ID, topic, 20).await;
+    let cursors = fill_store(store, TEST_CLIENT_ID, topic, 20).await;
 
-    let origin = 19;
+    let origin = 18;
 
-    let result = ctx
-        .storage
-        .store
-        .get_messages_after(topic, Some(&origin.to_string()), TEST_QUERY_SIZE)
+    let result = store
+        .get_messages_after(
+            topic,
+            &MessageFilter::default(),
+            Some(&cursors[origin - 1]),
+            TEST_QUERY_SIZE,
+        )
         .await
         .unwrap();
 
@@ -108,29 +110,23 @@ async fn test_after_origin_overflow(ctx: &StoreContext) {
     for n in 0..2 {
         assert_eq!(
             result.messages.get(n).unwrap().message_id.as_ref(),
-            (origin + n).to_string(),
+            (origin + 1 + n).to_string(),
             "check that item #{} equals expected {}",
             n,
-            origin + n
+            origin + 1 + n
         )
     }
 
-    assert_eq!(result.next_id, None, "Check next_id");
+    assert_eq!(result.next_cursor, None, "Check next_cursor");
 }
 
-// NOTE: Requires the dev MongoDB container (see `ops/docker-compose.yml`).
 #[named]
-#[test_context(StoreContext)]
-#[tokio::test]
-#[cfg_attr(not(feature = "storage-tests"), ignore)]
-async fn test_before_no_origin(ctx: &StoreContext) {
+pub async fn test_before_no_origin(store: &impl MessagesStore) {
     let topic = function_name!();
-    fill_store(ctx, TEST_CLIENT_ID, topic, 20).await;
+    let cursors = fill_store(store, TEST_CLIENT_ID, topic, 20).await;
 
-    let result = ctx
-        .storage
-        .store
-        .get_messages_before(topic, None, TEST_QUERY_SIZE)
+    let result = store
+        .get_messages_before(topic, &MessageFilter::default(), None, TEST_QUERY_SIZE)
         .await
         .unwrap();
 
@@ -150,24 +146,27 @@ async fn test_before_no_origin(ctx: &StoreContext) {
         )
     }
 
-    assert_eq!(result.next_id.unwrap().as_ref(), "17", "Check next_id");
+    assert_eq!(
+        result.next_cursor.as_ref(),
+        Some(&cursors[20 - TEST_QUERY_SIZE]),
+        "Check next_cursor"
+    );
 }
 
-// NOTE: Requires the dev MongoDB container (see `ops/docker-compose.yml`).
 #[named]
-#[test_context(StoreContext)]
-#[tokio::test]
-#[cfg_attr(not(feature = "storage-tests"), ignore)]
-async fn test_before_origin(ctx: &StoreContext) {
+pub async fn test_before_origin(store: &impl MessagesStore) {
     let topic = function_name!();
-    fill_store(ctx, TEST_CLIENT_ID, topic, 20).await;
+    let cursors = fill_store(store, TEST_CLIENT_ID, topic, 20).await;
 
     let origin = 16;
 
-    let result = ctx
-        .storage
-        .store
-        .get_messages_before(topic, Some(&origin.to_string()), TEST_QUERY_SIZE)
+    let result = store
+        .get_messages_before(
+            topic,
+            &MessageFilter::default(),
+            Some(&cursors[origin - 1]),
+            TEST_QUERY_SIZE,
+        )
         .await
         .unwrap();
 
@@ -180,35 +179,34 @@ async fn test_before_origin(ctx: &StoreContext) {
     for n in 0..TEST_QUERY_SIZE {
         assert_eq!(
             result.messages.get(n).unwrap().message_id.as_ref(),
-            (origin - n).to_string(),
+            (origin - 1 - n).to_string(),
             "check that item #{} equals expected {}",
             n,
-            origin - n
+            origin - 1 - n
         )
     }
 
     assert_eq!(
-        result.next_id.unwrap().as_ref(),
-        (origin - TEST_QUERY_SIZE).to_string(),
-        "Check next_id"
+        result.next_cursor.as_ref(),
+        Some(&cursors[origin - TEST_QUERY_SIZE - 1]),
+        "Check next_cursor"
     );
 }
 
-// NOTE: Requires the dev MongoDB container (see `ops/docker-compose.yml`).
 #[named]
-#[test_context(StoreContext)]
-#[tokio::test]
-#[cfg_attr(not(feature = "storage-tests"), ignore)]
-async fn test_before_origin_overflow(ctx: &StoreContext) {
+pub async fn test_before_origin_overflow(store: &impl MessagesStore) {
     let topic = function_name!();
-    fill_store(ctx, TEST_CLIENT_ID, topic, 20).await;
+    let cursors = fill_store(store, TEST_CLIENT_ID, topic, 20).await;
 
-    let origin = 2;
+    let origin = 3;
 
-    let result = ctx
-        .storage
-        .store
-        .get_messages_before(topic, Some(&origin.to_string()), TEST_QUERY_SIZE)
+    let result = store
+        .get_messages_before(
+            topic,
+            &MessageFilter::default(),
+            Some(&cursors[origin - 1]),
+            TEST_QUERY_SIZE,
+        )
         .await
         .unwrap();
 
@@ -217,37 +215,31 @@ async fn test_before_origin_overflow(ctx: &StoreContext) {
     for n in 0..2 {
         assert_eq!(
             result.messages.get(n).unwrap().message_id.as_ref(),
-            (origin - n).to_string(),
+            (origin - 1 - n).to_string(),
             "check that item #{} equals expected {}",
             n,
-            origin - n
+            origin - 1 - n
         )
     }
 
-    assert_eq!(result.next_id, None, "Check next_id");
+    assert_eq!(result.next_cursor, None, "Check next_cursor");
 }
 
-// NOTE: Requires the dev MongoDB container (see `ops/docker-compose.yml`).
 #[named]
-#[test_context(StoreContext)]
-#[tokio::test]
-#[cfg_attr(not(feature = "storage-tests"), ignore)]
-async fn test_multi_topic(ctx: &StoreContext) {
+pub async fn test_multi_topic(store: &impl MessagesStore) {
     const NUM_TOPICS: usize = 5;
     const QUERY_SIZE: usize = 2;
 
     for t in 0..NUM_TOPICS {
         let topic = format!("{}-{}", function_name!(), t + 1);
-        fill_store(ctx, TEST_CLIENT_ID, topic.as_str(), QUERY_SIZE as i32).await;
+        fill_store(store, TEST_CLIENT_ID, topic.as_str(), QUERY_SIZE as i32).await;
     }
 
     for t in 0..NUM_TOPICS {
         let topic = format!("{}-{}", function_name!(), t + 1);
 
-        let result = ctx
-            .storage
-            .store
-            .get_messages_after(topic.as_str(), None, QUERY_SIZE)
+        let result = store
+            .get_messages_after(topic.as_str(), &MessageFilter::default(), None, QUERY_SIZE)
             .await
             .unwrap();
 
@@ -272,12 +264,8 @@ async fn test_multi_topic(ctx: &StoreContext) {
     }
 }
 
-// NOTE: Requires the dev MongoDB container (see `ops/docker-compose.yml`).
 #[named]
-#[test_context(StoreContext)]
-#[tokio::test]
-#[cfg_attr(not(feature = "storage-tests"), ignore)]
-async fn test_multi_clients(ctx: &StoreContext) {
+pub async fn test_multi_clients(store: &impl MessagesStore) {
     const NUM_CLIENTS: usize = 5;
     const QUERY_SIZE: usize = 2;
 
@@ -285,14 +273,12 @@ async fn test_multi_clients(ctx: &StoreContext) {
 
     for t in 0..NUM_CLIENTS {
         let client_id = format!("{}-{}", TEST_CLIENT_ID, t + 1);
-        fill_store(ctx, client_id.as_str(), topic, QUERY_SIZE as i32).await;
+        fill_store(store, client_id.as_str(), topic, QUERY_SIZE as i32).await;
     }
 
     for t in 0..NUM_CLIENTS {
-        let result = ctx
-            .storage
-            .store
-            .get_messages_after(topic, None, QUERY_SIZE)
+        let result = store
+            .get_messages_after(topic, &MessageFilter::default(), None, QUERY_SIZE)
             .await
             .unwrap();
 
@@ -317,19 +303,433 @@ async fn test_multi_clients(ctx: &StoreContext) {
     }
 }
 
-async fn fill_store(ctx: &StoreContext, client_id: &str, topic: &str, size: i32) {
-    for id in 1..(size + 1) {
-        ctx.storage
-            .store
-            .upsert_message(
-                "publish",
-                client_id,
+#[named]
+pub async fn test_paginate_identical_timestamps(store: &impl MessagesStore) {
+    let topic = function_name!();
+    fill_batch(store, topic, 20).await;
+
+    let mut ids = vec![];
+    let mut origin = None;
+    loop {
+        let result = store
+            .get_messages_after(
                 topic,
-                &id.to_string(),
-                id.to_string().as_str(),
+                &MessageFilter::default(),
+                origin.as_ref(),
+                TEST_QUERY_SIZE,
             )
             .await
             .unwrap();
+        ids.extend(result.messages.iter().map(|m| m.message_id.to_string()));
+        match result.next_cursor {
+            Some(next_cursor) => origin = Some(next_cursor),
+            None => break,
+        }
+    }
+
+    let expected: Vec<String> = (1..=20).map(|id| id.to_string()).collect();
+    assert_eq!(ids, expected, "check no message is duplicated or skipped");
+}
+
+#[named]
+pub async fn test_invalid_cursor(store: &impl MessagesStore) {
+    let topic = function_name!();
+    let cursors = fill_store(store, TEST_CLIENT_ID, topic, 1).await;
+
+    let origin = MessageCursor {
+        id: Arc::from("not-an-id"),
+        ..cursors[0].clone()
+    };
+    let res = store
+        .get_messages_after(
+            topic,
+            &MessageFilter::default(),
+            Some(&origin),
+            TEST_QUERY_SIZE,
+        )
+        .await;
+
+    assert!(matches!(res, Err(StoreError::InvalidCursor(_))));
+}
+
+#[named]
+pub async fn test_filter(store: &impl MessagesStore) {
+    let topic = function_name!();
+    let cursors = fill_store(store, TEST_CLIENT_ID, topic, 5).await;
+
+    store
+        .upsert_message(&NewMessage {
+            method: Arc::from("subscription"),
+            tag: Some(1100),
+            ..new_message("other-client", topic, "6")
+        })
+        .await
+        .unwrap();
+
+    let time_range = MessageFilter {
+        since: Some(cursors[1].ts),
+        until: Some(cursors[3].ts),
+        ..Default::default()
+    };
+    let result = store
+        .get_messages_after(topic, &time_range, None, TEST_QUERY_SIZE)
+        .await
+        .unwrap();
+    let ids: Vec<&str> = result
+        .messages
+        .iter()
+        .map(|m| m.message_id.as_ref())
+        .collect();
+    assert_eq!(ids, ["2", "3"], "check time range");
+
+    let method = MessageFilter {
+        method: Some(Arc::from("subscription")),
+        ..Default::default()
+    };
+    let result = store
+        .get_messages_after(topic, &method, None, TEST_QUERY_SIZE)
+        .await
+        .unwrap();
+    let ids: Vec<&str> = result
+        .messages
+        .iter()
+        .map(|m| m.message_id.as_ref())
+        .collect();
+    assert_eq!(ids, ["6"], "check method");
+
+    let tags = MessageFilter {
+        tags: Some(1100..=1199),
+        ..Default::default()
+    };
+    let result = store
+        .get_messages_after(topic, &tags, None, TEST_QUERY_SIZE)
+        .await
+        .unwrap();
+    let ids: Vec<&str> = result
+        .messages
+        .iter()
+        .map(|m| m.message_id.as_ref())
+        .collect();
+    assert_eq!(ids, ["6"], "check tags");
+
+    let client = MessageFilter {
+        client_id: Some(Arc::from(TEST_CLIENT_ID)),
+        ..Default::default()
+    };
+    let result = store
+        .get_messages_before(topic, &client, None, TEST_QUERY_SIZE)
+        .await
+        .unwrap();
+    let ids: Vec<&str> = result
+        .messages
+        .iter()
+        .map(|m| m.message_id.as_ref())
+        .collect();
+    assert_eq!(ids, ["5", "4", "3"], "check client");
+    assert_eq!(
+        result.next_cursor.as_ref(),
+        Some(&cursors[2]),
+        "Check next_cursor"
+    );
+}
+
+#[named]
+pub async fn test_topics_messages(store: &impl MessagesStore) {
+    let topic: Arc<str> = Arc::from(function_name!());
+    let other_topic: Arc<str> = Arc::from(format!("{topic}-other"));
+    let cursors = fill_store(store, TEST_CLIENT_ID, &topic, 3).await;
+    let other_cursors = fill_store(store, TEST_CLIENT_ID, &other_topic, 3).await;
+
+    let origins = [
+        TopicOrigin {
+            topic: topic.clone(),
+            origin: Some(cursors[0].clone()),
+        },
+        TopicOrigin {
+            topic: other_topic.clone(),
+            origin: None,
+        },
+    ];
+    // The budget is shared by both topics, from the oldest message
+    let pages = store.get_topics_messages_after(&origins, 4).await.unwrap();
+
+    let ids: Vec<Vec<&str>> = pages
+        .iter()
+        .map(|page| {
+            page.messages
+                .iter()
+                .map(|m| m.message_id.as_ref())
+                .collect()
+        })
+        .collect();
+    assert_eq!(ids, [vec!["2", "3"], vec!["1", "2"]], "check result");
+    assert_eq!(
+        pages[0].next_cursor.as_ref(),
+        Some(&cursors[2]),
+        "Check next_cursor"
+    );
+    assert_eq!(
+        pages[1].next_cursor.as_ref(),
+        Some(&other_cursors[1]),
+        "Check next_cursor"
+    );
+
+    let origins = [
+        TopicOrigin {
+            topic: topic.clone(),
+            origin: pages[0].next_cursor.clone(),
+        },
+        TopicOrigin {
+            topic: other_topic.clone(),
+            origin: pages[1].next_cursor.clone(),
+        },
+    ];
+    let pages = store.get_topics_messages_after(&origins, 4).await.unwrap();
+
+    let ids: Vec<Vec<&str>> = pages
+        .iter()
+        .map(|page| {
+            page.messages
+                .iter()
+                .map(|m| m.message_id.as_ref())
+                .collect()
+        })
+        .collect();
+    assert_eq!(ids, [vec![], vec!["3"]], "check resumed result");
+    assert!(
+        pages.iter().all(|page| page.next_cursor.is_none()),
+        "Check next_cursor"
+    );
+}
+
+#[named]
+pub async fn test_has_messages(store: &impl MessagesStore) {
+    let topic = function_name!();
+    fill_store(store, TEST_CLIENT_ID, topic, 1).await;
+
+    assert!(store.has_messages(TEST_CLIENT_ID, topic).await.unwrap());
+    assert!(!store
+        .has_messages(&format!("{TEST_CLIENT_ID}-other"), topic)
+        .await
+        .unwrap());
+    assert!(!store
+        .has_messages(TEST_CLIENT_ID, &format!("{topic}-other"))
+        .await
+        .unwrap());
+
+    assert!(store.has_message(TEST_CLIENT_ID, topic, "1").await.unwrap());
+    assert!(!store.has_message(TEST_CLIENT_ID, topic, "2").await.unwrap());
+
+    let other_topic = format!("{topic}-other");
+    let topics = store
+        .topics_with_messages(TEST_CLIENT_ID, &[topic, &other_topic])
+        .await
+        .unwrap();
+    assert_eq!(topics, [Arc::from(topic)]);
+    assert!(store
+        .topics_with_messages(TEST_CLIENT_ID, &[])
+        .await
+        .unwrap()
+        .is_empty());
+}
+
+#[named]
+pub async fn test_count_messages(store: &impl MessagesStore) {
+    let topic = function_name!();
+    let other_topic = format!("{topic}-other");
+    // The client is only used by this test
+    let client_id = format!("{topic}-client");
+    fill_store(store, &client_id, topic, 3).await;
+    fill_store(store, &client_id, &other_topic, 2).await;
+
+    assert_eq!(store.count_messages(&client_id, None).await.unwrap(), 5);
+    assert_eq!(
+        store.count_messages(&client_id, Some(topic)).await.unwrap(),
+        3
+    );
+    assert_eq!(
+        store
+            .count_messages(&client_id, Some(&other_topic))
+            .await
+            .unwrap(),
+        2
+    );
+    assert_eq!(
+        store
+            .count_messages(&format!("{client_id}-other"), None)
+            .await
+            .unwrap(),
+        0
+    );
+}
+
+#[named]
+pub async fn test_upsert_messages(store: &impl MessagesStore) {
+    let topic: Arc<str> = Arc::from(function_name!());
+    let message = |message_id: &str| NewMessage {
+        method: Arc::from("publish"),
+        client_id: Arc::from(TEST_CLIENT_ID),
+        topic: topic.clone(),
+        message_id: Arc::from(message_id),
+        message: Arc::from(message_id),
+        tag: None,
+        expires_at: None,
+    };
+
+    store
+        .upsert_messages(&[message("1"), message("2"), message("3")])
+        .await
+        .unwrap();
+    // Upserting again doesn't duplicate the messages
+    let cursors: Vec<MessageCursor> = store
+        .upsert_messages(&[message("3"), message("4")])
+        .await
+        .unwrap()
+        .into_iter()
+        .map(Result::unwrap)
+        .collect();
+
+    let result = store
+        .get_messages_after(&topic, &MessageFilter::default(), None, 10)
+        .await
+        .unwrap();
+    let mut ids: Vec<&str> = result
+        .messages
+        .iter()
+        .map(|m| m.message_id.as_ref())
+        .collect();
+    ids.sort();
+    assert_eq!(ids, ["1", "2", "3", "4"], "check result");
+
+    for (message_id, cursor) in ["3", "4"].into_iter().zip(&cursors) {
+        let message = result
+            .messages
+            .iter()
+            .find(|m| m.message_id.as_ref() == message_id)
+            .unwrap();
+        assert_eq!(
+            message.cursor.as_ref(),
+            Some(cursor),
+            "check the cursor of {message_id}"
+        );
+    }
+}
+
+#[named]
+pub async fn test_delete_expired_messages(store: &impl MessagesStore) {
+    let topic = function_name!();
+    let now = Utc::now();
+
+    for (client_id, expires_at) in [
+        ("expired", Some(now - Duration::seconds(1))),
+        ("pending", Some(now + Duration::hours(1))),
+        ("forever", None),
+    ] {
+        store
+            .upsert_message(&NewMessage {
+                expires_at,
+                ..new_message(client_id, topic, "1")
+            })
+            .await
+            .unwrap();
+    }
+
+    let deleted = store.delete_expired_messages(now).await.unwrap();
+    assert_eq!(deleted, 1, "check deleted count");
+
+    assert!(!store.has_messages("expired", topic).await.unwrap());
+    assert!(store.has_messages("pending", topic).await.unwrap());
+    assert!(store.has_messages("forever", topic).await.unwrap());
+}
+
+#[named]
+pub async fn test_delete_messages(store: &impl MessagesStore) {
+    let topic = function_name!();
+    let other_topic = format!("{topic}-other");
+    let client_id = format!("{TEST_CLIENT_ID}-{topic}");
+    fill_store(store, &client_id, topic, 3).await;
+    fill_store(store, &client_id, &other_topic, 1).await;
+
+    assert_eq!(
+        store.delete_message(&client_id, topic, "1").await.unwrap(),
+        1
+    );
+    assert_eq!(
+        store.delete_message(&client_id, topic, "1").await.unwrap(),
+        0
+    );
+    assert_eq!(
+        store
+            .delete_topic_messages(&client_id, topic)
+            .await
+            .unwrap(),
+        2
+    );
+    assert!(!store.has_messages(&client_id, topic).await.unwrap());
+    assert!(store.has_messages(&client_id, &other_topic).await.unwrap());
+
+    assert_eq!(store.delete_client_messages(&client_id).await.unwrap(), 1);
+    assert!(!store.has_messages(&client_id, &other_topic).await.unwrap());
+}
+
+pub async fn test_ping<S: MessagesStore + RegistrationStore>(store: &S) {
+    assert!(MessagesStore::ping(store).await.is_ok());
+    assert!(RegistrationStore::ping(store).await.is_ok());
+}
+
+/// Only run against the SQL stores, MongoDB's client is closed once dropped.
+pub async fn test_close<S: MessagesStore>(store: &S) {
+    // The closed stores are unreachable
+    MessagesStore::close(store).await;
+    assert!(MessagesStore::ping(store).await.is_err());
+}
+
+/// Stores the `1..=size` messages, returning their cursors in order.
+async fn fill_store(
+    store: &impl MessagesStore,
+    client_id: &str,
+    topic: &str,
+    size: i32,
+) -> Vec<MessageCursor> {
+    let mut cursors = vec![];
+    for id in 1..(size + 1) {
+        let cursor = store
+            .upsert_message(&new_message(client_id, topic, &id.to_string()))
+            .await
+            .unwrap();
+        cursors.push(cursor);
         std::thread::sleep(time::Duration::from_millis(2));
     }
+
+    cursors
+}
+
+/// Stores the `1..=size` messages at once, so that many share a timestamp.
+async fn fill_batch(store: &impl MessagesStore, topic: &str, size: i32) {
+    let messages: Vec<NewMessage> = (1..(size + 1))
+        .map(|id| NewMessage {
+            method: Arc::from("publish"),
+            client_id: Arc::from(TEST_CLIENT_ID),
+            topic: Arc::from(topic),
+            message_id: Arc::from(id.to_string()),
+            message: Arc::from(id.to_string()),
+            tag: None,
+            expires_at: None,
+        })
+        .collect();
+
+    store.upsert_messages(&messages).await.unwrap();
+}
+
+/// A `publish` message whose content is its ID.
+fn new_message(client_id: &str, topic: &str, message_id: &str) -> NewMessage {
+    NewMessage {
+        method: Arc::from("publish"),
+        client_id: Arc::from(client_id),
+        topic: Arc::from(topic),
+        message_id: Arc::from(message_id),
+        message: Arc::from(message_id),
+        tag: None,
+        expires_at: None,
+    }
 }
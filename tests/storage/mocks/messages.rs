@@ -1,18 +1,35 @@
 use {
     async_trait::async_trait,
-    chrono::Utc,
+    chrono::{DateTime, Utc},
     gilgamesh::store::{
-        messages::{Message, MessagesStore, StoreMessages},
+        messages::{
+            split_topics_messages,
+            Message,
+            MessageCursor,
+            MessageFilter,
+            MessagesStore,
+            NewMessage,
+            StoreMessages,
+            TopicOrigin,
+        },
         StoreError,
     },
     moka::future::Cache,
-    std::{fmt::Debug, sync::Arc},
+    std::{
+        fmt::Debug,
+        sync::{
+            atomic::{AtomicBool, Ordering},
+            Arc,
+        },
+    },
 };
 
 #[derive(Debug)]
 pub struct MockMessageStore {
     pub messages: Cache<String, Message>,
     pub client_id: Option<String>,
+    /// Fails the pings, as if the database went down.
+    pub is_unavailable: AtomicBool,
 }
 
 fn cache_key(client_id: &str, topic: &str, message_id: &str) -> String {
@@ -24,6 +41,7 @@ impl MockMessageStore {
         Self {
             messages: Cache::builder().build(),
             client_id: None,
+            is_unavailable: AtomicBool::new(false),
         }
     }
 
@@ -49,53 +67,196 @@ impl MockMessageStore {
     pub fn test_get_messages(&self) -> Vec<Message> {
         self.messages.iter().map(|(_, v)| v).collect()
     }
+
+    pub fn test_get_filtered_messages(&self, filter: &MessageFilter) -> Vec<Message> {
+        self.test_get_messages()
+            .into_iter()
+            .filter(|m| filter.matches(m))
+            .collect()
+    }
+
+    async fn test_delete(&self, predicate: impl Fn(&Message) -> bool) -> Result<u64, StoreError> {
+        let keys: Vec<_> = self
+            .messages
+            .iter()
+            .filter(|(_, m)| predicate(m))
+            .map(|(k, _)| k)
+            .collect();
+
+        for key in &keys {
+            self.messages.invalidate(key.as_ref()).await;
+        }
+
+        Ok(keys.len() as u64)
+    }
 }
 
 #[async_trait]
 impl MessagesStore for MockMessageStore {
-    async fn upsert_message(
+    async fn upsert_message(&self, message: &NewMessage) -> Result<MessageCursor, StoreError> {
+        let cursor = MessageCursor {
+            ts: Utc::now(),
+            id: Arc::from(cache_key(
+                &message.client_id,
+                &message.topic,
+                &message.message_id,
+            )),
+        };
+
+        self.test_add(message.clone().into_message(cursor.clone()))
+            .await;
+
+        Ok(cursor)
+    }
+
+    async fn upsert_messages(
         &self,
-        method: &str,
-        client_id: &str,
-        topic: &str,
-        message_id: &str,
-        message: &str,
-    ) -> Result<(), StoreError> {
-        self.test_add(Message {
-            id: None,
-            timestamp: Utc::now().into(),
-            method: Arc::from(method),
-            client_id: Arc::from(client_id),
-            message_id: Arc::from(message_id),
-            topic: Arc::from(topic),
-            message: Arc::from(message),
-        })
-        .await;
+        messages: &[NewMessage],
+    ) -> Result<Vec<Result<MessageCursor, StoreError>>, StoreError> {
+        let mut cursors = Vec::with_capacity(messages.len());
+        for message in messages {
+            cursors.push(self.upsert_message(message).await);
+        }
 
-        Ok(())
+        Ok(cursors)
     }
 
     async fn get_messages_after(
         &self,
         _topic: &str,
-        _origin: Option<&str>,
+        filter: &MessageFilter,
+        _origin: Option<&MessageCursor>,
         _message_count: usize,
     ) -> Result<StoreMessages, StoreError> {
         Ok(StoreMessages {
-            messages: self.test_get_messages(),
-            next_id: Some(Arc::from("after")),
+            messages: self.test_get_filtered_messages(filter),
+            next_cursor: Some(MessageCursor {
+                ts: Utc::now(),
+                id: Arc::from("after"),
+            }),
         })
     }
 
     async fn get_messages_before(
         &self,
         _topic: &str,
-        _origin: Option<&str>,
+        filter: &MessageFilter,
+        _origin: Option<&MessageCursor>,
         _message_count: usize,
     ) -> Result<StoreMessages, StoreError> {
         Ok(StoreMessages {
-            messages: self.test_get_messages(),
-            next_id: Some(Arc::from("before")),
+            messages: self.test_get_filtered_messages(filter),
+            next_cursor: Some(MessageCursor {
+                ts: Utc::now(),
+                id: Arc::from("before"),
+            }),
         })
     }
+
+    async fn get_topics_messages_after(
+        &self,
+        origins: &[TopicOrigin],
+        message_count: usize,
+    ) -> Result<Vec<StoreMessages>, StoreError> {
+        let messages = self
+            .test_get_messages()
+            .into_iter()
+            .filter(|m| origins.iter().any(|o| o.topic == m.topic))
+            .collect();
+
+        Ok(split_topics_messages(origins, messages, message_count))
+    }
+
+    async fn has_messages(&self, client_id: &str, topic: &str) -> Result<bool, StoreError> {
+        Ok(self
+            .messages
+            .iter()
+            .any(|(_, m)| m.client_id.as_ref() == client_id && m.topic.as_ref() == topic))
+    }
+
+    async fn has_message(
+        &self,
+        client_id: &str,
+        topic: &str,
+        message_id: &str,
+    ) -> Result<bool, StoreError> {
+        Ok(self.messages.iter().any(|(_, m)| {
+            m.client_id.as_ref() == client_id
+                && m.topic.as_ref() == topic
+                && m.message_id.as_ref() == message_id
+        }))
+    }
+
+    async fn topics_with_messages(
+        &self,
+        client_id: &str,
+        topics: &[&str],
+    ) -> Result<Vec<Arc<str>>, StoreError> {
+        let mut found: Vec<Arc<str>> = self
+            .messages
+            .iter()
+            .filter(|(_, m)| {
+                m.client_id.as_ref() == client_id && topics.contains(&m.topic.as_ref())
+            })
+            .map(|(_, m)| m.topic)
+            .collect();
+        found.sort_unstable();
+        found.dedup();
+
+        Ok(found)
+    }
+
+    async fn count_messages(
+        &self,
+        client_id: &str,
+        topic: Option<&str>,
+    ) -> Result<u64, StoreError> {
+        Ok(self
+            .messages
+            .iter()
+            .filter(|(_, m)| {
+                m.client_id.as_ref() == client_id
+                    && topic.is_none_or(|topic| m.topic.as_ref() == topic)
+            })
+            .count() as u64)
+    }
+
+    async fn delete_message(
+        &self,
+        client_id: &str,
+        topic: &str,
+        message_id: &str,
+    ) -> Result<u64, StoreError> {
+        self.test_delete(|m| {
+            m.client_id.as_ref() == client_id
+                && m.topic.as_ref() == topic
+                && m.message_id.as_ref() == message_id
+        })
+        .await
+    }
+
+    async fn delete_topic_messages(&self, client_id: &str, topic: &str) -> Result<u64, StoreError> {
+        self.test_delete(|m| m.client_id.as_ref() == client_id && m.topic.as_ref() == topic)
+            .await
+    }
+
+    async fn delete_client_messages(&self, client_id: &str) -> Result<u64, StoreError> {
+        self.test_delete(|m| m.client_id.as_ref() == client_id)
+            .await
+    }
+
+    async fn delete_expired_messages(&self, now: DateTime<Utc>) -> Result<u64, StoreError> {
+        let now = now.into();
+        self.test_delete(|m| matches!(m.expires_at, Some(expires_at) if expires_at <= now))
+            .await
+    }
+
+    async fn ping(&self) -> Result<(), StoreError> {
+        if self.is_unavailable.load(Ordering::SeqCst) {
+            return Err(StoreError::Sql(sqlx::Error::PoolTimedOut));
+        }
+        Ok(())
+    }
+
+    async fn close(&self) {}
 }
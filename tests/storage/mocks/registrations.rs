@@ -1,22 +1,27 @@
 use {
     async_trait::async_trait,
+    chrono::{DateTime, Utc},
     gilgamesh::store::{
         registrations::{Registration, RegistrationStore},
         StoreError,
     },
     moka::future::Cache,
     std::{fmt::Debug, sync::Arc},
+    tokio::sync::Mutex,
 };
 
 #[derive(Debug)]
 pub struct MockRegistrationStore {
     pub registrations: Cache<String, Registration>,
+    /// Serializes the read-modify-write updates, like a database would.
+    updates: Mutex<()>,
 }
 
 impl MockRegistrationStore {
     pub fn new() -> Self {
         Self {
             registrations: Cache::builder().build(),
+            updates: Mutex::new(()),
         }
     }
 }
@@ -26,26 +31,130 @@ impl RegistrationStore for MockRegistrationStore {
     async fn upsert_registration(
         &self,
         client_id: &str,
-        tags: Vec<&str>,
+        tags: Option<Vec<&str>>,
         relay_url: &str,
+        expires_at: Option<DateTime<Utc>>,
     ) -> Result<(), StoreError> {
+        let _guard = self.updates.lock().await;
+        let now = Utc::now().into();
+        let previous = self.registrations.get(client_id);
+
+        let tags = match tags {
+            Some(tags) => tags.iter().map(|s| Arc::from(s.to_string())).collect(),
+            None => previous
+                .as_ref()
+                .map(|r| r.tags.clone())
+                .unwrap_or_default(),
+        };
+
+        let topics = previous
+            .as_ref()
+            .map(|r| r.topics.clone())
+            .unwrap_or_default();
+
         let reg = Registration {
             id: None,
             client_id: Arc::from(client_id),
-            tags: tags.iter().map(|s| Arc::from(s.to_string())).collect(),
+            tags,
+            topics,
             relay_url: Arc::from(relay_url),
+            created_at: previous.and_then(|r| r.created_at).or(Some(now)),
+            updated_at: Some(now),
+            expires_at: expires_at.map(Into::into),
         };
 
         self.registrations.insert(client_id.to_string(), reg).await;
         Ok(())
     }
 
+    async fn update_registration(
+        &self,
+        client_id: &str,
+        add_tags: Vec<&str>,
+        remove_tags: Vec<&str>,
+        relay_url: &str,
+        expires_at: Option<DateTime<Utc>>,
+    ) -> Result<(), StoreError> {
+        let _guard = self.updates.lock().await;
+
+        let mut registration = self.get_registration(client_id).await?;
+        update_list(&mut registration.tags, add_tags, remove_tags);
+        registration.relay_url = Arc::from(relay_url);
+        registration.updated_at = Some(Utc::now().into());
+        registration.expires_at = expires_at.map(Into::into);
+
+        self.registrations
+            .insert(client_id.to_string(), registration)
+            .await;
+        Ok(())
+    }
+
+    async fn update_registration_topics(
+        &self,
+        client_id: &str,
+        subscribe: Vec<&str>,
+        unsubscribe: Vec<&str>,
+    ) -> Result<(), StoreError> {
+        let _guard = self.updates.lock().await;
+
+        let mut registration = self.get_registration(client_id).await?;
+        update_list(&mut registration.topics, subscribe, unsubscribe);
+
+        self.registrations
+            .insert(client_id.to_string(), registration)
+            .await;
+        Ok(())
+    }
+
     async fn get_registration(&self, client_id: &str) -> Result<Registration, StoreError> {
+        let now = Utc::now();
         self.registrations
             .get(client_id)
+            .filter(|r| {
+                r.expires_at
+                    .is_none_or(|expires_at| expires_at.to_chrono() > now)
+            })
             .ok_or(StoreError::NotFound(
                 "registration".to_string(),
                 client_id.to_string(),
             ))
     }
+
+    async fn delete_registration(&self, client_id: &str) -> Result<u64, StoreError> {
+        Ok(self.registrations.remove(client_id).await.map_or(0, |_| 1))
+    }
+
+    async fn delete_expired_registrations(&self, now: DateTime<Utc>) -> Result<u64, StoreError> {
+        let expired = self
+            .registrations
+            .iter()
+            .filter(|(_, r)| {
+                r.expires_at
+                    .is_some_and(|expires_at| expires_at.to_chrono() <= now)
+            })
+            .map(|(client_id, _)| client_id)
+            .collect::<Vec<_>>();
+
+        for client_id in &expired {
+            self.registrations.invalidate(client_id.as_ref()).await;
+        }
+        Ok(expired.len() as u64)
+    }
+
+    async fn ping(&self) -> Result<(), StoreError> {
+        Ok(())
+    }
+
+    async fn close(&self) {}
+}
+
+/// Removes the `remove` values from the list and appends the missing `add`
+/// ones.
+fn update_list(list: &mut Vec<Arc<str>>, add: Vec<&str>, remove: Vec<&str>) {
+    list.retain(|value| !remove.contains(&value.as_ref()));
+    for value in add {
+        if !list.iter().any(|listed| listed.as_ref() == value) {
+            list.push(Arc::from(value));
+        }
+    }
 }
@@ -0,0 +1,41 @@
+use {
+    async_trait::async_trait,
+    chrono::{DateTime, Utc},
+    gilgamesh::store::{signatures::SignatureStore, StoreError},
+    std::{collections::HashSet, sync::Mutex},
+};
+
+#[derive(Debug, Default)]
+pub struct MockSignatureStore {
+    pub signatures: Mutex<HashSet<String>>,
+}
+
+impl MockSignatureStore {
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+#[async_trait]
+impl SignatureStore for MockSignatureStore {
+    async fn record_signature(
+        &self,
+        signature: &str,
+        _expires_at: DateTime<Utc>,
+    ) -> Result<bool, StoreError> {
+        Ok(self
+            .signatures
+            .lock()
+            .unwrap()
+            .insert(signature.to_string()))
+    }
+
+    async fn delete_signature(&self, signature: &str) -> Result<(), StoreError> {
+        self.signatures.lock().unwrap().remove(signature);
+        Ok(())
+    }
+
+    async fn delete_expired_signatures(&self, _now: DateTime<Utc>) -> Result<u64, StoreError> {
+        Ok(0)
+    }
+}
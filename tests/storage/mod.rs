@@ -1,3 +1,62 @@
+/// Runs the store test suites against the store of `$context`, each test
+/// gets the `$attrs` and a fresh context. The `$extra` tests of the messages
+/// suite only apply to some backends.
+macro_rules! store_tests {
+    ($context:ty, $attrs:tt) => {
+        store_tests!($context, $attrs, []);
+    };
+    ($context:ty, $attrs:tt, [$($extra:ident),* $(,)?]) => {
+        store_tests!(@suite $context, $attrs, messages, [
+            test_after_no_origin,
+            test_after_origin,
+            test_after_origin_overflow,
+            test_before_no_origin,
+            test_before_origin,
+            test_before_origin_overflow,
+            test_multi_topic,
+            test_multi_clients,
+            test_paginate_identical_timestamps,
+            test_invalid_cursor,
+            test_filter,
+            test_topics_messages,
+            test_has_messages,
+            test_count_messages,
+            test_upsert_messages,
+            test_delete_expired_messages,
+            test_delete_messages,
+            test_ping,
+            $($extra,)*
+        ]);
+        store_tests!(@suite $context, $attrs, registrations, [
+            test_registration,
+            test_registration_topics,
+            test_registration_lifecycle,
+            test_registration_tags,
+            test_concurrent_registration_tags,
+            test_registration_not_found,
+        ]);
+        store_tests!(@suite $context, $attrs, signatures, [
+            test_record_signature,
+            test_delete_signature,
+        ]);
+    };
+    (@suite $context:ty, $attrs:tt, $suite:ident, [$($test:ident),* $(,)?]) => {
+        $(store_tests!(@test $context, $attrs, $suite, $test);)*
+    };
+    (@test $context:ty, [$(#[$attr:meta])*], $suite:ident, $test:ident) => {
+        #[::test_context::test_context($context)]
+        #[::tokio::test]
+        $(#[$attr])*
+        async fn $test(ctx: &$context) {
+            crate::storage::$suite::$test(&ctx.storage.store).await;
+        }
+    };
+}
+
 pub mod messages;
 pub mod mocks;
+mod mongo;
+mod postgres;
 pub mod registrations;
+pub mod signatures;
+mod sqlite;
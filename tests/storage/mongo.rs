@@ -0,0 +1,4 @@
+use crate::context::StoreContext;
+
+// NOTE: Requires the dev MongoDB container (see `ops/docker-compose.yml`).
+store_tests!(StoreContext, [#[cfg_attr(not(feature = "storage-tests"), ignore)]]);
@@ -0,0 +1,82 @@
+use {
+    crate::context::PostgresStoreContext,
+    ::function_name::named,
+    futures::StreamExt,
+    gilgamesh::{
+        invalidation::{Invalidation, InvalidationBus},
+        store::messages::{MessageFilter, MessagesStore, NewMessage},
+    },
+    std::{sync::Arc, time},
+    test_context::test_context,
+};
+
+// NOTE: Requires the dev PostgreSQL container (see
+// `ops/docker-compose.storage.yml`).
+store_tests!(
+    PostgresStoreContext,
+    [#[cfg_attr(not(feature = "storage-tests"), ignore)]],
+    [test_close]
+);
+
+// NOTE: Requires the dev PostgreSQL container (see
+// `ops/docker-compose.storage.yml`).
+#[named]
+#[test_context(PostgresStoreContext)]
+#[tokio::test]
+#[cfg_attr(not(feature = "storage-tests"), ignore)]
+async fn test_invalidation_bus(ctx: &PostgresStoreContext) {
+    let store = &ctx.storage.store;
+    let mut invalidations = store.subscribe().await.unwrap();
+
+    store.publish(function_name!()).await.unwrap();
+
+    let invalidation = tokio::time::timeout(time::Duration::from_secs(5), invalidations.next())
+        .await
+        .unwrap()
+        .unwrap()
+        .unwrap();
+    assert_eq!(
+        invalidation,
+        Invalidation::Registration(Arc::from(function_name!()))
+    );
+}
+
+// NOTE: Requires the dev PostgreSQL container (see
+// `ops/docker-compose.storage.yml`).
+#[named]
+#[test_context(PostgresStoreContext)]
+#[tokio::test]
+#[cfg_attr(not(feature = "storage-tests"), ignore)]
+async fn test_upsert_messages_partial_failure(ctx: &PostgresStoreContext) {
+    let store = &ctx.storage.store;
+    let topic = function_name!();
+    let message = |message_id: &str, message: &str| NewMessage {
+        method: Arc::from("publish"),
+        client_id: Arc::from("12345"),
+        topic: Arc::from(topic),
+        message_id: Arc::from(message_id),
+        message: Arc::from(message),
+        tag: None,
+        expires_at: None,
+    };
+
+    // PostgreSQL rejects the NUL characters in text
+    let results = store
+        .upsert_messages(&[message("1", "1"), message("2", "\0"), message("3", "3")])
+        .await
+        .unwrap();
+    assert!(results[0].is_ok());
+    assert!(results[1].is_err());
+    assert!(results[2].is_ok());
+
+    let result = store
+        .get_messages_after(topic, &MessageFilter::default(), None, 10)
+        .await
+        .unwrap();
+    let message_ids: Vec<&str> = result
+        .messages
+        .iter()
+        .map(|message| message.message_id.as_ref())
+        .collect();
+    assert_eq!(message_ids, ["1", "3"]);
+}
@@ -1,34 +1,29 @@
+//! The tests of the registration stores, run against each backend by
+//! `store_tests!`.
+
 use {
-    crate::context::StoreContext,
+    chrono::{Duration, Utc},
+    futures::future::join_all,
     gilgamesh::store::{
         registrations::{Registration, RegistrationStore},
         StoreError,
     },
     std::sync::Arc,
-    test_context::test_context,
 };
 
 const TEST_CLIENT_ID: &str = "12345";
 const TEST_RELAY_URL: &str = "https:://test.relay.walletconnect.com";
+const OTHER_RELAY_URL: &str = "https:://other.relay.walletconnect.com";
+const TEST_UPDATE_COUNT: u32 = 20;
 
-// NOTE: Requires the dev MongoDB container (see `ops/docker-compose.yml`).
-#[test_context(StoreContext)]
-#[tokio::test]
-#[cfg_attr(not(feature = "storage-tests"), ignore)]
-async fn test_registration(ctx: &StoreContext) {
+pub async fn test_registration(store: &impl RegistrationStore) {
     const TAGS: [&str; 2] = ["1234", "5678"];
-    ctx.storage
-        .store
-        .upsert_registration(TEST_CLIENT_ID, Vec::from(TAGS), TEST_RELAY_URL)
+    store
+        .upsert_registration(TEST_CLIENT_ID, Some(Vec::from(TAGS)), TEST_RELAY_URL, None)
         .await
         .unwrap();
 
-    let registration = ctx
-        .storage
-        .store
-        .get_registration(TEST_CLIENT_ID)
-        .await
-        .unwrap();
+    let registration = store.get_registration(TEST_CLIENT_ID).await.unwrap();
     assert_eq!(registration.client_id.as_ref(), TEST_CLIENT_ID);
     assert_eq!(registration.relay_url.as_ref(), TEST_RELAY_URL);
 
@@ -36,15 +31,226 @@ async fn test_registration(ctx: &StoreContext) {
     assert_eq!(tags, TAGS);
 }
 
-// NOTE: Requires the dev MongoDB container (see `ops/docker-compose.yml`).
-#[test_context(StoreContext)]
-#[tokio::test]
-#[cfg_attr(not(feature = "storage-tests"), ignore)]
-async fn test_registration_not_found(ctx: &StoreContext) {
+pub async fn test_registration_topics(store: &impl RegistrationStore) {
+    let client_id = format!("{TEST_CLIENT_ID}-topics");
+    let topics = |registration: &Registration| -> Vec<String> {
+        registration
+            .topics
+            .iter()
+            .map(ToString::to_string)
+            .collect()
+    };
+
+    store
+        .upsert_registration(&client_id, Some(vec!["1234"]), TEST_RELAY_URL, None)
+        .await
+        .unwrap();
+    assert!(store
+        .get_registration(&client_id)
+        .await
+        .unwrap()
+        .topics
+        .is_empty());
+
+    // Known topics aren't added twice, unknown ones are ignored when removed
+    store
+        .update_registration_topics(&client_id, vec!["topic-1", "topic-2"], vec![])
+        .await
+        .unwrap();
+    store
+        .update_registration_topics(&client_id, vec!["topic-2", "topic-3"], vec![
+            "topic-1", "topic-4",
+        ])
+        .await
+        .unwrap();
+
+    // Registering again keeps the topics
+    store
+        .upsert_registration(&client_id, Some(vec!["5678"]), TEST_RELAY_URL, None)
+        .await
+        .unwrap();
+
+    let registration = store.get_registration(&client_id).await.unwrap();
+    let tags: Vec<&str> = registration.tags.iter().map(Arc::as_ref).collect();
+    assert_eq!(tags, ["5678"]);
+    assert_eq!(topics(&registration), ["topic-2", "topic-3"]);
+
+    // A deleted registration isn't recreated by a late subscription
+    assert_eq!(store.delete_registration(&client_id).await.unwrap(), 1);
+    assert!(matches!(
+        store
+            .update_registration_topics(&client_id, vec!["topic-1"], vec![])
+            .await,
+        Err(StoreError::NotFound(_, _))
+    ));
+    assert!(matches!(
+        store.get_registration(&client_id).await,
+        Err(StoreError::NotFound(_, _))
+    ));
+}
+
+pub async fn test_registration_lifecycle(store: &impl RegistrationStore) {
+    const CLIENT_ID: &str = "12345-lifecycle";
+    const EXPIRED_CLIENT_ID: &str = "12345-lifecycle-expired";
+
+    let now = Utc::now();
+    store
+        .upsert_registration(CLIENT_ID, Some(vec!["1234"]), TEST_RELAY_URL, None)
+        .await
+        .unwrap();
+    let created = store.get_registration(CLIENT_ID).await.unwrap();
+    assert!(created.created_at.is_some());
+    assert_eq!(created.updated_at, created.created_at);
+    assert_eq!(created.expires_at, None);
+
+    // Registering again keeps the creation time
+    store
+        .upsert_registration(
+            CLIENT_ID,
+            Some(vec!["1234"]),
+            TEST_RELAY_URL,
+            Some(now + Duration::hours(1)),
+        )
+        .await
+        .unwrap();
+    let updated = store.get_registration(CLIENT_ID).await.unwrap();
+    assert_eq!(updated.created_at, created.created_at);
+    assert!(updated.updated_at >= created.updated_at);
+    assert!(updated.expires_at.is_some());
+
+    // Expired registrations are hidden until they are deleted
+    store
+        .upsert_registration(
+            EXPIRED_CLIENT_ID,
+            Some(vec!["1234"]),
+            TEST_RELAY_URL,
+            Some(now - Duration::seconds(1)),
+        )
+        .await
+        .unwrap();
+    assert!(matches!(
+        store.get_registration(EXPIRED_CLIENT_ID).await,
+        Err(StoreError::NotFound(_, _))
+    ));
+    assert_eq!(store.delete_expired_registrations(now).await.unwrap(), 1);
+    assert_eq!(
+        store.delete_registration(EXPIRED_CLIENT_ID).await.unwrap(),
+        0
+    );
+
+    assert_eq!(store.delete_registration(CLIENT_ID).await.unwrap(), 1);
+    assert!(matches!(
+        store.get_registration(CLIENT_ID).await,
+        Err(StoreError::NotFound(_, _))
+    ));
+}
+
+pub async fn test_registration_tags(store: &impl RegistrationStore) {
+    const CLIENT_ID: &str = "12345-tags";
+    const MISSING_CLIENT_ID: &str = "12345-tags-missing";
+
+    store
+        .upsert_registration(CLIENT_ID, Some(vec!["1234", "5678"]), TEST_RELAY_URL, None)
+        .await
+        .unwrap();
+
+    // Known tags aren't added twice, unknown ones are ignored when removed,
+    // the relay and the expiry are replaced
+    let expires_at = Utc::now() + Duration::days(1);
+    store
+        .update_registration(
+            CLIENT_ID,
+            vec!["5678", "4000"],
+            vec!["1234", "9999"],
+            OTHER_RELAY_URL,
+            Some(expires_at),
+        )
+        .await
+        .unwrap();
+
+    let registration = store.get_registration(CLIENT_ID).await.unwrap();
+    let tags: Vec<&str> = registration.tags.iter().map(Arc::as_ref).collect();
+    assert_eq!(tags, ["5678", "4000"]);
+    assert_eq!(registration.relay_url.as_ref(), OTHER_RELAY_URL);
+    assert_eq!(
+        registration
+            .expires_at
+            .map(|expires_at| expires_at.timestamp_millis()),
+        Some(expires_at.timestamp_millis())
+    );
+
+    assert!(matches!(
+        store
+            .update_registration(
+                MISSING_CLIENT_ID,
+                vec!["1234"],
+                vec![],
+                TEST_RELAY_URL,
+                None
+            )
+            .await,
+        Err(StoreError::NotFound(_, _))
+    ));
+
+    // A deleted registration isn't recreated by a late update
+    assert_eq!(store.delete_registration(CLIENT_ID).await.unwrap(), 1);
+    assert!(matches!(
+        store
+            .update_registration(CLIENT_ID, vec![], vec!["5678"], TEST_RELAY_URL, None)
+            .await,
+        Err(StoreError::NotFound(_, _))
+    ));
+    assert!(matches!(
+        store.get_registration(CLIENT_ID).await,
+        Err(StoreError::NotFound(_, _))
+    ));
+}
+
+pub async fn test_concurrent_registration_tags(store: &impl RegistrationStore) {
+    const CLIENT_ID: &str = "12345-concurrent-tags";
+
+    let update_tags = |add_tags, remove_tags| {
+        store.update_registration(CLIENT_ID, add_tags, remove_tags, TEST_RELAY_URL, None)
+    };
+
+    store
+        .upsert_registration(CLIENT_ID, Some(vec!["0"]), TEST_RELAY_URL, None)
+        .await
+        .unwrap();
+
+    let tags: Vec<String> = (1..=TEST_UPDATE_COUNT).map(|i| i.to_string()).collect();
+    let results = join_all(
+        tags.iter()
+            .map(|tag| update_tags(vec![tag.as_str()], vec![])),
+    )
+    .await;
+    assert!(results.iter().all(Result::is_ok), "{results:?}");
+
+    let registration = store.get_registration(CLIENT_ID).await.unwrap();
+    let mut registered: Vec<u32> = registration
+        .tags
+        .iter()
+        .map(|tag| tag.parse().unwrap())
+        .collect();
+    registered.sort_unstable();
+    assert_eq!(registered, (0..=TEST_UPDATE_COUNT).collect::<Vec<_>>());
+
+    let results = join_all(
+        tags.iter()
+            .map(|tag| update_tags(vec![], vec![tag.as_str()])),
+    )
+    .await;
+    assert!(results.iter().all(Result::is_ok), "{results:?}");
+
+    let registration = store.get_registration(CLIENT_ID).await.unwrap();
+    let registered: Vec<&str> = registration.tags.iter().map(Arc::as_ref).collect();
+    assert_eq!(registered, ["0"]);
+}
+
+pub async fn test_registration_not_found(store: &impl RegistrationStore) {
     let client_id = format!("{TEST_CLIENT_ID}-not-found");
 
-    let res: Result<Registration, StoreError> =
-        ctx.storage.store.get_registration(client_id.as_str()).await;
+    let res: Result<Registration, StoreError> = store.get_registration(client_id.as_str()).await;
 
     match res {
         Ok(r) => panic!("Expected error, got: {r:?}"),
@@ -0,0 +1,62 @@
+//! The tests of the signature stores, run against each backend by
+//! `store_tests!`.
+
+use {
+    ::function_name::named,
+    chrono::{Duration, Utc},
+    gilgamesh::store::signatures::SignatureStore,
+};
+
+#[named]
+pub async fn test_record_signature(store: &impl SignatureStore) {
+    let now = Utc::now();
+    // Unique across runs since the signatures outlive the tests
+    let signature = format!("{}-{}", function_name!(), now.timestamp_millis());
+    let expired = format!("{signature}-expired");
+
+    assert!(store
+        .record_signature(&signature, now + Duration::hours(1))
+        .await
+        .unwrap());
+    assert!(
+        !store
+            .record_signature(&signature, now + Duration::hours(1))
+            .await
+            .unwrap(),
+        "check replayed signature"
+    );
+
+    assert!(store
+        .record_signature(&expired, now - Duration::seconds(1))
+        .await
+        .unwrap());
+    store.delete_expired_signatures(now).await.unwrap();
+
+    assert!(store
+        .record_signature(&expired, now + Duration::hours(1))
+        .await
+        .unwrap());
+    assert!(!store
+        .record_signature(&signature, now + Duration::hours(1))
+        .await
+        .unwrap());
+}
+
+#[named]
+pub async fn test_delete_signature(store: &impl SignatureStore) {
+    let now = Utc::now();
+    let signature = format!("{}-{}", function_name!(), now.timestamp_millis());
+
+    assert!(store
+        .record_signature(&signature, now + Duration::hours(1))
+        .await
+        .unwrap());
+    store.delete_signature(&signature).await.unwrap();
+
+    // Released for the retries
+    assert!(store
+        .record_signature(&signature, now + Duration::hours(1))
+        .await
+        .unwrap());
+    store.delete_signature(&signature).await.unwrap();
+}
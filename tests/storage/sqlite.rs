@@ -0,0 +1,3 @@
+use crate::context::SqliteStoreContext;
+
+store_tests!(SqliteStoreContext, [], [test_close]);